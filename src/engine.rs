@@ -3,22 +3,93 @@
 use std::collections::{HashMap, VecDeque};
 use std::time;
 
-/// All the possible kind types of objects the engine deals with.
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+use crate::glob;
+use crate::resp::Protocol;
+
+mod blocking;
+mod hash;
+mod list;
+mod listpack;
+mod quicklist;
+mod server;
+mod set;
+mod skiplist;
+mod sorted_set;
+mod stream;
+mod string;
+mod value;
+
+use blocking::{BlockOn, BlockedClients, blocked_type, timeout_reply};
+use hash::HashPart;
+use list::ListEnd;
+use server::Client;
+use set::SetOperation;
+use sorted_set::{RangeKind, ScoreEnd};
+use value::Value;
+
+pub use string::format_double;
+
+/// How many keys with a TTL to sample per iteration of the active expire
+/// cycle.
+const ACTIVE_EXPIRE_SAMPLE_SIZE: usize = 20;
+
+/// Sampling repeats while more than this percent of sampled keys were
+/// expired.
+const ACTIVE_EXPIRE_REPEAT_PERCENT: usize = 25;
+
+/// The most time a single active expire cycle may spend.
+const ACTIVE_EXPIRE_TIME_LIMIT: time::Duration = time::Duration::from_millis(25);
+
+/// How many items `SCAN` and friends return per call when not given a
+/// `COUNT`.
+const SCAN_DEFAULT_COUNT: usize = 10;
+
+/// The most picks a negative count may ask of `HRANDFIELD` and
+/// `SRANDMEMBER`. Repeated picks are all held in the reply, so a larger
+/// count could exhaust memory.
+const RANDOM_PICKS_MAX: i64 = 1_000_000;
+
+/// All the possible kind types of objects the engine deals with. Types only
+/// RESP3 has are converted to RESP2 types for clients still speaking RESP2.
+#[derive(Clone, Debug, PartialEq)]
 pub enum Object {
     /// An array of objects.
     Array(ObjectArray),
 
+    /// A large integer, kept as its decimal digits.
+    BigNumber(Vec<u8>),
+
+    Boolean(bool),
+
     /// A bulk string object. Bulk strings may have `\r` or `\n`.
     BulkString(Option<Vec<u8>>),
 
+    Double(f64),
+
     /// An error with a message.
     Error(Vec<u8>), // TODO: Confirm somehow this doesn't have `\r\n`?
 
     Integer(i64),
 
+    /// Pairs of keys and values.
+    Map(Vec<(Object, Object)>),
+
+    /// A null where an array is expected, such as when a blocking pop times
+    /// out.
+    Null,
+
+    /// Out of band data sent to a client rather than a reply to a command.
+    Push(Vec<Object>),
+
+    /// An unordered collection of unique objects.
+    Set(Vec<Object>),
+
     /// A simple string object. May not have `\r\n`.
     SimpleString(Vec<u8>), // TODO: Confirm somehow this doesn't have `\r\n`?
+
+    /// A string with a three letter format, such as `txt`, for the client to
+    /// show as is.
+    VerbatimString([u8; 3], Vec<u8>),
 }
 
 impl Object {
@@ -46,279 +117,1214 @@ impl Object {
     }
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ObjectArray {
     pub items: Vec<Object>,
 }
 
-impl ObjectArray {
-    fn lrange(&self, start: i64, stop: i64) -> &[Object] {
-        let len = self.items.len();
-
-        // Check for empty array
-        if len == 0 {
-            return &self.items[0..0];
-        }
-
-        // Negative indexes offset from end of the array.
-        let start = if start < 0 {
-            std::cmp::max(0, len as i64 + start)
-        } else {
-            start
-        } as usize;
-        let stop = if stop < 0 {
-            std::cmp::max(0, len as i64 + stop)
-        } else {
-            stop
-        } as usize;
-
-        // Check for start within range.
-        if start >= len || start > stop {
-            return &self.items[0..0];
-        }
-
-        // Check for stop within range.
-        let stop = std::cmp::min(stop, len - 1);
+/// A key in the data table. Keys are binary safe strings.
+type Key = Vec<u8>;
 
-        &self.items[start..=stop]
-    }
-}
+/// Identifies the client a command comes from.
+pub type ClientId = usize;
 
 /// An entry value in the data table.
+#[derive(Clone)]
 struct Entry {
     /// The entry's value.
-    value: Object,
+    value: Value,
 
-    /// When the entry was created.
-    created_at: time::Instant,
+    /// When this entry expires, if ever.
+    expires_at: Option<time::SystemTime>,
+}
+
+impl Entry {
+    /// Returns whether the entry has a TTL.
+    fn has_expiration(&self) -> bool {
+        self.expires_at.is_some()
+    }
 
-    /// For how long this entry is valid.
-    duration: Option<time::Duration>,
+    /// Returns whether the entry's TTL has passed at the given time, or it's
+    /// a hash whose fields have all passed their TTLs.
+    fn is_expired(&self, now: time::SystemTime) -> bool {
+        match (self.expires_at, &self.value) {
+            (Some(expires_at), _) if expires_at <= now => true,
+            (_, Value::Hash(hash)) => hash.is_expired(now),
+            _ => false,
+        }
+    }
 }
 
 struct EntryBuilder {
-    value: Object,
-    duration: Option<time::Duration>,
+    value: Value,
+    expires_at: Option<time::SystemTime>,
 }
 
 impl EntryBuilder {
-    fn new(value: Object) -> Self {
+    fn new(value: Value) -> Self {
         EntryBuilder {
             value,
-            duration: None,
+            expires_at: None,
         }
     }
 
     fn build(self) -> Entry {
         Entry {
             value: self.value,
-            created_at: time::Instant::now(),
-            duration: self.duration,
+            expires_at: self.expires_at,
         }
     }
 
-    fn duration_ms(&mut self, duration_ms: u64) {
-        self.duration = Some(time::Duration::from_millis(duration_ms));
+    fn expires_at(&mut self, expires_at: time::SystemTime) {
+        self.expires_at = Some(expires_at);
+    }
+}
+
+/// The unit a time argument is given in.
+#[derive(Clone, Copy)]
+enum TimeUnit {
+    Seconds,
+    Milliseconds,
+}
+
+impl TimeUnit {
+    /// Converts an amount of this unit to milliseconds, returning `None` on
+    /// overflow.
+    fn to_ms(self, amount: i64) -> Option<i64> {
+        match self {
+            TimeUnit::Seconds => amount.checked_mul(1000),
+            TimeUnit::Milliseconds => Some(amount),
+        }
+    }
+
+    /// Converts milliseconds to this unit, rounding to the nearest unit.
+    fn convert_ms(self, ms: i64) -> i64 {
+        match self {
+            TimeUnit::Seconds => (ms + 500) / 1000,
+            TimeUnit::Milliseconds => ms,
+        }
+    }
+}
+
+/// When `SET` should write its value.
+#[derive(Clone, Copy, PartialEq)]
+enum SetCondition {
+    /// Only when the key doesn't exist.
+    Nx,
+    /// Only when the key already exists.
+    Xx,
+}
+
+/// The `NX`, `XX`, `GT` and `LT` options of the expire commands, which
+/// decide whether a TTL may change.
+#[derive(Default)]
+struct ExpireFlags {
+    nx: bool,
+    xx: bool,
+    gt: bool,
+    lt: bool,
+}
+
+impl ExpireFlags {
+    /// Sets the flag named by an uppercase option. Returns whether the
+    /// option was recognized.
+    fn set(&mut self, option: &[u8]) -> bool {
+        match option {
+            b"NX" => self.nx = true,
+            b"XX" => self.xx = true,
+            b"GT" => self.gt = true,
+            b"LT" => self.lt = true,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Returns whether a TTL may change from the current one to a new one,
+    /// both given as Unix time in milliseconds. No TTL is treated as an
+    /// infinite TTL when comparing with GT or LT.
+    fn allows(&self, current_ms: Option<i64>, expires_at_ms: i64) -> bool {
+        match current_ms {
+            Some(_) if self.nx => false,
+            Some(current_ms) if self.gt => expires_at_ms > current_ms,
+            Some(current_ms) if self.lt => expires_at_ms < current_ms,
+            Some(_) => true,
+            None => !self.xx && !self.gt,
+        }
+    }
+}
+
+/// What `SET` should do with the key's TTL.
+enum SetExpiration {
+    /// Expire at the given time.
+    At(time::SystemTime),
+    /// Keep the TTL the key already has.
+    Keep,
+}
+
+/// The set of keys that have a TTL. Keys are kept in a vector so that they
+/// can be randomly sampled by the active expire cycle, along with a map of
+/// each key's position in the vector so they can be removed in O(1).
+struct ExpireSet {
+    keys: Vec<Key>,
+    positions: HashMap<Key, usize>,
+}
+
+impl ExpireSet {
+    fn new() -> Self {
+        ExpireSet {
+            keys: Vec::new(),
+            positions: HashMap::new(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn insert(&mut self, key: &[u8]) {
+        if !self.positions.contains_key(key) {
+            self.positions.insert(key.to_vec(), self.keys.len());
+            self.keys.push(key.to_vec());
+        }
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        let Some(position) = self.positions.remove(key) else {
+            return;
+        };
+        self.keys.swap_remove(position);
+        if let Some(moved) = self.keys.get(position) {
+            self.positions.insert(moved.clone(), position);
+        }
+    }
+
+    /// Picks a random key from the set.
+    fn sample(&self, rng: &mut Rng) -> Option<&Key> {
+        if self.keys.is_empty() {
+            None
+        } else {
+            let i = rng.next_below(self.keys.len());
+            Some(&self.keys[i])
+        }
+    }
+}
+
+/// Gives every key a slot that stays fixed for as long as the key exists.
+/// `SCAN` walks the slots in order with the cursor being the next slot to
+/// visit, so every key present for a whole scan is returned exactly once.
+/// Freed slots are reused by new keys, which a scan may or may not return.
+struct KeySlots {
+    slots: Vec<Option<Key>>,
+    free: Vec<usize>,
+    positions: HashMap<Key, usize>,
+}
+
+impl KeySlots {
+    fn new() -> Self {
+        KeySlots {
+            slots: Vec::new(),
+            free: Vec::new(),
+            positions: HashMap::new(),
+        }
+    }
+
+    fn insert(&mut self, key: &[u8]) {
+        if self.positions.contains_key(key) {
+            return;
+        }
+
+        // The free list may hold stale positions after trailing slots were
+        // truncated, so only use positions that are really free.
+        let mut position = None;
+        while let Some(free) = self.free.pop() {
+            if self.slots.get(free).is_some_and(|slot| slot.is_none()) {
+                position = Some(free);
+                break;
+            }
+        }
+
+        let position = match position {
+            Some(position) => {
+                self.slots[position] = Some(key.to_vec());
+                position
+            }
+            None => {
+                self.slots.push(Some(key.to_vec()));
+                self.slots.len() - 1
+            }
+        };
+        self.positions.insert(key.to_vec(), position);
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        let Some(position) = self.positions.remove(key) else {
+            return;
+        };
+        self.slots[position] = None;
+        self.free.push(position);
+
+        // Give back trailing empty slots so the table shrinks with the
+        // keyspace.
+        while self.slots.last().is_some_and(|slot| slot.is_none()) {
+            self.slots.pop();
+        }
+        if self.slots.is_empty() {
+            self.free.clear();
+        }
+    }
+
+    /// Visits slots from the cursor until `count` keys are found. Returns
+    /// the cursor to continue from, zero when the scan is complete, and the
+    /// keys found.
+    fn scan(&self, cursor: usize, count: usize) -> (usize, Vec<Key>) {
+        let mut keys = Vec::new();
+        let mut position = cursor;
+        while position < self.slots.len() && keys.len() < count {
+            if let Some(key) = &self.slots[position] {
+                keys.push(key.clone());
+            }
+            position += 1;
+        }
+
+        if position >= self.slots.len() {
+            position = 0;
+        }
+
+        (position, keys)
+    }
+}
+
+/// Options shared by `SCAN` and friends.
+struct ScanOptions {
+    /// Only return items matching this glob pattern.
+    pattern: Option<Vec<u8>>,
+
+    /// About how many items to return.
+    count: usize,
+
+    /// Only return keys with values of this type.
+    type_name: Option<Vec<u8>>,
+}
+
+impl ScanOptions {
+    /// Parses the options following a cursor. `TYPE` is only accepted when
+    /// scanning keys.
+    fn parse(elements: &mut VecDeque<Vec<u8>>, allow_type: bool) -> Result<Self, Object> {
+        let mut options = ScanOptions {
+            pattern: None,
+            count: SCAN_DEFAULT_COUNT,
+            type_name: None,
+        };
+
+        while let Some(mut option) = elements.pop_front() {
+            convert_to_ascii_uppercase(&mut option);
+            let Some(value) = elements.pop_front() else {
+                return Err(new_syntax_error());
+            };
+            match option.as_slice() {
+                b"MATCH" => options.pattern = Some(value),
+                b"COUNT" => match parse_i64(&value) {
+                    Some(count) if count >= 1 => options.count = count as usize,
+                    Some(_) => return Err(new_syntax_error()),
+                    None => return Err(new_not_integer_error()),
+                },
+                b"TYPE" if allow_type => {
+                    let mut value = value;
+                    value.make_ascii_lowercase();
+                    options.type_name = Some(value);
+                }
+                _ => return Err(new_syntax_error()),
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// Returns whether an item matches the pattern, if there is one.
+    fn is_match(&self, item: &[u8]) -> bool {
+        match &self.pattern {
+            Some(pattern) => glob::is_match(pattern, item),
+            None => true,
+        }
+    }
+}
+
+/// A small xorshift pseudo-random number generator. Good enough for
+/// sampling keys, not for anything needing real randomness.
+#[derive(Clone, Debug)]
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Create a generator seeded from the system clock.
+    fn new() -> Self {
+        let seed = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Rng { state: seed | 1 }
+    }
+
+    /// Create a generator with a fixed seed, so tests are repeatable.
+    #[cfg(test)]
+    fn with_seed(seed: u64) -> Self {
+        Rng { state: seed | 1 }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+
+    /// Returns a number in `0..n`. `n` must be non-zero.
+    fn next_below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Picks indexes into a collection of `len` items for commands such as
+    /// `HRANDFIELD`. A positive count picks up to that many distinct
+    /// indexes, and a negative count picks exactly that many, possibly
+    /// repeating. Negative counts must be bounded by `parse_random_count`,
+    /// as callers collect every pick.
+    fn sample(&mut self, len: usize, count: i64) -> impl Iterator<Item = usize> + '_ {
+        let mut indexes = Vec::new();
+        let mut repeating = 0;
+        if len > 0 && count < 0 {
+            repeating = count.unsigned_abs();
+        } else if len > 0 {
+            let count = std::cmp::min(count as usize, len);
+            indexes = (0..len).collect();
+            // A partial Fisher-Yates shuffle.
+            for i in 0..count {
+                let j = i + self.next_below(len - i);
+                indexes.swap(i, j);
+            }
+            indexes.truncate(count);
+        }
+
+        let repeated = (0..repeating).map(move |_| self.next_below(len));
+        indexes.into_iter().chain(repeated)
     }
 }
 
 /// Holds the current state of the engine.
 pub struct Engine {
     /// The key/value data store.
-    data: HashMap<Object, Entry>,
+    data: HashMap<Key, Entry>,
+
+    /// Keys in `data` that have a TTL.
+    expires: ExpireSet,
+
+    /// Keys in `data` of hashes that have fields with a TTL.
+    field_expires: ExpireSet,
+
+    /// Slots of the keys in `data` for scanning.
+    slots: KeySlots,
+
+    /// Random numbers for sampling.
+    rng: Rng,
+
+    /// Clients waiting on keys.
+    blocked: BlockedClients,
+
+    /// Replies for clients that were blocked, ready to be sent.
+    replies: Vec<(ClientId, Object)>,
+
+    /// Clients that have changed their connection's settings.
+    clients: HashMap<ClientId, Client>,
+
+    /// The protocol of the client whose command is running, for replies
+    /// that are shaped differently in each protocol.
+    reply_protocol: Protocol,
 }
 
 impl Engine {
     pub fn new() -> Self {
         let data = HashMap::new();
-        Self { data }
+        let expires = ExpireSet::new();
+        let field_expires = ExpireSet::new();
+        let slots = KeySlots::new();
+        let rng = Rng::new();
+        let blocked = BlockedClients::new();
+        let replies = Vec::new();
+        let clients = HashMap::new();
+        let reply_protocol = Protocol::Resp2;
+        Self {
+            data,
+            expires,
+            field_expires,
+            slots,
+            rng,
+            blocked,
+            replies,
+            clients,
+            reply_protocol,
+        }
     }
 
-    /// Do the command described in the given object.
-    pub fn do_command(&mut self, object: Object) -> Object {
-        let Object::Array(elements) = object else {
-            return Object::new_error(b"expected an non-empty array");
+    /// Takes the replies for clients that were blocked and have since been
+    /// served or timed out.
+    pub fn take_replies(&mut self) -> Vec<(ClientId, Object)> {
+        std::mem::take(&mut self.replies)
+    }
+
+    /// Returns when the next blocked client times out, if any might.
+    pub fn next_block_deadline(&self) -> Option<time::Instant> {
+        self.blocked.next_deadline()
+    }
+
+    /// Gives up on blocked clients whose timeouts have passed, replying
+    /// with null.
+    pub fn time_out_blocked_clients(&mut self) {
+        for client in self.blocked.timed_out(time::Instant::now()) {
+            let Some((command, _)) = self.blocked.command(client) else {
+                continue;
+            };
+            self.blocked.unblock(client);
+            self.replies.push((client, timeout_reply(&command)));
+        }
+    }
+
+    /// Returns the protocol a client's replies are sent in.
+    pub fn protocol(&self, client: ClientId) -> Protocol {
+        match self.clients.get(&client) {
+            Some(state) => state.protocol,
+            None => Protocol::Resp2,
+        }
+    }
+
+    /// Forgets a client that disconnected.
+    pub fn remove_client(&mut self, client: ClientId) {
+        self.blocked.unblock(client);
+        self.clients.remove(&client);
+    }
+
+    /// Serves blocked clients waiting on keys that became ready, running
+    /// their commands again in the order they blocked. Serving a client may
+    /// make more keys ready, which are then served in turn.
+    fn serve_blocked_clients(&mut self) {
+        while let Some(key) = self.blocked.pop_ready_key() {
+            for client in self.blocked.waiters(&key) {
+                let Some((command, elements)) = self.blocked.command(client) else {
+                    continue;
+                };
+                if let Some(entry) = self.data.get(&key)
+                    && entry.value.type_name() != blocked_type(&command)
+                {
+                    continue;
+                }
+                self.reply_protocol = self.protocol(client);
+                match self.execute(&command, elements) {
+                    Ok(reply) => {
+                        self.blocked.unblock(client);
+                        self.replies.push((client, reply));
+                    }
+                    Err(block_on) => self.blocked.block(client, command, block_on),
+                }
+                // Nobody else can be served once the key is gone.
+                if !self.data.contains_key(&key) {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Removes expired keys by repeatedly sampling keys with a TTL, in the
+    /// same manner as Redis. Sampling continues while a large share of the
+    /// sampled keys turn out to be expired, up to a time limit.
+    pub fn active_expire_cycle(&mut self) {
+        let started = time::Instant::now();
+        loop {
+            // Keys with a TTL and hashes with field TTLs are sampled alike.
+            let key_samples = std::cmp::min(ACTIVE_EXPIRE_SAMPLE_SIZE, self.expires.len());
+            let field_samples = std::cmp::min(ACTIVE_EXPIRE_SAMPLE_SIZE, self.field_expires.len());
+            let samples = key_samples + field_samples;
+            if samples == 0 {
+                return;
+            }
+
+            let mut expired = 0;
+            for i in 0..samples {
+                let expires = if i < key_samples {
+                    &self.expires
+                } else {
+                    &self.field_expires
+                };
+                let Some(key) = expires.sample(&mut self.rng).cloned() else {
+                    break;
+                };
+                if self.expire_if_needed(&key) {
+                    expired += 1;
+                }
+            }
+
+            if expired * 100 <= samples * ACTIVE_EXPIRE_REPEAT_PERCENT {
+                return;
+            }
+            if started.elapsed() > ACTIVE_EXPIRE_TIME_LIMIT {
+                return;
+            }
+        }
+    }
+
+    /// Deletes the key if its entry has expired, or removes the expired
+    /// fields of a hash, deleting the key if no fields are left. Returns
+    /// whether anything expired.
+    fn expire_if_needed(&mut self, key: &[u8]) -> bool {
+        let now = time::SystemTime::now();
+        let Some(entry) = self.data.get_mut(key) else {
+            return false;
         };
 
-        let mut elements = VecDeque::from(elements.items);
+        if entry.is_expired(now) {
+            self.remove_entry(key);
+            return true;
+        }
+
+        let Value::Hash(hash) = &mut entry.value else {
+            return false;
+        };
+        if !hash.has_expirations() {
+            self.field_expires.remove(key);
+            return false;
+        }
+
+        let removed = hash.remove_expired(now);
+        if hash.is_empty() {
+            self.remove_entry(key);
+        } else if !hash.has_expirations() {
+            self.field_expires.remove(key);
+        }
+        removed > 0
+    }
+
+    /// Gets the entry at a key, lazily deleting it if it has expired.
+    fn get_entry(&mut self, key: &[u8]) -> Option<&Entry> {
+        self.expire_if_needed(key);
+        self.data.get(key)
+    }
+
+    /// Gets the mutable entry at a key, lazily deleting it if it has expired.
+    fn get_entry_mut(&mut self, key: &[u8]) -> Option<&mut Entry> {
+        self.expire_if_needed(key);
+        self.data.get_mut(key)
+    }
+
+    /// Gets the mutable entry at a key, inserting a new entry with the given
+    /// value if the key doesn't exist or has expired.
+    fn get_entry_or_insert(&mut self, key: Key, value: Value) -> &mut Entry {
+        self.expire_if_needed(&key);
+        if !self.data.contains_key(&key) {
+            self.slots.insert(&key);
+        }
+        self.data
+            .entry(key)
+            .or_insert_with(|| EntryBuilder::new(value).build())
+    }
+
+    /// Sets the entry at a key, replacing any existing entry.
+    fn insert_entry(&mut self, key: Key, entry: Entry) {
+        if entry.has_expiration() {
+            self.expires.insert(&key);
+        } else {
+            self.expires.remove(&key);
+        }
+        self.slots.insert(&key);
+        match &entry.value {
+            Value::Hash(hash) if hash.has_expirations() => self.field_expires.insert(&key),
+            _ => self.field_expires.remove(&key),
+        }
+        if !matches!(entry.value, Value::String(_)) {
+            self.blocked.signal_key_ready(&key);
+        }
+        self.data.insert(key, entry);
+    }
+
+    /// Sets or clears the TTL of an existing entry.
+    fn set_expiration(&mut self, key: &[u8], expires_at: Option<time::SystemTime>) {
+        let Some(entry) = self.data.get_mut(key) else {
+            return;
+        };
+        entry.expires_at = expires_at;
+        if expires_at.is_some() {
+            self.expires.insert(key);
+        } else {
+            self.expires.remove(key);
+        }
+    }
+
+    /// Removes the entry at a key.
+    fn remove_entry(&mut self, key: &[u8]) -> Option<Entry> {
+        self.expires.remove(key);
+        self.field_expires.remove(key);
+        self.slots.remove(key);
+        self.data.remove(key)
+    }
 
-        let Some(command) = elements.pop_front() else {
-            return Object::new_error(b"expected an non-empty array");
+    /// Do the command described in the given object for a client. Returns
+    /// `None` when the client is blocked, in which case its reply comes
+    /// later from `take_replies`.
+    pub fn do_command(&mut self, client: ClientId, object: Object) -> Option<Object> {
+        let Object::Array(elements) = object else {
+            return Some(Object::new_error(b"expected an non-empty array"));
         };
 
-        let Object::BulkString(Some(mut command)) = command else {
-            return Object::new_error(b"expected first element to be a non-null bulk string");
+        // Commands are made up of strings, so only the strings are kept.
+        let mut strings = VecDeque::new();
+        for element in elements.items {
+            let Object::BulkString(Some(string)) = element else {
+                return Some(Object::new_error(
+                    b"expected elements to be non-null bulk strings",
+                ));
+            };
+            strings.push_back(string);
+        }
+        let mut elements = strings;
+
+        let Some(mut command) = elements.pop_front() else {
+            return Some(Object::new_error(b"expected an non-empty array"));
         };
 
         convert_to_ascii_uppercase(&mut command);
 
-        match command.as_slice() {
+        self.reply_protocol = self.protocol(client);
+        let result = match command.as_slice() {
+            // These act on the client itself, so they're run apart from the
+            // commands that may block.
+            b"HELLO" => Ok(self.do_hello(client, elements)),
+            b"CLIENT" => Ok(self.do_client(client, elements)),
+            _ => self.execute(&command, elements),
+        };
+
+        let reply = match result {
+            Ok(reply) => Some(reply),
+            Err(block_on) => {
+                self.blocked.block(client, command, block_on);
+                None
+            }
+        };
+
+        self.serve_blocked_clients();
+
+        reply
+    }
+
+    /// Runs a command given its uppercase name. Returns the reply, or what
+    /// to block on when a blocking command can't be served yet.
+    fn execute(&mut self, command: &[u8], elements: VecDeque<Vec<u8>>) -> Result<Object, BlockOn> {
+        let reply = match command {
             b"GET" => self.do_get(elements),
+            b"MGET" => self.do_mget(elements),
+            b"MSET" => self.do_mset(elements, "mset", false),
+            b"MSETNX" => self.do_mset(elements, "msetnx", true),
+            b"GETSET" => self.do_getset(elements),
+            b"GETDEL" => self.do_getdel(elements),
+            b"GETEX" => self.do_getex(elements),
+            b"SETNX" => self.do_setnx(elements),
+            b"SETEX" => self.do_setex(elements, "setex", b"EX"),
+            b"PSETEX" => self.do_setex(elements, "psetex", b"PX"),
+            b"APPEND" => self.do_append(elements),
+            b"STRLEN" => self.do_strlen(elements),
+            b"GETRANGE" => self.do_getrange(elements),
+            b"SETRANGE" => self.do_setrange(elements),
+            b"INCR" => self.do_incr(elements, "incr", 1, false),
+            b"DECR" => self.do_incr(elements, "decr", -1, false),
+            b"INCRBY" => self.do_incr(elements, "incrby", 1, true),
+            b"DECRBY" => self.do_incr(elements, "decrby", -1, true),
+            b"INCRBYFLOAT" => self.do_incrbyfloat(elements),
             b"PING" => Object::new_simple_string(b"PONG"),
+            b"DEBUG" => self.do_debug(elements),
             b"ECHO" => self.do_echo(elements),
-            b"RPUSH" => self.do_rpush(elements),
-            b"LPUSH" => self.do_lpush(elements),
+            b"RPUSH" => self.do_push(elements, "rpush", ListEnd::Right, false),
+            b"LPUSH" => self.do_push(elements, "lpush", ListEnd::Left, false),
+            b"RPUSHX" => self.do_push(elements, "rpushx", ListEnd::Right, true),
+            b"LPUSHX" => self.do_push(elements, "lpushx", ListEnd::Left, true),
             b"SET" => self.do_set(elements),
             b"LRANGE" => self.do_lrange(elements),
             b"LLEN" => self.do_llen(elements),
-            b"LPOP" => self.do_lpop(elements),
+            b"LPOP" => self.do_pop(elements, "lpop", ListEnd::Left),
+            b"RPOP" => self.do_pop(elements, "rpop", ListEnd::Right),
+            b"LINDEX" => self.do_lindex(elements),
+            b"LSET" => self.do_lset(elements),
+            b"LINSERT" => self.do_linsert(elements),
+            b"LREM" => self.do_lrem(elements),
+            b"LTRIM" => self.do_ltrim(elements),
+            b"LPOS" => self.do_lpos(elements),
+            b"BLPOP" => return self.do_blocking_pop(elements, "blpop", ListEnd::Left),
+            b"BRPOP" => return self.do_blocking_pop(elements, "brpop", ListEnd::Right),
+            b"BLMOVE" => return self.do_blmove(elements),
+            b"BLMPOP" => return self.do_blmpop(elements),
+            b"BRPOPLPUSH" => return self.do_brpoplpush(elements),
+            b"LMOVE" => self.do_lmove(elements),
+            b"RPOPLPUSH" => self.do_rpoplpush(elements),
+            b"LMPOP" => self.do_lmpop(elements),
+            b"HSET" => self.do_hset(elements, "hset", false),
+            b"HMSET" => self.do_hset(elements, "hmset", true),
+            b"HSETNX" => self.do_hsetnx(elements),
+            b"HGET" => self.do_hget(elements),
+            b"HMGET" => self.do_hmget(elements),
+            b"HDEL" => self.do_hdel(elements),
+            b"HGETALL" => self.do_hgetall(elements, "hgetall", HashPart::Both),
+            b"HKEYS" => self.do_hgetall(elements, "hkeys", HashPart::Fields),
+            b"HVALS" => self.do_hgetall(elements, "hvals", HashPart::Values),
+            b"HLEN" => self.do_hlen(elements),
+            b"HEXISTS" => self.do_hexists(elements),
+            b"HSTRLEN" => self.do_hstrlen(elements),
+            b"HINCRBY" => self.do_hincrby(elements),
+            b"HINCRBYFLOAT" => self.do_hincrbyfloat(elements),
+            b"HRANDFIELD" => self.do_hrandfield(elements),
+            b"HEXPIRE" => self.do_hexpire(elements, "hexpire", TimeUnit::Seconds, false),
+            b"HPEXPIRE" => self.do_hexpire(elements, "hpexpire", TimeUnit::Milliseconds, false),
+            b"HEXPIREAT" => self.do_hexpire(elements, "hexpireat", TimeUnit::Seconds, true),
+            b"HPEXPIREAT" => self.do_hexpire(elements, "hpexpireat", TimeUnit::Milliseconds, true),
+            b"HTTL" => self.do_httl(elements, "httl", TimeUnit::Seconds, false),
+            b"HPTTL" => self.do_httl(elements, "hpttl", TimeUnit::Milliseconds, false),
+            b"HEXPIRETIME" => self.do_httl(elements, "hexpiretime", TimeUnit::Seconds, true),
+            b"HPEXPIRETIME" => self.do_httl(elements, "hpexpiretime", TimeUnit::Milliseconds, true),
+            b"HPERSIST" => self.do_hpersist(elements),
+            b"SADD" => self.do_sadd(elements),
+            b"SREM" => self.do_srem(elements),
+            b"SMEMBERS" => self.do_smembers(elements),
+            b"SISMEMBER" => self.do_sismember(elements),
+            b"SMISMEMBER" => self.do_smismember(elements),
+            b"SCARD" => self.do_scard(elements),
+            b"SPOP" => self.do_spop(elements),
+            b"SRANDMEMBER" => self.do_srandmember(elements),
+            b"SMOVE" => self.do_smove(elements),
+            b"SINTER" => self.do_set_operation(elements, "sinter", SetOperation::Inter),
+            b"SUNION" => self.do_set_operation(elements, "sunion", SetOperation::Union),
+            b"SDIFF" => self.do_set_operation(elements, "sdiff", SetOperation::Diff),
+            b"SINTERSTORE" => {
+                self.do_set_operation_store(elements, "sinterstore", SetOperation::Inter)
+            }
+            b"SUNIONSTORE" => {
+                self.do_set_operation_store(elements, "sunionstore", SetOperation::Union)
+            }
+            b"SDIFFSTORE" => {
+                self.do_set_operation_store(elements, "sdiffstore", SetOperation::Diff)
+            }
+            b"SINTERCARD" => self.do_sintercard(elements),
+            b"ZADD" => self.do_zadd(elements),
+            b"ZINCRBY" => self.do_zincrby(elements),
+            b"ZREM" => self.do_zrem(elements),
+            b"ZSCORE" => self.do_zscore(elements),
+            b"ZMSCORE" => self.do_zmscore(elements),
+            b"ZCARD" => self.do_zcard(elements),
+            b"ZRANK" => self.do_zrank(elements, "zrank", false),
+            b"ZREVRANK" => self.do_zrank(elements, "zrevrank", true),
+            b"ZRANGE" => self.do_zrange(elements, "zrange", RangeKind::Rank, false),
+            b"ZREVRANGE" => self.do_zrange(elements, "zrevrange", RangeKind::Rank, true),
+            b"ZRANGEBYSCORE" => self.do_zrange(elements, "zrangebyscore", RangeKind::Score, false),
+            b"ZREVRANGEBYSCORE" => {
+                self.do_zrange(elements, "zrevrangebyscore", RangeKind::Score, true)
+            }
+            b"ZRANGEBYLEX" => self.do_zrange(elements, "zrangebylex", RangeKind::Lex, false),
+            b"ZREVRANGEBYLEX" => self.do_zrange(elements, "zrevrangebylex", RangeKind::Lex, true),
+            b"ZRANGESTORE" => self.do_zrangestore(elements),
+            b"ZCOUNT" => self.do_zcount(elements, "zcount", RangeKind::Score),
+            b"ZLEXCOUNT" => self.do_zcount(elements, "zlexcount", RangeKind::Lex),
+            b"ZREMRANGEBYRANK" => self.do_zremrange(elements, "zremrangebyrank", RangeKind::Rank),
+            b"ZREMRANGEBYSCORE" => {
+                self.do_zremrange(elements, "zremrangebyscore", RangeKind::Score)
+            }
+            b"ZREMRANGEBYLEX" => self.do_zremrange(elements, "zremrangebylex", RangeKind::Lex),
+            b"ZUNION" => self.do_zset_operation(elements, "zunion", SetOperation::Union),
+            b"ZINTER" => self.do_zset_operation(elements, "zinter", SetOperation::Inter),
+            b"ZDIFF" => self.do_zset_operation(elements, "zdiff", SetOperation::Diff),
+            b"ZUNIONSTORE" => {
+                self.do_zset_operation_store(elements, "zunionstore", SetOperation::Union)
+            }
+            b"ZINTERSTORE" => {
+                self.do_zset_operation_store(elements, "zinterstore", SetOperation::Inter)
+            }
+            b"ZDIFFSTORE" => {
+                self.do_zset_operation_store(elements, "zdiffstore", SetOperation::Diff)
+            }
+            b"ZPOPMIN" => self.do_zpop(elements, "zpopmin", ScoreEnd::Min),
+            b"ZPOPMAX" => self.do_zpop(elements, "zpopmax", ScoreEnd::Max),
+            b"BZPOPMIN" => return self.do_bzpop(elements, "bzpopmin", ScoreEnd::Min),
+            b"BZPOPMAX" => return self.do_bzpop(elements, "bzpopmax", ScoreEnd::Max),
+            b"ZMPOP" => self.do_zmpop(elements),
+            b"BZMPOP" => return self.do_bzmpop(elements),
+            b"XADD" => self.do_xadd(elements),
+            b"XRANGE" => self.do_xrange(elements, "xrange", false),
+            b"XREVRANGE" => self.do_xrange(elements, "xrevrange", true),
+            b"XREAD" => return self.do_xread(elements, "xread", false),
+            b"XREADGROUP" => return self.do_xread(elements, "xreadgroup", true),
+            b"XLEN" => self.do_xlen(elements),
+            b"XDEL" => self.do_xdel(elements),
+            b"XTRIM" => self.do_xtrim(elements),
+            b"XGROUP" => self.do_xgroup(elements),
+            b"XACK" => self.do_xack(elements),
+            b"XPENDING" => self.do_xpending(elements),
+            b"XCLAIM" => self.do_xclaim(elements),
+            b"XAUTOCLAIM" => self.do_xautoclaim(elements),
+            b"XINFO" => self.do_xinfo(elements),
+            b"EXPIRE" => self.do_expire(elements, "expire", TimeUnit::Seconds, false),
+            b"PEXPIRE" => self.do_expire(elements, "pexpire", TimeUnit::Milliseconds, false),
+            b"EXPIREAT" => self.do_expire(elements, "expireat", TimeUnit::Seconds, true),
+            b"PEXPIREAT" => self.do_expire(elements, "pexpireat", TimeUnit::Milliseconds, true),
+            b"TTL" => self.do_ttl(elements, "ttl", TimeUnit::Seconds, false),
+            b"PTTL" => self.do_ttl(elements, "pttl", TimeUnit::Milliseconds, false),
+            b"EXPIRETIME" => self.do_ttl(elements, "expiretime", TimeUnit::Seconds, true),
+            b"PEXPIRETIME" => self.do_ttl(elements, "pexpiretime", TimeUnit::Milliseconds, true),
+            b"PERSIST" => self.do_persist(elements),
+            b"DEL" => self.do_del(elements, "del"),
+            b"UNLINK" => self.do_del(elements, "unlink"),
+            b"EXISTS" => self.do_exists(elements),
+            b"TYPE" => self.do_type(elements),
+            b"RENAME" => self.do_rename(elements, "rename", false),
+            b"RENAMENX" => self.do_rename(elements, "renamenx", true),
+            b"COPY" => self.do_copy(elements),
+            b"KEYS" => self.do_keys(elements),
+            b"SCAN" => self.do_scan(elements),
+            b"LSCAN" => self.do_lscan(elements),
             _ => Object::new_error(b"unknown command"),
-        }
+        };
+
+        Ok(reply)
     }
 
-    fn do_lpop(&mut self, mut elements: VecDeque<Object>) -> Object {
-        let Some(key) = elements.pop_front() else {
-            return Object::new_error(b"LLEN requires a key argument");
+    /// Do an expire command, setting the TTL of a key. The time is either
+    /// relative to now or an absolute Unix time, in the given unit.
+    fn do_expire(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        unit: TimeUnit,
+        is_absolute: bool,
+    ) -> Object {
+        let (Some(key), Some(amount)) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error(name);
         };
 
-        let count = match elements.pop_front() {
-            Some(Object::BulkString(Some(count))) => parse_usize(&count),
-            None => Some(1),
-            _ => None,
+        let Some(amount) = parse_i64(&amount) else {
+            return new_not_integer_error();
         };
 
-        let Some(mut count) = count else {
-            return Object::new_error(b"invalid count");
+        let mut flags = ExpireFlags::default();
+        while let Some(mut option) = elements.pop_front() {
+            convert_to_ascii_uppercase(&mut option);
+            if !flags.set(&option) {
+                let message = format!(
+                    "ERR Unsupported option {}",
+                    String::from_utf8_lossy(&option)
+                );
+                return Object::new_error(message.as_bytes());
+            }
+        }
+        let ExpireFlags { nx, xx, gt, lt } = flags;
+        if nx && (xx || gt || lt) {
+            return Object::new_error(
+                b"ERR NX and XX, GT or LT options at the same time are not compatible",
+            );
+        }
+        if gt && lt {
+            return Object::new_error(b"ERR GT and LT options at the same time are not compatible");
+        }
+
+        let expires_at_ms = to_expire_time_ms(amount, unit, is_absolute);
+        let Some(expires_at_ms) = expires_at_ms else {
+            let message = format!("ERR invalid expire time in '{name}' command");
+            return Object::new_error(message.as_bytes());
         };
 
-        let Some(entry) = self.data.get_mut(&key) else {
-            return Object::BulkString(None);
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::Integer(0);
         };
 
-        // TODO: Check for expiration?
+        let current_ms = entry.expires_at.map(to_unix_ms);
+        if !flags.allows(current_ms, expires_at_ms) {
+            return Object::Integer(0);
+        }
+
+        if expires_at_ms <= to_unix_ms(time::SystemTime::now()) {
+            self.remove_entry(&key);
+        } else {
+            self.set_expiration(&key, Some(from_unix_ms(expires_at_ms)));
+        }
 
-        let Object::Array(array) = &mut entry.value else {
-            return Object::BulkString(None);
+        Object::Integer(1)
+    }
+
+    /// Do a TTL command, returning the remaining time to live of a key or,
+    /// when absolute, the Unix time at which the key expires.
+    fn do_ttl(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        unit: TimeUnit,
+        is_absolute: bool,
+    ) -> Object {
+        let (Some(key), None) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error(name);
         };
 
-        if array.items.is_empty() {
-            return Object::BulkString(None);
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::Integer(-2);
+        };
+
+        let Some(expires_at) = entry.expires_at else {
+            return Object::Integer(-1);
+        };
+
+        Object::Integer(to_ttl(expires_at, unit, is_absolute))
+    }
+
+    /// Do a persist command, removing the TTL from a key.
+    fn do_persist(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), None) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("persist");
+        };
+
+        match self.get_entry(&key) {
+            Some(entry) if entry.has_expiration() => {
+                self.set_expiration(&key, None);
+                Object::Integer(1)
+            }
+            _ => Object::Integer(0),
+        }
+    }
+
+    /// Do a delete command, removing keys and returning how many existed.
+    /// `UNLINK` is the same as `DEL` since values are always freed on the
+    /// engine thread.
+    fn do_del(&mut self, elements: VecDeque<Vec<u8>>, name: &str) -> Object {
+        if elements.is_empty() {
+            return new_arity_error(name);
+        }
+
+        let mut count = 0;
+        for key in elements {
+            self.expire_if_needed(&key);
+            if self.remove_entry(&key).is_some() {
+                count += 1;
+            }
         }
 
-        if count == 1 {
-            return array.items.remove(0);
+        Object::Integer(count)
+    }
+
+    /// Do an exists command, counting how many of the given keys exist. A key
+    /// given more than once is counted more than once.
+    fn do_exists(&mut self, elements: VecDeque<Vec<u8>>) -> Object {
+        if elements.is_empty() {
+            return new_arity_error("exists");
         }
 
-        let mut popped = Vec::new();
-        while count > 0 {
-            let item = array.items.remove(0);
-            popped.push(item);
-            count -= 1;
+        let mut count = 0;
+        for key in elements {
+            if self.get_entry(&key).is_some() {
+                count += 1;
+            }
         }
 
-        Object::new_array(popped)
+        Object::Integer(count)
     }
 
-    fn do_llen(&mut self, mut elements: VecDeque<Object>) -> Object {
-        let Some(key) = elements.pop_front() else {
-            return Object::new_error(b"LLEN requires a key argument");
+    /// Do a type command, returning the kind of value stored at a key.
+    fn do_type(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), None) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("type");
         };
 
-        let Some(entry) = self.data.get(&key) else {
-            return Object::Integer(0);
+        let name = match self.get_entry(&key) {
+            Some(entry) => entry.value.type_name(),
+            None => "none",
         };
 
-        // TODO: Check for expiration?
+        Object::new_simple_string(name.as_bytes())
+    }
 
-        let Object::Array(array) = &entry.value else {
-            return Object::Integer(0);
+    /// Do a rename command, moving a key's value and TTL to a new key. When
+    /// `is_nx` is set the rename only happens if the new key doesn't exist.
+    fn do_rename(&mut self, mut elements: VecDeque<Vec<u8>>, name: &str, is_nx: bool) -> Object {
+        let (Some(key), Some(new_key), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error(name);
         };
 
-        Object::Integer(array.items.len() as i64)
+        if self.get_entry(&key).is_none() {
+            return Object::new_error(b"ERR no such key");
+        }
+
+        if key == new_key {
+            return if is_nx {
+                Object::Integer(0)
+            } else {
+                Object::new_simple_string(b"OK")
+            };
+        }
+
+        if is_nx && self.get_entry(&new_key).is_some() {
+            return Object::Integer(0);
+        }
+
+        if let Some(entry) = self.remove_entry(&key) {
+            self.insert_entry(new_key, entry);
+        }
+
+        if is_nx {
+            Object::Integer(1)
+        } else {
+            Object::new_simple_string(b"OK")
+        }
     }
 
-    fn do_lrange(&mut self, mut elements: VecDeque<Object>) -> Object {
-        let Some(key) = elements.pop_front() else {
-            return Object::new_error(b"LRANGE requires a key argument");
+    /// Do a copy command, copying a key's value and TTL to another key.
+    fn do_copy(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(new_key)) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("copy");
         };
 
-        let Some(Object::BulkString(Some(start))) = elements.pop_front() else {
-            return Object::new_error(b"LRANGE requires a start index");
-        };
+        let mut is_replace = false;
+        while let Some(mut option) = elements.pop_front() {
+            convert_to_ascii_uppercase(&mut option);
+            match option.as_slice() {
+                b"REPLACE" => is_replace = true,
+                b"DB" => {
+                    // There is only the one database.
+                    let Some(db) = elements.pop_front() else {
+                        return new_syntax_error();
+                    };
+                    match parse_i64(&db) {
+                        Some(0) => (),
+                        Some(_) => return Object::new_error(b"ERR DB index is out of range"),
+                        None => return new_not_integer_error(),
+                    }
+                }
+                _ => return new_syntax_error(),
+            }
+        }
 
-        let Some(Object::BulkString(Some(stop))) = elements.pop_front() else {
-            return Object::new_error(b"LRANGE requires a stop index");
-        };
+        if key == new_key {
+            return Object::new_error(b"ERR source and destination objects are the same");
+        }
 
-        let Some(start) = parse_i64(&start) else {
-            return Object::new_error(b"couldn't parse start as an integer");
+        let Some(entry) = self.get_entry(&key).cloned() else {
+            return Object::Integer(0);
         };
 
-        let Some(stop) = parse_i64(&stop) else {
-            return Object::new_error(b"couldn't parse stop as an integer");
-        };
+        if !is_replace && self.get_entry(&new_key).is_some() {
+            return Object::Integer(0);
+        }
 
-        let Some(entry) = self.data.get(&key) else {
-            return Object::new_empty_array();
-        };
+        self.insert_entry(new_key, entry);
 
-        // TODO: Check for expiration?
+        Object::Integer(1)
+    }
 
-        let Object::Array(array) = &entry.value else {
-            return Object::new_empty_array();
+    /// Do a keys command, returning all keys matching a pattern.
+    fn do_keys(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(pattern), None) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("keys");
         };
 
-        let array = array
-            .lrange(start, stop)
+        let now = time::SystemTime::now();
+        let keys = self
+            .data
             .iter()
-            .map(|obj| obj.clone())
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .filter(|(key, _)| glob::is_match(&pattern, key))
+            .map(|(key, _)| key.clone())
             .collect();
 
-        Object::new_array(array)
+        new_bulk_string_array(keys)
     }
 
-    fn do_rpush(&mut self, mut elements: VecDeque<Object>) -> Object {
-        let Some(key) = elements.pop_front() else {
-            return Object::new_error(b"RPUSH requires a key argument");
+    /// Do a scan command, returning the next batch of keys from a cursor.
+    fn do_scan(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(cursor) = elements.pop_front() else {
+            return new_arity_error("scan");
         };
 
-        if elements.is_empty() {
-            return Object::new_error(b"RPUSH requires an element argument");
-        }
-
-        let entry = self
-            .data
-            .entry(key)
-            .or_insert(EntryBuilder::new(Object::new_empty_array()).build());
+        let Some(cursor) = parse_usize(&cursor) else {
+            return Object::new_error(b"ERR invalid cursor");
+        };
 
-        let Object::Array(array) = &mut entry.value else {
-            return Object::new_error(b"object at key is not an array");
+        let options = match ScanOptions::parse(&mut elements, true) {
+            Ok(options) => options,
+            Err(e) => return e,
         };
 
-        while let Some(element) = elements.pop_front() {
-            array.items.push(element);
+        let (cursor, keys) = self.slots.scan(cursor, options.count);
+
+        let mut matched = Vec::new();
+        for key in keys {
+            let Some(entry) = self.get_entry(&key) else {
+                continue;
+            };
+            let is_type = match &options.type_name {
+                Some(type_name) => type_name.as_slice() == entry.value.type_name().as_bytes(),
+                None => true,
+            };
+            if is_type && options.is_match(&key) {
+                matched.push(key);
+            }
         }
 
-        Object::Integer(array.items.len() as i64)
+        new_scan_reply(cursor, matched)
     }
 
-    fn do_lpush(&mut self, mut elements: VecDeque<Object>) -> Object {
-        let Some(key) = elements.pop_front() else {
-            return Object::new_error(b"LPUSH requires a key argument");
+    /// Do an `LSCAN` command, walking the elements of a list with a cursor
+    /// and the same options as `SCAN`. Redis has no such command, but
+    /// `LRANGE` over a large list replies with everything at once, and
+    /// debugging tools need to page through big lists the way `SCAN` pages
+    /// through keys. The cursor is an index from the head of the list, so
+    /// pushing or popping at the head while scanning shifts where the scan
+    /// resumes.
+    fn do_lscan(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        if elements.len() == 1 && elements[0].eq_ignore_ascii_case(b"HELP") {
+            return new_help_reply(&[
+                "LSCAN <key> <cursor> [MATCH <pattern>] [COUNT <count>]",
+                "    Walk the elements of the list at <key> from <cursor>, as SCAN walks",
+                "    keys. This command is not part of Redis. The cursor is an index",
+                "    from the head of the list, so pushes and pops at the head shift",
+                "    where the walk resumes.",
+                "LSCAN HELP",
+                "    Print this help.",
+            ]);
+        }
+
+        let (Some(key), Some(cursor)) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("lscan");
         };
 
-        if elements.is_empty() {
-            return Object::new_error(b"LPUSH requires an element argument");
-        }
+        let Some(cursor) = parse_usize(&cursor) else {
+            return Object::new_error(b"ERR invalid cursor");
+        };
 
-        let entry = self
-            .data
-            .entry(key)
-            .or_insert(EntryBuilder::new(Object::new_empty_array()).build());
+        let options = match ScanOptions::parse(&mut elements, false) {
+            Ok(options) => options,
+            Err(e) => return e,
+        };
 
-        let Object::Array(array) = &mut entry.value else {
-            return Object::new_error(b"object at key is not an array");
+        let Some(entry) = self.get_entry(&key) else {
+            return new_scan_reply(0, Vec::new());
         };
 
-        while let Some(element) = elements.pop_front() {
-            array.items.insert(0, element);
-        }
+        let Value::List(list) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let items = list.iter_at(cursor).take(options.count);
+        let next = cursor.saturating_add(options.count);
+        let cursor = if next >= list.len() { 0 } else { next };
+        let matched = items.filter(|item| options.is_match(item)).collect();
 
-        Object::Integer(array.items.len() as i64)
+        new_scan_reply(cursor, matched)
     }
 
     /// Do an echo command. This returns the arguments as is back to the client.
-    fn do_echo(&mut self, mut elements: VecDeque<Object>) -> Object {
+    fn do_echo(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
         let Some(arg) = elements.pop_front() else {
             return Object::new_error(b"ECHO requires an argument");
         };
@@ -327,59 +1333,91 @@ impl Engine {
             return Object::new_error(b"ECHO requires exactly one argument");
         }
 
-        arg
+        Object::BulkString(Some(arg))
     }
 
-    /// Do an set command.
-    fn do_set(&mut self, mut elements: VecDeque<Object>) -> Object {
-        let Some(key) = elements.pop_front() else {
-            return Object::new_error(b"SET requires a key argument");
-        };
-
-        let Some(value) = elements.pop_front() else {
-            return Object::new_error(b"SET requires a value argument");
-        };
-
-        let mut entry_builder = EntryBuilder::new(value);
-
-        match elements.pop_front() {
-            Some(Object::BulkString(Some(mut option))) => {
-                convert_to_ascii_uppercase(&mut option);
-                match option.as_slice() {
-                    b"PX" => {
-                        match elements.pop_front() {
-                            Some(Object::BulkString(Some(option))) => {
-                                let mut duration_ms = 0;
-                                for b in option {
-                                    if b'0' <= b && b < b'9' {
-                                        let digit = (b - b'0') as u64;
-                                        duration_ms = 10 * duration_ms + digit;
-                                        // TODO: Handle overflow? It's a rather big int.
-                                    } else {
-                                        return Object::new_error(b"Invalid PX duration");
-                                    }
-                                }
-                                entry_builder.duration_ms(duration_ms);
-                            }
-                            _ => return Object::new_error(b"PX requires a duration"),
-                        }
+    /// Do a set command.
+    fn do_set(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(value)) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("set");
+        };
+
+        let mut condition = None;
+        let mut expiration = None;
+        let mut is_get = false;
+        while let Some(mut option) = elements.pop_front() {
+            convert_to_ascii_uppercase(&mut option);
+            match option.as_slice() {
+                b"NX" if condition != Some(SetCondition::Xx) => {
+                    condition = Some(SetCondition::Nx);
+                }
+                b"XX" if condition != Some(SetCondition::Nx) => {
+                    condition = Some(SetCondition::Xx);
+                }
+                b"GET" => is_get = true,
+                b"KEEPTTL" if expiration.is_none() => {
+                    expiration = Some(SetExpiration::Keep);
+                }
+                b"EX" | b"PX" | b"EXAT" | b"PXAT" if expiration.is_none() => {
+                    let Some(amount) = elements.pop_front() else {
+                        return new_syntax_error();
+                    };
+                    match parse_expiration_option(&option, &amount, "set") {
+                        Ok(expires_at) => expiration = Some(SetExpiration::At(expires_at)),
+                        Err(e) => return e,
                     }
-                    // TODO: Error handle other things here.
-                    _ => (),
                 }
+                _ => return new_syntax_error(),
             }
-            // TODO: Error handle other things here.
-            _ => (),
+        }
+
+        let (old_value, old_expires_at) = match self.get_entry(&key) {
+            Some(entry) => (Some(&entry.value), entry.expires_at),
+            None => (None, None),
+        };
+
+        let reply = if is_get {
+            match old_value {
+                Some(Value::String(string)) => Object::BulkString(Some(string.clone())),
+                Some(_) => return new_wrong_type_error(),
+                None => Object::BulkString(None),
+            }
+        } else {
+            Object::new_simple_string(b"OK")
+        };
+
+        let is_allowed = match condition {
+            Some(SetCondition::Nx) => old_value.is_none(),
+            Some(SetCondition::Xx) => old_value.is_some(),
+            None => true,
+        };
+        if !is_allowed {
+            return if is_get {
+                reply
+            } else {
+                Object::BulkString(None)
+            };
+        }
+
+        let mut entry_builder = EntryBuilder::new(Value::String(value));
+        match expiration {
+            Some(SetExpiration::At(expires_at)) => entry_builder.expires_at(expires_at),
+            Some(SetExpiration::Keep) => {
+                if let Some(expires_at) = old_expires_at {
+                    entry_builder.expires_at(expires_at);
+                }
+            }
+            None => (),
         }
 
         let entry = entry_builder.build();
-        let _ = self.data.insert(key, entry);
+        self.insert_entry(key, entry);
 
-        Object::new_simple_string(b"OK")
+        reply
     }
 
     /// Do a get command.
-    fn do_get(&mut self, mut elements: VecDeque<Object>) -> Object {
+    fn do_get(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
         let Some(key) = elements.pop_front() else {
             return Object::new_error(b"GET requires a key argument");
         };
@@ -388,58 +1426,242 @@ impl Engine {
             return Object::new_error(b"GET requires exactly one argument");
         }
 
-        let Some(entry) = self.data.get(&key) else {
+        let Some(entry) = self.get_entry(&key) else {
             return Object::BulkString(None);
         };
 
-        let is_expired = match entry.duration {
-            Some(duration) => entry.created_at + duration < time::Instant::now(),
-            None => false,
+        let Value::String(string) = &entry.value else {
+            return new_wrong_type_error();
         };
 
-        if is_expired {
-            return Object::BulkString(None);
+        Object::BulkString(Some(string.clone()))
+    }
+}
+
+/// Creates the error returned when a command has the wrong number of
+/// arguments.
+fn new_arity_error(name: &str) -> Object {
+    let message = format!("ERR wrong number of arguments for '{name}' command");
+    Object::new_error(message.as_bytes())
+}
+
+/// Creates the error returned when an argument isn't a valid integer.
+fn new_not_integer_error() -> Object {
+    Object::new_error(b"ERR value is not an integer or out of range")
+}
+
+/// Creates the error returned for an unknown subcommand of a command.
+fn new_unknown_subcommand_error(subcommand: &[u8], command: &str) -> Object {
+    let message = format!(
+        "ERR unknown subcommand '{}'. Try {command} HELP.",
+        String::from_utf8_lossy(subcommand),
+    );
+    Object::new_error(message.as_bytes())
+}
+
+/// Parses the count of a random pick command such as `HRANDFIELD`. A
+/// negative count may ask for at most `RANDOM_PICKS_MAX` picks, as every
+/// pick is built into the reply.
+fn parse_random_count(count: &[u8]) -> Result<i64, Object> {
+    match parse_i64(count) {
+        Some(count) if count < -RANDOM_PICKS_MAX => {
+            Err(Object::new_error(b"ERR value is out of range"))
         }
+        Some(count) => Ok(count),
+        None => Err(new_not_integer_error()),
+    }
+}
+
+/// Creates the error returned for malformed command options.
+fn new_syntax_error() -> Object {
+    Object::new_error(b"ERR syntax error")
+}
+
+/// Creates the two element reply of `SCAN` and friends.
+fn new_scan_reply(cursor: usize, items: Vec<Vec<u8>>) -> Object {
+    let cursor = Object::BulkString(Some(cursor.to_string().into_bytes()));
+    Object::new_array(vec![cursor, new_bulk_string_array(items)])
+}
+
+/// Creates an array of bulk strings.
+fn new_bulk_string_array(strings: Vec<Vec<u8>>) -> Object {
+    let items = strings
+        .into_iter()
+        .map(|string| Object::BulkString(Some(string)))
+        .collect();
+    Object::new_array(items)
+}
 
-        entry.value.clone()
+/// Creates a map of field names to values, as replied by commands such as
+/// `XINFO` and `HELLO`.
+fn new_info_reply(fields: Vec<(&str, Object)>) -> Object {
+    let fields = fields
+        .into_iter()
+        .map(|(name, value)| (Object::BulkString(Some(name.as_bytes().to_vec())), value))
+        .collect();
+    Object::Map(fields)
+}
+
+/// Creates a reply of pairs, such as members and their scores. RESP3
+/// clients get an array of two element arrays, while RESP2 clients get the
+/// pairs flattened into one array.
+fn new_pairs_reply(pairs: Vec<(Object, Object)>, protocol: Protocol) -> Object {
+    let items = match protocol {
+        Protocol::Resp2 => pairs.into_iter().flat_map(|(a, b)| [a, b]).collect(),
+        Protocol::Resp3 => pairs
+            .into_iter()
+            .map(|(a, b)| Object::new_array(vec![a, b]))
+            .collect(),
+    };
+    Object::new_array(items)
+}
+
+/// Creates the reply of a `HELP` subcommand, one simple string per line.
+fn new_help_reply(lines: &[&str]) -> Object {
+    let lines = lines
+        .iter()
+        .map(|line| Object::new_simple_string(line.as_bytes()))
+        .collect();
+    Object::new_array(lines)
+}
+
+/// Creates an array of integers.
+fn new_integer_array(integers: impl IntoIterator<Item = i64>) -> Object {
+    Object::new_array(integers.into_iter().map(Object::Integer).collect())
+}
+
+/// Creates the error returned when a command is used on a key holding the
+/// wrong kind of value.
+fn new_wrong_type_error() -> Object {
+    Object::new_error(b"WRONGTYPE Operation against a key holding the wrong kind of value")
+}
+
+/// Converts an expire time argument, either relative to now or an absolute
+/// Unix time, to milliseconds since the Unix epoch. Returns `None` on
+/// overflow.
+fn to_expire_time_ms(amount: i64, unit: TimeUnit, is_absolute: bool) -> Option<i64> {
+    let ms = unit.to_ms(amount)?;
+    if is_absolute {
+        Some(ms)
+    } else {
+        ms.checked_add(to_unix_ms(time::SystemTime::now()))
     }
 }
 
-/// Convert in place a byte slice to ASCII uppercase.
-fn convert_to_ascii_uppercase(s: &mut [u8]) {
-    for i in 0..s.len() {
-        s[i] = s[i].to_ascii_uppercase();
+/// Parses the amount given with an uppercase `EX`, `PX`, `EXAT` or `PXAT`
+/// option into the time to expire at. The amount must be positive.
+fn parse_expiration_option(
+    option: &[u8],
+    amount: &[u8],
+    name: &str,
+) -> Result<time::SystemTime, Object> {
+    let Some(amount) = parse_i64(amount) else {
+        return Err(new_not_integer_error());
+    };
+    let unit = match option {
+        b"EX" | b"EXAT" => TimeUnit::Seconds,
+        _ => TimeUnit::Milliseconds,
+    };
+    let is_absolute = option.ends_with(b"AT");
+    let expires_at_ms = if amount > 0 {
+        to_expire_time_ms(amount, unit, is_absolute)
+    } else {
+        None
+    };
+    let Some(expires_at_ms) = expires_at_ms else {
+        let message = format!("ERR invalid expire time in '{name}' command");
+        return Err(Object::new_error(message.as_bytes()));
+    };
+    Ok(from_unix_ms(expires_at_ms))
+}
+
+/// Converts when something expires to the reply of a TTL command: the time
+/// left to live or, when absolute, the Unix time, in the given unit.
+fn to_ttl(expires_at: time::SystemTime, unit: TimeUnit, is_absolute: bool) -> i64 {
+    let expires_at_ms = to_unix_ms(expires_at);
+    let ms = if is_absolute {
+        expires_at_ms
+    } else {
+        std::cmp::max(0, expires_at_ms - to_unix_ms(time::SystemTime::now()))
+    };
+    unit.convert_ms(ms)
+}
+
+/// Converts a time to milliseconds since the Unix epoch.
+fn to_unix_ms(t: time::SystemTime) -> i64 {
+    match t.duration_since(time::UNIX_EPOCH) {
+        Ok(d) => d.as_millis() as i64,
+        Err(e) => -(e.duration().as_millis() as i64),
     }
 }
 
-/// Parse an i64 from a string of bytes. To parse correctly the string
-/// must be this in ASCII: (+|-)?\d+
-fn parse_i64(s: &[u8]) -> Option<i64> {
-    if s.is_empty() {
+/// Converts milliseconds since the Unix epoch to a time.
+fn from_unix_ms(ms: i64) -> time::SystemTime {
+    let d = time::Duration::from_millis(ms.unsigned_abs());
+    if ms >= 0 {
+        time::UNIX_EPOCH + d
+    } else {
+        time::UNIX_EPOCH - d
+    }
+}
+
+/// Resolves an inclusive range of indexes, where negative indexes offset
+/// from the end, to positions within a sequence of the given length. Returns
+/// `None` when the range is empty.
+fn normalize_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+
+    // Negative indexes offset from the end.
+    let start = if start < 0 { start + len } else { start };
+    let stop = if stop < 0 { stop + len } else { stop };
+    let start = std::cmp::max(0, start);
+
+    // Check for start within range.
+    if start >= len || start > stop {
         return None;
     }
 
-    let (is_negative, s) = match s[0] {
-        b'-' => (true, &s[1..]),
-        b'+' => (false, &s[1..]),
+    // Check for stop within range.
+    let stop = std::cmp::min(stop, len - 1);
+
+    Some((start as usize, stop as usize))
+}
+
+/// Convert in place a byte slice to ASCII uppercase.
+fn convert_to_ascii_uppercase(s: &mut [u8]) {
+    s.make_ascii_uppercase();
+}
+
+/// Parse an i64 from a string of bytes. To parse correctly the string
+/// must be this in ASCII, other than "-0": -?(0|[1-9]\d*)
+fn parse_i64(s: &[u8]) -> Option<i64> {
+    let (is_negative, digits) = match s.split_first() {
+        Some((b'-', rest)) => (true, rest),
         _ => (false, s),
     };
 
-    if s.is_empty() {
-        return None;
+    // Only accept the form an integer is printed in, so there's no plus
+    // sign, no leading zeros and no negative zero.
+    match digits {
+        [] => return None,
+        [b'0'] if is_negative => return None,
+        [b'0', _, ..] => return None,
+        _ => (),
     }
 
-    let mut n = 0;
-    for b in s {
-        if b'0' <= *b && *b <= b'9' {
-            n = 10 * n + (b - b'0') as i64;
-        } else {
+    let mut n: i64 = 0;
+    for b in digits {
+        if !b.is_ascii_digit() {
             return None;
         }
-    }
-
-    if is_negative {
-        n = -n;
+        // Accumulate toward the sign so the smallest integer can be parsed.
+        let digit = (b - b'0') as i64;
+        n = n.checked_mul(10)?;
+        n = if is_negative {
+            n.checked_sub(digit)?
+        } else {
+            n.checked_add(digit)?
+        };
     }
 
     Some(n)
@@ -452,10 +1674,10 @@ fn parse_usize(s: &[u8]) -> Option<usize> {
         return None;
     }
 
-    let mut n = 0;
+    let mut n: usize = 0;
     for b in s {
-        if b'0' <= *b && *b <= b'9' {
-            n = 10 * n + (b - b'0') as usize;
+        if b.is_ascii_digit() {
+            n = n.checked_mul(10)?.checked_add((b - b'0') as usize)?;
         } else {
             return None;
         }
@@ -463,3 +1685,351 @@ fn parse_usize(s: &[u8]) -> Option<usize> {
 
     Some(n)
 }
+
+/// Runs a command on an engine and returns its reply.
+#[cfg(test)]
+pub(crate) fn run(engine: &mut Engine, args: &[&str]) -> Object {
+    send(engine, 1, args).expect("command doesn't block")
+}
+
+/// Runs a command on an engine for a client. Returns its reply, or `None`
+/// when the client is blocked.
+#[cfg(test)]
+pub(crate) fn send(engine: &mut Engine, client: ClientId, args: &[&str]) -> Option<Object> {
+    let args = args
+        .iter()
+        .map(|arg| Object::BulkString(Some(arg.as_bytes().to_vec())))
+        .collect();
+    engine.do_command(client, Object::new_array(args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Engine, Object, new_not_integer_error, new_syntax_error, new_wrong_type_error, parse_i64,
+        parse_usize, run,
+    };
+
+    fn bulk(string: &str) -> Object {
+        Object::BulkString(Some(string.as_bytes().to_vec()))
+    }
+
+    /// Creates the reply of a `SCAN` like command.
+    fn scan_reply(cursor: &str, items: &[&str]) -> Object {
+        let items = items.iter().map(|item| bulk(item)).collect();
+        Object::new_array(vec![bulk(cursor), Object::new_array(items)])
+    }
+
+    #[test]
+    fn parses_integers_in_printed_form() {
+        assert_eq!(parse_i64(b"0"), Some(0));
+        assert_eq!(parse_i64(b"-17"), Some(-17));
+        assert_eq!(parse_i64(b"9223372036854775807"), Some(i64::MAX));
+        assert_eq!(parse_i64(b"-9223372036854775808"), Some(i64::MIN));
+        for s in [&b""[..], b"-", b"-0", b"+1", b"01", b"1a", b" 1", b"1.0"] {
+            assert_eq!(parse_i64(s), None, "{:?}", String::from_utf8_lossy(s));
+        }
+    }
+
+    #[test]
+    fn rejects_integers_out_of_range() {
+        assert_eq!(parse_i64(b"9223372036854775808"), None);
+        assert_eq!(parse_i64(b"-9223372036854775809"), None);
+        assert_eq!(parse_i64(b"99999999999999999999"), None);
+        assert_eq!(parse_usize(b"18446744073709551615"), Some(usize::MAX));
+        assert_eq!(parse_usize(b"18446744073709551616"), None);
+        assert_eq!(parse_usize(b"-1"), None);
+    }
+
+    #[test]
+    fn lscan_walks_a_list_and_says_it_is_not_redis() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["RPUSH", "l", "a", "b", "c"]);
+        assert_eq!(
+            run(&mut engine, &["LSCAN", "l", "0", "COUNT", "2"]),
+            scan_reply("2", &["a", "b"])
+        );
+        assert_eq!(
+            run(&mut engine, &["LSCAN", "l", "2", "COUNT", "2"]),
+            scan_reply("0", &["c"])
+        );
+
+        let Object::Array(help) = run(&mut engine, &["LSCAN", "help"]) else {
+            panic!("expected an array");
+        };
+        assert!(help.items.contains(&Object::new_simple_string(
+            b"    keys. This command is not part of Redis. The cursor is an index"
+        )));
+    }
+
+    #[test]
+    fn scan_returns_lasting_keys_exactly_once() {
+        let mut engine = Engine::new();
+        for i in 0..100 {
+            run(&mut engine, &["SET", &format!("keep:{i}"), "v"]);
+            run(&mut engine, &["SET", &format!("temp:{i}"), "v"]);
+        }
+
+        let mut seen = std::collections::HashMap::new();
+        let mut cursor = "0".to_string();
+        let mut round = 0;
+        loop {
+            let Object::Array(reply) = run(&mut engine, &["SCAN", &cursor, "COUNT", "7"]) else {
+                panic!("expected an array");
+            };
+            let [Object::BulkString(Some(next)), Object::Array(keys)] = &reply.items[..] else {
+                panic!("expected a cursor and keys");
+            };
+            for key in keys.items.iter() {
+                let Object::BulkString(Some(key)) = key else {
+                    panic!("expected a key");
+                };
+                *seen.entry(key.clone()).or_insert(0) += 1;
+            }
+            cursor = String::from_utf8(next.clone()).unwrap();
+            if cursor == "0" {
+                break;
+            }
+
+            // Free slots and fill them, and others, with new keys between
+            // calls.
+            for i in 0..5 {
+                let n = round * 5 + i;
+                run(&mut engine, &["DEL", &format!("temp:{}", (n * 37) % 100)]);
+                run(&mut engine, &["SET", &format!("new:{n}"), "v"]);
+            }
+            round += 1;
+        }
+
+        for i in 0..100 {
+            let key = format!("keep:{i}").into_bytes();
+            assert_eq!(seen.get(&key), Some(&1), "keep:{i}");
+        }
+        assert!(seen.values().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn keys_skips_hashes_whose_fields_all_expired() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["HSET", "gone", "a", "1", "b", "2"]);
+        run(&mut engine, &["HSET", "kept", "a", "1", "b", "2"]);
+        let fields = ["FIELDS", "2", "a", "b"];
+        run(
+            &mut engine,
+            &[&["HPEXPIRE", "gone", "1"][..], &fields].concat(),
+        );
+        run(&mut engine, &["HPEXPIRE", "kept", "1", "FIELDS", "1", "a"]);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+
+        assert_eq!(
+            run(&mut engine, &["KEYS", "*"]),
+            Object::new_array(vec![bulk("kept")])
+        );
+        assert_eq!(run(&mut engine, &["SCAN", "0"]), scan_reply("0", &["kept"]));
+    }
+
+    #[test]
+    fn active_expiry_removes_keys_everywhere() {
+        let mut engine = Engine::new();
+        for i in 0..50 {
+            run(&mut engine, &["SET", &format!("k{i}"), "v", "PX", "1"]);
+        }
+        // Only keys with a TTL are sampled, and all of those have expired,
+        // so the cycle keeps going until they're all gone.
+        run(&mut engine, &["SET", "kept", "v"]);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert_eq!(engine.data.len(), 51);
+
+        engine.active_expire_cycle();
+        assert_eq!(engine.data.len(), 1);
+        assert_eq!(engine.expires.len(), 0);
+        assert_eq!(engine.slots.scan(0, 100), (0, vec![b"kept".to_vec()]));
+        assert_eq!(
+            run(&mut engine, &["EXISTS", "k0", "kept"]),
+            Object::Integer(1)
+        );
+    }
+
+    #[test]
+    fn lazy_expiry_applies_to_every_type() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["RPUSH", "list", "a"]);
+        run(&mut engine, &["HSET", "hash", "f", "v"]);
+        run(&mut engine, &["SADD", "set", "m"]);
+        for key in ["list", "hash", "set"] {
+            run(&mut engine, &["PEXPIRE", key, "1"]);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(5));
+
+        assert_eq!(run(&mut engine, &["LLEN", "list"]), Object::Integer(0));
+        assert_eq!(
+            run(&mut engine, &["HGET", "hash", "f"]),
+            Object::BulkString(None)
+        );
+        assert_eq!(
+            run(&mut engine, &["SISMEMBER", "set", "m"]),
+            Object::Integer(0)
+        );
+        assert!(engine.data.is_empty());
+        assert_eq!(engine.expires.len(), 0);
+
+        // A new value doesn't inherit the expired TTL.
+        run(&mut engine, &["RPUSH", "list", "b"]);
+        assert_eq!(run(&mut engine, &["TTL", "list"]), Object::Integer(-1));
+    }
+
+    #[test]
+    fn set_accepts_and_rejects_option_combinations() {
+        let ok = Object::new_simple_string(b"OK");
+        let nil = Object::BulkString(None);
+        let syntax = new_syntax_error();
+        let invalid = Object::new_error(b"ERR invalid expire time in 'set' command");
+        let cases: Vec<(&[&str], Object, Object)> = vec![
+            (&["s", "new"], ok.clone(), bulk("new")),
+            (&["s", "new", "nx"], nil.clone(), bulk("old")),
+            (&["s", "new", "XX"], ok.clone(), bulk("new")),
+            (&["n", "new", "XX"], nil.clone(), nil.clone()),
+            (&["n", "new", "NX", "GET"], nil.clone(), bulk("new")),
+            (&["s", "new", "GET"], bulk("old"), bulk("new")),
+            (&["s", "new", "NX", "GET"], bulk("old"), bulk("old")),
+            (&["s", "new", "EX", "10", "GET"], bulk("old"), bulk("new")),
+            (&["s", "new", "KEEPTTL"], ok.clone(), bulk("new")),
+            (&["s", "new", "EXAT", "1"], ok.clone(), nil.clone()),
+            (&["s", "new", "PXAT", "1"], ok.clone(), nil.clone()),
+            (&["s", "new", "NX", "XX"], syntax.clone(), bulk("old")),
+            (&["s", "new", "XX", "NX"], syntax.clone(), bulk("old")),
+            (
+                &["s", "new", "EX", "10", "KEEPTTL"],
+                syntax.clone(),
+                bulk("old"),
+            ),
+            (
+                &["s", "new", "KEEPTTL", "PX", "10"],
+                syntax.clone(),
+                bulk("old"),
+            ),
+            (
+                &["s", "new", "EX", "10", "PXAT", "10"],
+                syntax.clone(),
+                bulk("old"),
+            ),
+            (&["s", "new", "EX"], syntax.clone(), bulk("old")),
+            (&["s", "new", "SOON"], syntax.clone(), bulk("old")),
+            (&["s", "new", "EX", "0"], invalid.clone(), bulk("old")),
+            (&["s", "new", "PX", "-5"], invalid.clone(), bulk("old")),
+            (
+                &["s", "new", "EX", "ten"],
+                new_not_integer_error(),
+                bulk("old"),
+            ),
+            (
+                &["l", "new", "GET"],
+                new_wrong_type_error(),
+                new_wrong_type_error(),
+            ),
+        ];
+        for (args, reply, value) in cases {
+            let mut engine = Engine::new();
+            run(&mut engine, &["SET", "s", "old"]);
+            run(&mut engine, &["RPUSH", "l", "x"]);
+            let command = [&["SET"][..], args].concat();
+            assert_eq!(run(&mut engine, &command), reply, "{args:?}");
+            assert_eq!(run(&mut engine, &["GET", args[0]]), value, "{args:?}");
+        }
+    }
+
+    #[test]
+    fn set_keeps_the_ttl_only_when_asked() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["SET", "k", "v", "EX", "100"]);
+        run(&mut engine, &["SET", "k", "w", "KEEPTTL"]);
+        assert_eq!(run(&mut engine, &["TTL", "k"]), Object::Integer(100));
+        run(&mut engine, &["SET", "k", "x"]);
+        assert_eq!(run(&mut engine, &["TTL", "k"]), Object::Integer(-1));
+    }
+
+    #[test]
+    fn rename_carries_the_ttl_over() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["SET", "a", "1", "EX", "100"]);
+        run(&mut engine, &["SET", "b", "2", "EX", "50"]);
+        run(&mut engine, &["SET", "c", "3"]);
+        assert_eq!(
+            run(&mut engine, &["RENAME", "a", "b"]),
+            Object::new_simple_string(b"OK")
+        );
+        assert_eq!(run(&mut engine, &["GET", "b"]), bulk("1"));
+        assert_eq!(run(&mut engine, &["TTL", "b"]), Object::Integer(100));
+        assert_eq!(run(&mut engine, &["EXISTS", "a"]), Object::Integer(0));
+
+        // A key without a TTL replaces one with a TTL.
+        run(&mut engine, &["RENAME", "c", "b"]);
+        assert_eq!(run(&mut engine, &["TTL", "b"]), Object::Integer(-1));
+        assert_eq!(engine.expires.len(), 0);
+
+        assert_eq!(
+            run(&mut engine, &["RENAME", "b", "b"]),
+            Object::new_simple_string(b"OK")
+        );
+        assert_eq!(
+            run(&mut engine, &["RENAMENX", "b", "b"]),
+            Object::Integer(0)
+        );
+        assert_eq!(run(&mut engine, &["GET", "b"]), bulk("3"));
+        assert_eq!(
+            run(&mut engine, &["RENAME", "a", "a"]),
+            Object::new_error(b"ERR no such key")
+        );
+
+        run(&mut engine, &["SET", "d", "4"]);
+        assert_eq!(
+            run(&mut engine, &["RENAMENX", "d", "b"]),
+            Object::Integer(0)
+        );
+        assert_eq!(
+            run(&mut engine, &["RENAMENX", "d", "e"]),
+            Object::Integer(1)
+        );
+        assert_eq!(run(&mut engine, &["GET", "e"]), bulk("4"));
+    }
+
+    #[test]
+    fn copy_replaces_only_when_asked() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["RPUSH", "list", "a", "b"]);
+        run(&mut engine, &["PEXPIRE", "list", "100000"]);
+        run(&mut engine, &["SET", "taken", "v"]);
+
+        assert_eq!(
+            run(&mut engine, &["COPY", "list", "taken"]),
+            Object::Integer(0)
+        );
+        assert_eq!(run(&mut engine, &["GET", "taken"]), bulk("v"));
+        assert_eq!(
+            run(&mut engine, &["COPY", "list", "taken", "REPLACE"]),
+            Object::Integer(1)
+        );
+        assert_eq!(
+            run(&mut engine, &["TYPE", "taken"]),
+            Object::new_simple_string(b"list")
+        );
+        assert_eq!(run(&mut engine, &["TTL", "taken"]), Object::Integer(100));
+
+        // The copy is independent of the original.
+        run(&mut engine, &["RPUSH", "taken", "c"]);
+        assert_eq!(run(&mut engine, &["LLEN", "list"]), Object::Integer(2));
+
+        assert_eq!(
+            run(&mut engine, &["COPY", "missing", "new"]),
+            Object::Integer(0)
+        );
+        assert_eq!(
+            run(&mut engine, &["COPY", "list", "list", "REPLACE"]),
+            Object::new_error(b"ERR source and destination objects are the same")
+        );
+        assert_eq!(
+            run(&mut engine, &["COPY", "list", "new", "DB", "1"]),
+            Object::new_error(b"ERR DB index is out of range")
+        );
+    }
+}
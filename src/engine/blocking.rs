@@ -0,0 +1,294 @@
+//! Bookkeeping for clients blocked by commands such as `BLPOP`.
+//!
+//! A blocking command that can't be served right away parks its client here
+//! along with the command's arguments. When a key a client waits on may have
+//! become servable, the key is marked ready and the engine runs the parked
+//! command again. Clients waiting on the same key are served in the order
+//! they blocked.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time;
+
+use super::{ClientId, Key, Object};
+
+/// Returned by a blocking command's handler when the client must wait.
+pub struct BlockOn {
+    /// The command's arguments, to run the command again with.
+    pub elements: VecDeque<Vec<u8>>,
+
+    /// The keys to wait on.
+    pub keys: Vec<Key>,
+
+    /// How long to wait before giving up, or `None` to wait forever.
+    pub timeout: Option<time::Duration>,
+}
+
+/// A client waiting on keys.
+struct BlockedClient {
+    /// The uppercase command name.
+    command: Vec<u8>,
+
+    /// The command's arguments.
+    elements: VecDeque<Vec<u8>>,
+
+    /// The keys being waited on.
+    keys: Vec<Key>,
+
+    /// When to give up waiting.
+    deadline: Option<time::Instant>,
+}
+
+/// The registry of blocked clients.
+pub struct BlockedClients {
+    /// What each blocked client waits for.
+    clients: HashMap<ClientId, BlockedClient>,
+
+    /// The clients waiting on each key, in the order they blocked.
+    waiters: HashMap<Key, VecDeque<ClientId>>,
+
+    /// Keys that may now serve a waiting client, in the order they became
+    /// ready.
+    ready: VecDeque<Key>,
+
+    /// The same keys as `ready` for quick lookups.
+    ready_set: HashSet<Key>,
+}
+
+impl BlockedClients {
+    pub fn new() -> Self {
+        BlockedClients {
+            clients: HashMap::new(),
+            waiters: HashMap::new(),
+            ready: VecDeque::new(),
+            ready_set: HashSet::new(),
+        }
+    }
+
+    /// Parks a client until one of its keys is ready or it times out. A
+    /// client that is already blocked keeps its deadline and its place in
+    /// line.
+    pub fn block(&mut self, client: ClientId, command: Vec<u8>, block_on: BlockOn) {
+        if let Some(blocked) = self.clients.get_mut(&client) {
+            blocked.elements = block_on.elements;
+            return;
+        }
+
+        let deadline = block_on
+            .timeout
+            .map(|timeout| time::Instant::now() + timeout);
+        for key in block_on.keys.iter() {
+            let waiters = self.waiters.entry(key.clone()).or_default();
+            if !waiters.contains(&client) {
+                waiters.push_back(client);
+            }
+        }
+        let blocked = BlockedClient {
+            command,
+            elements: block_on.elements,
+            keys: block_on.keys,
+            deadline,
+        };
+        self.clients.insert(client, blocked);
+    }
+
+    /// Removes a client from the registry. Returns whether it was blocked.
+    pub fn unblock(&mut self, client: ClientId) -> bool {
+        let Some(blocked) = self.clients.remove(&client) else {
+            return false;
+        };
+        for key in blocked.keys.iter() {
+            if let Some(waiters) = self.waiters.get_mut(key) {
+                waiters.retain(|waiter| *waiter != client);
+                if waiters.is_empty() {
+                    self.waiters.remove(key);
+                }
+            }
+        }
+        true
+    }
+
+    /// Marks a key as possibly able to serve waiting clients. Keys nobody
+    /// waits on are ignored.
+    pub fn signal_key_ready(&mut self, key: &[u8]) {
+        if self.waiters.contains_key(key) && !self.ready_set.contains(key) {
+            self.ready_set.insert(key.to_vec());
+            self.ready.push_back(key.to_vec());
+        }
+    }
+
+    /// Takes the next key marked as ready.
+    pub fn pop_ready_key(&mut self) -> Option<Key> {
+        let key = self.ready.pop_front()?;
+        self.ready_set.remove(&key);
+        Some(key)
+    }
+
+    /// Returns the clients waiting on a key in the order they blocked.
+    pub fn waiters(&self, key: &[u8]) -> Vec<ClientId> {
+        match self.waiters.get(key) {
+            Some(waiters) => waiters.iter().copied().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the command and a copy of the arguments a client is blocked
+    /// on.
+    pub fn command(&self, client: ClientId) -> Option<(Vec<u8>, VecDeque<Vec<u8>>)> {
+        let blocked = self.clients.get(&client)?;
+        Some((blocked.command.clone(), blocked.elements.clone()))
+    }
+
+    /// Returns the clients whose deadlines have passed.
+    pub fn timed_out(&self, now: time::Instant) -> Vec<ClientId> {
+        self.clients
+            .iter()
+            .filter(|(_, blocked)| blocked.deadline.is_some_and(|deadline| deadline <= now))
+            .map(|(client, _)| *client)
+            .collect()
+    }
+
+    /// Returns the earliest deadline of any blocked client.
+    pub fn next_deadline(&self) -> Option<time::Instant> {
+        self.clients
+            .values()
+            .filter_map(|blocked| blocked.deadline)
+            .min()
+    }
+}
+
+/// Returns the name of the type of value a blocking command waits for. A
+/// key holding another type doesn't serve the command.
+pub fn blocked_type(command: &[u8]) -> &'static str {
+    match command {
+        b"BZPOPMIN" | b"BZPOPMAX" | b"BZMPOP" => "zset",
+        b"XREAD" | b"XREADGROUP" => "stream",
+        _ => "list",
+    }
+}
+
+/// Returns the reply to a blocking command that timed out. Commands that
+/// move a single item reply with a null bulk string and the rest with a
+/// null array.
+pub fn timeout_reply(command: &[u8]) -> Object {
+    match command {
+        b"BLMOVE" | b"BRPOPLPUSH" => Object::BulkString(None),
+        _ => Object::Null,
+    }
+}
+
+/// Parses a timeout given in seconds, which may have a fractional part. A
+/// timeout of zero means to wait forever.
+pub fn parse_timeout(timeout: &[u8]) -> Result<Option<time::Duration>, Object> {
+    let timeout = std::str::from_utf8(timeout)
+        .ok()
+        .and_then(|timeout| timeout.parse::<f64>().ok())
+        .filter(|timeout| timeout.is_finite());
+    let Some(timeout) = timeout else {
+        return Err(Object::new_error(
+            b"ERR timeout is not a float or out of range",
+        ));
+    };
+    if timeout < 0.0 {
+        return Err(Object::new_error(b"ERR timeout is negative"));
+    }
+    if timeout == 0.0 {
+        return Ok(None);
+    }
+    match time::Duration::try_from_secs_f64(timeout) {
+        Ok(timeout) => Ok(Some(timeout)),
+        Err(_) => Err(Object::new_error(b"ERR timeout is out of range")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time;
+
+    use super::super::{Engine, Object, send};
+
+    fn bulk(string: &str) -> Object {
+        Object::BulkString(Some(string.as_bytes().to_vec()))
+    }
+
+    /// The reply to a pop from a list.
+    fn popped(key: &str, item: &str) -> Object {
+        Object::new_array(vec![bulk(key), bulk(item)])
+    }
+
+    #[test]
+    fn waiters_are_served_in_the_order_they_blocked() {
+        let mut engine = Engine::new();
+        assert_eq!(send(&mut engine, 2, &["BLPOP", "l", "0"]), None);
+        assert_eq!(send(&mut engine, 1, &["BRPOP", "other", "l", "0"]), None);
+        assert_eq!(send(&mut engine, 3, &["BLPOP", "l", "0"]), None);
+
+        // One push wakes exactly one waiter, the first to block.
+        assert_eq!(
+            send(&mut engine, 9, &["RPUSH", "l", "a"]),
+            Some(Object::Integer(1))
+        );
+        assert_eq!(engine.take_replies(), vec![(2, popped("l", "a"))]);
+        assert_eq!(engine.blocked.waiters(b"l"), vec![1, 3]);
+
+        // Each waiter takes one item while there are items left.
+        send(&mut engine, 9, &["RPUSH", "l", "b", "c", "d"]);
+        assert_eq!(
+            engine.take_replies(),
+            vec![(1, popped("l", "d")), (3, popped("l", "b"))]
+        );
+        assert!(engine.blocked.waiters(b"other").is_empty());
+        assert_eq!(
+            send(&mut engine, 9, &["LRANGE", "l", "0", "-1"]),
+            Some(Object::new_array(vec![bulk("c")]))
+        );
+    }
+
+    #[test]
+    fn timed_out_waiters_get_a_null_reply() {
+        let mut engine = Engine::new();
+        assert_eq!(send(&mut engine, 1, &["BLPOP", "l", "0.01"]), None);
+        assert_eq!(
+            send(
+                &mut engine,
+                2,
+                &["BLMOVE", "l", "m", "LEFT", "LEFT", "0.01"]
+            ),
+            None
+        );
+        assert_eq!(send(&mut engine, 3, &["BLPOP", "l", "0"]), None);
+        assert!(engine.next_block_deadline().is_some());
+
+        engine.time_out_blocked_clients();
+        assert!(engine.take_replies().is_empty());
+        std::thread::sleep(time::Duration::from_millis(20));
+        engine.time_out_blocked_clients();
+        let mut replies = engine.take_replies();
+        replies.sort_by_key(|(client, _)| *client);
+        assert_eq!(
+            replies,
+            vec![(1, Object::Null), (2, Object::BulkString(None))]
+        );
+
+        // The client waiting forever is still served.
+        assert_eq!(engine.next_block_deadline(), None);
+        send(&mut engine, 9, &["RPUSH", "l", "a"]);
+        assert_eq!(engine.take_replies(), vec![(3, popped("l", "a"))]);
+    }
+
+    #[test]
+    fn disconnected_waiters_are_forgotten() {
+        let mut engine = Engine::new();
+        send(&mut engine, 1, &["BLPOP", "l", "0"]);
+        send(&mut engine, 2, &["BLPOP", "l", "0"]);
+        engine.remove_client(1);
+        assert_eq!(engine.blocked.waiters(b"l"), vec![2]);
+
+        send(&mut engine, 9, &["RPUSH", "l", "a", "b"]);
+        assert_eq!(engine.take_replies(), vec![(2, popped("l", "a"))]);
+        assert!(engine.blocked.waiters(b"l").is_empty());
+        assert_eq!(
+            send(&mut engine, 9, &["LLEN", "l"]),
+            Some(Object::Integer(1))
+        );
+    }
+}
@@ -0,0 +1,940 @@
+//! The hash type and its commands.
+
+use std::collections::{BTreeSet, HashMap, VecDeque, hash_map};
+use std::time;
+
+use super::listpack::{self, ListPack};
+use super::string::{format_f64, new_not_finite_error, new_not_float_error, parse_f64};
+use super::value::Value;
+use super::{
+    Engine, ExpireFlags, Object, TimeUnit, convert_to_ascii_uppercase, from_unix_ms,
+    new_arity_error, new_bulk_string_array, new_integer_array, new_not_integer_error,
+    new_pairs_reply, new_syntax_error, new_wrong_type_error, parse_i64, parse_random_count,
+    to_expire_time_ms, to_ttl, to_unix_ms,
+};
+
+/// The most fields a hash keeps in a listpack before moving to a table.
+const COMPACT_MAX_FIELDS: usize = 128;
+
+/// The longest field or value a hash keeps in a listpack before moving to a
+/// table.
+const COMPACT_MAX_LEN: usize = 64;
+
+/// A map of fields to values. Fields may have their own TTLs.
+#[derive(Clone, Debug)]
+pub struct Hash {
+    encoding: Encoding,
+
+    /// When each field with a TTL expires.
+    expires: HashMap<Vec<u8>, time::SystemTime>,
+
+    /// The same TTLs ordered by time, so expired fields are found quickly.
+    deadlines: BTreeSet<(time::SystemTime, Vec<u8>)>,
+}
+
+/// How a hash's fields are stored.
+#[derive(Clone, Debug)]
+enum Encoding {
+    /// Fields and values alternate in a listpack, for small hashes.
+    Compact(ListPack),
+
+    /// A hash table, for hashes with many or long fields.
+    Table(HashMap<Vec<u8>, Vec<u8>>),
+}
+
+impl Hash {
+    /// Creates a new empty hash.
+    pub fn new() -> Self {
+        Hash {
+            encoding: Encoding::Compact(ListPack::new()),
+            expires: HashMap::new(),
+            deadlines: BTreeSet::new(),
+        }
+    }
+
+    /// Returns the number of fields.
+    pub fn len(&self) -> usize {
+        match &self.encoding {
+            Encoding::Compact(pack) => pack.len() / 2,
+            Encoding::Table(table) => table.len(),
+        }
+    }
+
+    /// Returns whether the hash has no fields.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the value of a field.
+    pub fn get(&self, field: &[u8]) -> Option<&[u8]> {
+        match &self.encoding {
+            Encoding::Compact(pack) => {
+                let index = find_field(pack, field)?;
+                pack.get(index + 1)
+            }
+            Encoding::Table(table) => table.get(field).map(|value| value.as_slice()),
+        }
+    }
+
+    /// Returns whether a field exists.
+    pub fn contains(&self, field: &[u8]) -> bool {
+        self.get(field).is_some()
+    }
+
+    /// Sets the value of a field, clearing any TTL it has. Returns whether
+    /// the field is new.
+    pub fn insert(&mut self, field: &[u8], value: &[u8]) -> bool {
+        self.set_expiration(field, None);
+        self.update(field, value)
+    }
+
+    /// Sets the value of a field, keeping any TTL it has. Returns whether
+    /// the field is new.
+    pub fn update(&mut self, field: &[u8], value: &[u8]) -> bool {
+        if let Encoding::Compact(pack) = &mut self.encoding {
+            let index = find_field(pack, field);
+            let is_too_big = field.len() > COMPACT_MAX_LEN
+                || value.len() > COMPACT_MAX_LEN
+                || (index.is_none() && pack.len() / 2 >= COMPACT_MAX_FIELDS);
+            if !is_too_big {
+                return match index {
+                    Some(index) => {
+                        pack.splice(index + 1, 1, &[value]);
+                        false
+                    }
+                    None => {
+                        pack.push_back(field);
+                        pack.push_back(value);
+                        true
+                    }
+                };
+            }
+            self.convert_to_table();
+        }
+
+        let Encoding::Table(table) = &mut self.encoding else {
+            unreachable!("hash was converted to a table");
+        };
+        table.insert(field.to_vec(), value.to_vec()).is_none()
+    }
+
+    /// Removes a field. Returns whether it existed.
+    pub fn remove(&mut self, field: &[u8]) -> bool {
+        self.set_expiration(field, None);
+        match &mut self.encoding {
+            Encoding::Compact(pack) => match find_field(pack, field) {
+                Some(index) => {
+                    pack.splice(index, 2, &[]);
+                    true
+                }
+                None => false,
+            },
+            Encoding::Table(table) => table.remove(field).is_some(),
+        }
+    }
+
+    /// Returns an iterator over the fields and their values.
+    pub fn iter(&self) -> Iter<'_> {
+        match &self.encoding {
+            Encoding::Compact(pack) => Iter::Compact(pack.iter()),
+            Encoding::Table(table) => Iter::Table(table.iter()),
+        }
+    }
+
+    /// Returns when a field expires, if it has a TTL.
+    pub fn expires_at(&self, field: &[u8]) -> Option<time::SystemTime> {
+        self.expires.get(field).copied()
+    }
+
+    /// Sets or clears the TTL of a field.
+    pub fn set_expiration(&mut self, field: &[u8], expires_at: Option<time::SystemTime>) {
+        if let Some(old) = self.expires.remove(field) {
+            self.deadlines.remove(&(old, field.to_vec()));
+        }
+        if let Some(expires_at) = expires_at {
+            self.expires.insert(field.to_vec(), expires_at);
+            self.deadlines.insert((expires_at, field.to_vec()));
+        }
+    }
+
+    /// Returns whether any field has a TTL.
+    pub fn has_expirations(&self) -> bool {
+        !self.expires.is_empty()
+    }
+
+    /// Returns whether every field's TTL has passed at the given time, so
+    /// the hash is gone once its expired fields are removed.
+    pub fn is_expired(&self, now: time::SystemTime) -> bool {
+        self.expires.len() == self.len()
+            && self
+                .deadlines
+                .last()
+                .is_some_and(|(expires_at, _)| *expires_at <= now)
+    }
+
+    /// Removes the fields whose TTLs have passed at the given time. Returns
+    /// how many were removed.
+    pub fn remove_expired(&mut self, now: time::SystemTime) -> usize {
+        let mut removed = 0;
+        while let Some((expires_at, _)) = self.deadlines.first()
+            && *expires_at <= now
+        {
+            if let Some((_, field)) = self.deadlines.pop_first() {
+                self.remove(&field);
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// Moves the fields from a listpack into a hash table. Hashes never move
+    /// back to a listpack.
+    fn convert_to_table(&mut self) {
+        let table = self
+            .iter()
+            .map(|(field, value)| (field.to_vec(), value.to_vec()))
+            .collect();
+        self.encoding = Encoding::Table(table);
+    }
+}
+
+/// Returns the index in a listpack of the entry holding a field.
+fn find_field(pack: &ListPack, field: &[u8]) -> Option<usize> {
+    let position = pack.iter().step_by(2).position(|entry| entry == field)?;
+    Some(position * 2)
+}
+
+/// An iterator over the fields and values of a hash.
+pub enum Iter<'a> {
+    Compact(listpack::Iter<'a>),
+    Table(hash_map::Iter<'a, Vec<u8>, Vec<u8>>),
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Iter::Compact(iter) => {
+                let field = iter.next()?;
+                let value = iter.next()?;
+                Some((field, value))
+            }
+            Iter::Table(iter) => {
+                let (field, value) = iter.next()?;
+                Some((field.as_slice(), value.as_slice()))
+            }
+        }
+    }
+}
+
+/// What part of each field a hash command replies with.
+#[derive(Clone, Copy)]
+pub enum HashPart {
+    Fields,
+    Values,
+    Both,
+}
+
+impl Engine {
+    /// Do an `HSET` or `HMSET` command. `HSET` replies with the number of
+    /// new fields and `HMSET` with OK.
+    pub(super) fn do_hset(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        is_hmset: bool,
+    ) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error(name);
+        };
+
+        if elements.is_empty() || !elements.len().is_multiple_of(2) {
+            return new_arity_error(name);
+        }
+
+        let entry = self.get_entry_or_insert(key, Value::Hash(Hash::new()));
+
+        let Value::Hash(hash) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let mut added = 0;
+        while let (Some(field), Some(value)) = (elements.pop_front(), elements.pop_front()) {
+            if hash.insert(&field, &value) {
+                added += 1;
+            }
+        }
+
+        if is_hmset {
+            Object::new_simple_string(b"OK")
+        } else {
+            Object::Integer(added)
+        }
+    }
+
+    /// Do an `HSETNX` command.
+    pub(super) fn do_hsetnx(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(field), Some(value), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("hsetnx");
+        };
+
+        let entry = self.get_entry_or_insert(key, Value::Hash(Hash::new()));
+
+        let Value::Hash(hash) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        if hash.contains(&field) {
+            return Object::Integer(0);
+        }
+
+        hash.insert(&field, &value);
+
+        Object::Integer(1)
+    }
+
+    /// Do an `HGET` command.
+    pub(super) fn do_hget(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(field), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("hget");
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::BulkString(None);
+        };
+
+        let Value::Hash(hash) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        Object::BulkString(hash.get(&field).map(|value| value.to_vec()))
+    }
+
+    /// Do an `HMGET` command.
+    pub(super) fn do_hmget(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error("hmget");
+        };
+
+        if elements.is_empty() {
+            return new_arity_error("hmget");
+        }
+
+        let hash = match self.get_entry(&key).map(|entry| &entry.value) {
+            Some(Value::Hash(hash)) => Some(hash),
+            Some(_) => return new_wrong_type_error(),
+            None => None,
+        };
+
+        let items = elements
+            .iter()
+            .map(|field| {
+                let value = hash.and_then(|hash| hash.get(field));
+                Object::BulkString(value.map(|value| value.to_vec()))
+            })
+            .collect();
+
+        Object::new_array(items)
+    }
+
+    /// Do an `HDEL` command.
+    pub(super) fn do_hdel(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error("hdel");
+        };
+
+        if elements.is_empty() {
+            return new_arity_error("hdel");
+        }
+
+        let Some(entry) = self.get_entry_mut(&key) else {
+            return Object::Integer(0);
+        };
+
+        let Value::Hash(hash) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let removed = elements.iter().filter(|field| hash.remove(field)).count();
+
+        if hash.is_empty() {
+            self.remove_entry(&key);
+        }
+
+        Object::Integer(removed as i64)
+    }
+
+    /// Do a command replying with all the fields of a hash, their values,
+    /// or both.
+    pub(super) fn do_hgetall(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        part: HashPart,
+    ) -> Object {
+        let (Some(key), None) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error(name);
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return match part {
+                HashPart::Both => Object::Map(Vec::new()),
+                _ => Object::new_empty_array(),
+            };
+        };
+
+        let Value::Hash(hash) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        if let HashPart::Both = part {
+            let pairs = hash
+                .iter()
+                .map(|(field, value)| {
+                    let field = Object::BulkString(Some(field.to_vec()));
+                    let value = Object::BulkString(Some(value.to_vec()));
+                    (field, value)
+                })
+                .collect();
+            return Object::Map(pairs);
+        }
+
+        let items = hash
+            .iter()
+            .map(|(field, value)| match part {
+                HashPart::Fields => field.to_vec(),
+                _ => value.to_vec(),
+            })
+            .collect();
+
+        new_bulk_string_array(items)
+    }
+
+    /// Do an `HLEN` command.
+    pub(super) fn do_hlen(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), None) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("hlen");
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::Integer(0);
+        };
+
+        let Value::Hash(hash) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        Object::Integer(hash.len() as i64)
+    }
+
+    /// Do an `HEXISTS` command.
+    pub(super) fn do_hexists(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(field), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("hexists");
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::Integer(0);
+        };
+
+        let Value::Hash(hash) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        Object::Integer(hash.contains(&field) as i64)
+    }
+
+    /// Do an `HSTRLEN` command.
+    pub(super) fn do_hstrlen(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(field), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("hstrlen");
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::Integer(0);
+        };
+
+        let Value::Hash(hash) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let len = hash.get(&field).map_or(0, |value| value.len());
+
+        Object::Integer(len as i64)
+    }
+
+    /// Do an `HINCRBY` command.
+    pub(super) fn do_hincrby(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(field), Some(amount), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("hincrby");
+        };
+
+        let Some(amount) = parse_i64(&amount) else {
+            return new_not_integer_error();
+        };
+
+        let entry = self.get_entry_or_insert(key, Value::Hash(Hash::new()));
+
+        let Value::Hash(hash) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let n = match hash.get(&field) {
+            Some(value) => match parse_i64(value) {
+                Some(n) => n,
+                None => return Object::new_error(b"ERR hash value is not an integer"),
+            },
+            None => 0,
+        };
+
+        let Some(n) = n.checked_add(amount) else {
+            return Object::new_error(b"ERR increment or decrement would overflow");
+        };
+
+        hash.update(&field, n.to_string().as_bytes());
+
+        Object::Integer(n)
+    }
+
+    /// Do an `HINCRBYFLOAT` command.
+    pub(super) fn do_hincrbyfloat(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(field), Some(amount), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("hincrbyfloat");
+        };
+
+        let Some(amount) = parse_f64(&amount) else {
+            return new_not_float_error();
+        };
+
+        // Checked up front so a missing key isn't created on error.
+        if !amount.is_finite() {
+            return new_not_finite_error();
+        }
+
+        let entry = self.get_entry_or_insert(key, Value::Hash(Hash::new()));
+
+        let Value::Hash(hash) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let n = match hash.get(&field) {
+            Some(value) => match parse_f64(value) {
+                Some(n) => n,
+                None => return Object::new_error(b"ERR hash value is not a float"),
+            },
+            None => 0.0,
+        };
+
+        let n = n + amount;
+        if !n.is_finite() {
+            return new_not_finite_error();
+        }
+
+        let value = format_f64(n).into_bytes();
+        hash.update(&field, &value);
+
+        Object::BulkString(Some(value))
+    }
+
+    /// Do an `HRANDFIELD` command.
+    pub(super) fn do_hrandfield(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error("hrandfield");
+        };
+
+        let count = match elements.pop_front().map(|count| parse_random_count(&count)) {
+            Some(Ok(count)) => Some(count),
+            Some(Err(e)) => return e,
+            None => None,
+        };
+
+        let with_values = match elements.pop_front() {
+            Some(mut option) if count.is_some() => {
+                convert_to_ascii_uppercase(&mut option);
+                if option != b"WITHVALUES" {
+                    return new_syntax_error();
+                }
+                true
+            }
+            Some(_) => return new_syntax_error(),
+            None => false,
+        };
+
+        if !elements.is_empty() {
+            return new_syntax_error();
+        }
+
+        match self.get_entry(&key).map(|entry| &entry.value) {
+            Some(Value::Hash(_)) => (),
+            Some(_) => return new_wrong_type_error(),
+            None if count.is_some() => return Object::new_empty_array(),
+            None => return Object::BulkString(None),
+        }
+
+        // Borrow the hash apart from the random number generator.
+        let Some(Value::Hash(hash)) = self.data.get(&key).map(|entry| &entry.value) else {
+            return Object::BulkString(None);
+        };
+
+        let fields: Vec<(&[u8], &[u8])> = hash.iter().collect();
+        let Some(count) = count else {
+            let (field, _) = fields[self.rng.next_below(fields.len())];
+            return Object::BulkString(Some(field.to_vec()));
+        };
+
+        let picked = self
+            .rng
+            .sample(fields.len(), count)
+            .map(|index| fields[index]);
+        if !with_values {
+            return new_bulk_string_array(picked.map(|(field, _)| field.to_vec()).collect());
+        }
+        let pairs = picked
+            .map(|(field, value)| {
+                (
+                    Object::BulkString(Some(field.to_vec())),
+                    Object::BulkString(Some(value.to_vec())),
+                )
+            })
+            .collect();
+        new_pairs_reply(pairs, self.reply_protocol)
+    }
+}
+
+impl Engine {
+    /// Do a field expire command, setting the TTL of fields in a hash from
+    /// an amount of time or, when absolute, a Unix time.
+    pub(super) fn do_hexpire(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        unit: TimeUnit,
+        is_absolute: bool,
+    ) -> Object {
+        let (Some(key), Some(amount)) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error(name);
+        };
+
+        let Some(amount) = parse_i64(&amount) else {
+            return new_not_integer_error();
+        };
+
+        if amount < 0 {
+            return Object::new_error(b"ERR invalid expire time, must be >= 0");
+        }
+
+        let mut flags = ExpireFlags::default();
+        if let Some(option) = elements.front()
+            && !option.eq_ignore_ascii_case(b"FIELDS")
+        {
+            let mut option = elements.pop_front().unwrap_or_default();
+            convert_to_ascii_uppercase(&mut option);
+            if !flags.set(&option) {
+                return new_missing_fields_error();
+            }
+        }
+
+        let fields = match parse_fields(elements) {
+            Ok(fields) => fields,
+            Err(e) => return e,
+        };
+
+        let Some(expires_at_ms) = to_expire_time_ms(amount, unit, is_absolute) else {
+            let message = format!("ERR invalid expire time in '{name}' command");
+            return Object::new_error(message.as_bytes());
+        };
+
+        let Some(entry) = self.get_entry_mut(&key) else {
+            return new_integer_array(fields.iter().map(|_| -2));
+        };
+
+        let Value::Hash(hash) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let now_ms = to_unix_ms(time::SystemTime::now());
+        let mut replies = Vec::with_capacity(fields.len());
+        for field in fields.iter() {
+            let reply = if !hash.contains(field) {
+                -2
+            } else if !flags.allows(hash.expires_at(field).map(to_unix_ms), expires_at_ms) {
+                0
+            } else if expires_at_ms <= now_ms {
+                hash.remove(field);
+                2
+            } else {
+                hash.set_expiration(field, Some(from_unix_ms(expires_at_ms)));
+                1
+            };
+            replies.push(reply);
+        }
+
+        if hash.is_empty() {
+            self.remove_entry(&key);
+        } else if hash.has_expirations() {
+            self.field_expires.insert(&key);
+        }
+
+        new_integer_array(replies)
+    }
+
+    /// Do a field TTL command, returning the remaining time to live of
+    /// fields in a hash or, when absolute, the Unix time they expire at.
+    pub(super) fn do_httl(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        unit: TimeUnit,
+        is_absolute: bool,
+    ) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error(name);
+        };
+
+        let fields = match parse_fields(elements) {
+            Ok(fields) => fields,
+            Err(e) => return e,
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return new_integer_array(fields.iter().map(|_| -2));
+        };
+
+        let Value::Hash(hash) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        new_integer_array(fields.iter().map(|field| {
+            if !hash.contains(field) {
+                return -2;
+            }
+            match hash.expires_at(field) {
+                Some(expires_at) => to_ttl(expires_at, unit, is_absolute),
+                None => -1,
+            }
+        }))
+    }
+
+    /// Do an `HPERSIST` command, removing the TTL from fields in a hash.
+    pub(super) fn do_hpersist(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error("hpersist");
+        };
+
+        let fields = match parse_fields(elements) {
+            Ok(fields) => fields,
+            Err(e) => return e,
+        };
+
+        let Some(entry) = self.get_entry_mut(&key) else {
+            return new_integer_array(fields.iter().map(|_| -2));
+        };
+
+        let Value::Hash(hash) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        new_integer_array(fields.iter().map(|field| {
+            if !hash.contains(field) {
+                -2
+            } else if hash.expires_at(field).is_none() {
+                -1
+            } else {
+                hash.set_expiration(field, None);
+                1
+            }
+        }))
+    }
+}
+
+/// Parses the `FIELDS numfields field [field ...]` arguments that end the
+/// field expiration commands.
+fn parse_fields(mut elements: VecDeque<Vec<u8>>) -> Result<Vec<Vec<u8>>, Object> {
+    let Some(option) = elements.pop_front() else {
+        return Err(new_missing_fields_error());
+    };
+
+    if !option.eq_ignore_ascii_case(b"FIELDS") {
+        return Err(new_missing_fields_error());
+    }
+
+    let num_fields = elements.pop_front().and_then(|n| parse_i64(&n));
+    let num_fields = match num_fields {
+        Some(num_fields) if num_fields > 0 => num_fields as usize,
+        _ => {
+            return Err(Object::new_error(
+                b"ERR Parameter `numFields` should be greater than 0",
+            ));
+        }
+    };
+
+    if elements.len() != num_fields {
+        return Err(Object::new_error(
+            b"ERR The `numfields` parameter must match the number of arguments",
+        ));
+    }
+
+    Ok(elements.into())
+}
+
+/// Creates the error returned when the `FIELDS` argument is missing.
+fn new_missing_fields_error() -> Object {
+    Object::new_error(b"ERR Mandatory argument FIELDS is missing or not at the right position")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Engine, Object, new_integer_array, run};
+
+    #[test]
+    fn hrandfield_limits_negative_counts() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["HSET", "h", "f", "v"]);
+        let out_of_range = Object::new_error(b"ERR value is out of range");
+        for count in [
+            "-9223372036854775808",
+            "-4611686018427387904",
+            "-4611686018427387903",
+            "-1000001",
+        ] {
+            assert_eq!(run(&mut engine, &["HRANDFIELD", "h", count]), out_of_range);
+            assert_eq!(
+                run(&mut engine, &["HRANDFIELD", "h", count, "WITHVALUES"]),
+                out_of_range
+            );
+        }
+
+        let Object::Array(picks) = run(&mut engine, &["HRANDFIELD", "h", "-3", "WITHVALUES"])
+        else {
+            panic!("expected an array");
+        };
+        let pair = [b"f".to_vec(), b"v".to_vec()].map(|s| Object::BulkString(Some(s)));
+        let pairs: Vec<Object> = (0..3).flat_map(|_| pair.clone()).collect();
+        assert_eq!(picks.items, pairs);
+
+        let Object::Array(picks) = run(&mut engine, &["HRANDFIELD", "h", "9223372036854775807"])
+        else {
+            panic!("expected an array");
+        };
+        assert_eq!(picks.items.len(), 1);
+
+        let Object::Array(picks) = run(&mut engine, &["HRANDFIELD", "h", "-1000000"]) else {
+            panic!("expected an array");
+        };
+        assert_eq!(picks.items.len(), 1_000_000);
+    }
+
+    #[test]
+    fn hrandfield_pairs_values_for_resp3() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["HSET", "h", "f", "v"]);
+        run(&mut engine, &["HELLO", "3"]);
+        let pair = Object::new_array(
+            [b"f".to_vec(), b"v".to_vec()]
+                .map(|s| Object::BulkString(Some(s)))
+                .to_vec(),
+        );
+        assert_eq!(
+            run(&mut engine, &["HRANDFIELD", "h", "-2", "WITHVALUES"]),
+            Object::new_array(vec![pair.clone(), pair])
+        );
+        assert_eq!(
+            run(&mut engine, &["HRANDFIELD", "h", "1"]),
+            Object::new_array(vec![Object::BulkString(Some(b"f".to_vec()))])
+        );
+    }
+
+    fn integers(integers: &[i64]) -> Object {
+        new_integer_array(integers.iter().copied())
+    }
+
+    #[test]
+    fn hexpire_replies_per_field_by_condition() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["HSET", "h", "a", "1", "b", "2", "c", "3"]);
+        let cases: &[(&[&str], &[i64])] = &[
+            (&["100", "XX", "FIELDS", "2", "a", "missing"], &[0, -2]),
+            (&["100", "NX", "FIELDS", "1", "a"], &[1]),
+            (&["200", "NX", "FIELDS", "1", "a"], &[0]),
+            (&["50", "GT", "FIELDS", "1", "a"], &[0]),
+            (&["200", "GT", "FIELDS", "1", "a"], &[1]),
+            (&["300", "LT", "FIELDS", "1", "a"], &[0]),
+            (&["150", "LT", "FIELDS", "1", "a"], &[1]),
+            // A field without a TTL never expires, so is greater than any.
+            (&["100", "GT", "FIELDS", "1", "b"], &[0]),
+            (&["100", "LT", "FIELDS", "1", "b"], &[1]),
+            (&["0", "FIELDS", "1", "c"], &[2]),
+        ];
+        for (args, expected) in cases {
+            let command = [&["HEXPIRE", "h"][..], args].concat();
+            assert_eq!(run(&mut engine, &command), integers(expected), "{args:?}");
+        }
+
+        assert_eq!(
+            run(&mut engine, &["HTTL", "h", "FIELDS", "3", "a", "b", "c"]),
+            integers(&[150, 100, -2])
+        );
+        assert_eq!(
+            run(
+                &mut engine,
+                &["HEXPIRE", "missing", "10", "FIELDS", "1", "a"]
+            ),
+            integers(&[-2])
+        );
+        assert_eq!(
+            run(
+                &mut engine,
+                &["HPEXPIREAT", "h", "1", "FIELDS", "2", "a", "b"]
+            ),
+            integers(&[2, 2])
+        );
+        assert_eq!(run(&mut engine, &["EXISTS", "h"]), Object::Integer(0));
+    }
+
+    #[test]
+    fn hash_is_deleted_when_its_last_field_expires() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["HSET", "lazy", "f", "v"]);
+        run(&mut engine, &["HSET", "active", "f", "v"]);
+        for key in ["lazy", "active"] {
+            run(&mut engine, &["HPEXPIRE", key, "1", "FIELDS", "1", "f"]);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(5));
+
+        assert_eq!(run(&mut engine, &["HLEN", "lazy"]), Object::Integer(0));
+        assert!(!engine.data.contains_key(b"lazy".as_slice()));
+
+        engine.active_expire_cycle();
+        assert!(engine.data.is_empty());
+        assert_eq!(engine.field_expires.len(), 0);
+        assert_eq!(engine.slots.scan(0, 10), (0, Vec::new()));
+    }
+}
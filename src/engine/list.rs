@@ -0,0 +1,786 @@
+//! List commands.
+
+use std::collections::VecDeque;
+
+use super::blocking::{BlockOn, parse_timeout};
+use super::quicklist::QuickList;
+use super::value::Value;
+use super::{
+    Engine, Key, Object, convert_to_ascii_uppercase, new_arity_error, new_bulk_string_array,
+    new_not_integer_error, new_syntax_error, new_wrong_type_error, parse_i64,
+};
+
+/// An end of a list.
+#[derive(Clone, Copy)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+impl ListEnd {
+    /// Parses `LEFT` or `RIGHT`, ignoring case.
+    pub fn parse(end: &[u8]) -> Option<ListEnd> {
+        if end.eq_ignore_ascii_case(b"LEFT") {
+            Some(ListEnd::Left)
+        } else if end.eq_ignore_ascii_case(b"RIGHT") {
+            Some(ListEnd::Right)
+        } else {
+            None
+        }
+    }
+
+    /// Adds a value at this end of a list.
+    pub fn push(self, list: &mut QuickList, value: &[u8]) {
+        match self {
+            ListEnd::Left => list.push_front(value),
+            ListEnd::Right => list.push_back(value),
+        }
+    }
+
+    /// Removes and returns the value at this end of a list.
+    pub fn pop(self, list: &mut QuickList) -> Option<Vec<u8>> {
+        match self {
+            ListEnd::Left => list.pop_front(),
+            ListEnd::Right => list.pop_back(),
+        }
+    }
+}
+
+impl Engine {
+    /// Do a push command, adding elements to one end of a list. When
+    /// `only_if_exists` is set nothing is pushed unless the list exists.
+    pub(super) fn do_push(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        end: ListEnd,
+        only_if_exists: bool,
+    ) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error(name);
+        };
+
+        if elements.is_empty() {
+            return new_arity_error(name);
+        }
+
+        if only_if_exists && self.get_entry(&key).is_none() {
+            return Object::Integer(0);
+        }
+
+        let entry = self.get_entry_or_insert(key.clone(), Value::List(QuickList::new()));
+
+        let Value::List(list) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        while let Some(element) = elements.pop_front() {
+            end.push(list, &element);
+        }
+
+        let len = list.len();
+        self.blocked.signal_key_ready(&key);
+
+        Object::Integer(len as i64)
+    }
+
+    /// Do a pop command, removing elements from one end of a list. Without a
+    /// count a single element is returned, otherwise an array.
+    pub(super) fn do_pop(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        end: ListEnd,
+    ) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error(name);
+        };
+
+        let count = match elements.pop_front() {
+            Some(count) => match parse_i64(&count) {
+                Some(count) if count >= 0 => Some(count as usize),
+                _ => return Object::new_error(b"ERR value is out of range, must be positive"),
+            },
+            None => None,
+        };
+
+        if !elements.is_empty() {
+            return new_arity_error(name);
+        }
+
+        let Some(entry) = self.get_entry_mut(&key) else {
+            return match count {
+                Some(_) => Object::Null,
+                None => Object::BulkString(None),
+            };
+        };
+
+        let Value::List(list) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let reply = match count {
+            None => Object::BulkString(end.pop(list)),
+            Some(count) => {
+                let mut popped = Vec::new();
+                while popped.len() < count {
+                    let Some(item) = end.pop(list) else {
+                        break;
+                    };
+                    popped.push(item);
+                }
+                new_bulk_string_array(popped)
+            }
+        };
+
+        if list.is_empty() {
+            self.remove_entry(&key);
+        }
+
+        reply
+    }
+
+    pub(super) fn do_llen(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), None) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("llen");
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::Integer(0);
+        };
+
+        let Value::List(list) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        Object::Integer(list.len() as i64)
+    }
+
+    pub(super) fn do_lrange(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(start), Some(stop), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("lrange");
+        };
+
+        let (Some(start), Some(stop)) = (parse_i64(&start), parse_i64(&stop)) else {
+            return new_not_integer_error();
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::new_empty_array();
+        };
+
+        let Value::List(list) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let items = list.lrange(start, stop).collect();
+
+        new_bulk_string_array(items)
+    }
+
+    /// Do an index command, returning the element at an index of a list.
+    pub(super) fn do_lindex(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(index), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("lindex");
+        };
+
+        let Some(index) = parse_i64(&index) else {
+            return new_not_integer_error();
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::BulkString(None);
+        };
+
+        let Value::List(list) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let item = resolve_index(index, list.len()).and_then(|index| list.get(index));
+
+        Object::BulkString(item)
+    }
+
+    /// Do a set command on a list, replacing the element at an index.
+    pub(super) fn do_lset(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(index), Some(element), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("lset");
+        };
+
+        let Some(index) = parse_i64(&index) else {
+            return new_not_integer_error();
+        };
+
+        let Some(entry) = self.get_entry_mut(&key) else {
+            return Object::new_error(b"ERR no such key");
+        };
+
+        let Value::List(list) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        match resolve_index(index, list.len()) {
+            Some(index) if list.set(index, &element) => Object::new_simple_string(b"OK"),
+            _ => Object::new_error(b"ERR index out of range"),
+        }
+    }
+
+    /// Do an insert command, adding an element before or after the first
+    /// occurrence of a pivot element.
+    pub(super) fn do_linsert(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(mut position), Some(pivot), Some(element), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("linsert");
+        };
+
+        convert_to_ascii_uppercase(&mut position);
+        let is_after = match position.as_slice() {
+            b"BEFORE" => false,
+            b"AFTER" => true,
+            _ => return new_syntax_error(),
+        };
+
+        let Some(entry) = self.get_entry_mut(&key) else {
+            return Object::Integer(0);
+        };
+
+        let Value::List(list) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let Some(index) = list.iter().position(|item| item == pivot) else {
+            return Object::Integer(-1);
+        };
+
+        let index = if is_after { index + 1 } else { index };
+        list.insert(index, &element);
+
+        Object::Integer(list.len() as i64)
+    }
+
+    /// Do a remove command, removing occurrences of an element. A positive
+    /// count removes from the head, a negative count from the tail, and zero
+    /// removes all occurrences.
+    pub(super) fn do_lrem(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(count), Some(element), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("lrem");
+        };
+
+        let Some(count) = parse_i64(&count) else {
+            return new_not_integer_error();
+        };
+
+        let Some(entry) = self.get_entry_mut(&key) else {
+            return Object::Integer(0);
+        };
+
+        let Value::List(list) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let limit = if count == 0 {
+            usize::MAX
+        } else {
+            count.unsigned_abs() as usize
+        };
+        let removed = list.remove_matching(&element, limit, count < 0);
+
+        if list.is_empty() {
+            self.remove_entry(&key);
+        }
+
+        Object::Integer(removed as i64)
+    }
+
+    /// Do a trim command, keeping only the elements within a range.
+    pub(super) fn do_ltrim(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(start), Some(stop), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("ltrim");
+        };
+
+        let (Some(start), Some(stop)) = (parse_i64(&start), parse_i64(&stop)) else {
+            return new_not_integer_error();
+        };
+
+        let Some(entry) = self.get_entry_mut(&key) else {
+            return Object::new_simple_string(b"OK");
+        };
+
+        let Value::List(list) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        list.trim(start, stop);
+
+        if list.is_empty() {
+            self.remove_entry(&key);
+        }
+
+        Object::new_simple_string(b"OK")
+    }
+
+    /// Do a position command, returning the indexes of matching elements.
+    pub(super) fn do_lpos(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(element)) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("lpos");
+        };
+
+        let mut rank: i64 = 1;
+        let mut count = None;
+        let mut max_len = 0;
+        while let Some(mut option) = elements.pop_front() {
+            convert_to_ascii_uppercase(&mut option);
+            let Some(value) = elements.pop_front() else {
+                return new_syntax_error();
+            };
+            let Some(value) = parse_i64(&value) else {
+                return new_not_integer_error();
+            };
+            match option.as_slice() {
+                b"RANK" => {
+                    if value == 0 {
+                        return Object::new_error(
+                            b"ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the last match",
+                        );
+                    }
+                    rank = value;
+                }
+                b"COUNT" => {
+                    if value < 0 {
+                        return Object::new_error(b"ERR COUNT can't be negative");
+                    }
+                    count = Some(value as usize);
+                }
+                b"MAXLEN" => {
+                    if value < 0 {
+                        return Object::new_error(b"ERR MAXLEN can't be negative");
+                    }
+                    max_len = value as usize;
+                }
+                _ => return new_syntax_error(),
+            }
+        }
+
+        let no_match = match count {
+            Some(_) => Object::new_empty_array(),
+            None => Object::BulkString(None),
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return no_match;
+        };
+
+        let Value::List(list) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        // A count of zero means all matches, and a max length of zero means
+        // the whole list.
+        let wanted = match count {
+            Some(0) => usize::MAX,
+            Some(count) => count,
+            None => 1,
+        };
+        let max_len = if max_len == 0 { list.len() } else { max_len };
+        let skip = rank.unsigned_abs() as usize - 1;
+
+        let len = list.len();
+        let indexes: Box<dyn Iterator<Item = (usize, Vec<u8>)>> = if rank > 0 {
+            Box::new(list.iter().enumerate())
+        } else {
+            Box::new(
+                list.iter()
+                    .rev()
+                    .enumerate()
+                    .map(|(i, item)| (len - 1 - i, item)),
+            )
+        };
+        let matches: Vec<Object> = indexes
+            .take(max_len)
+            .filter(|(_, item)| *item == element)
+            .skip(skip)
+            .take(wanted)
+            .map(|(index, _)| Object::Integer(index as i64))
+            .collect();
+
+        match count {
+            Some(_) => Object::new_array(matches),
+            None => matches.into_iter().next().unwrap_or(no_match),
+        }
+    }
+}
+
+impl Engine {
+    /// Do an `LMOVE` command.
+    pub(super) fn do_lmove(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(source), Some(destination), Some(from), Some(to), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("lmove");
+        };
+
+        let (Some(from), Some(to)) = (ListEnd::parse(&from), ListEnd::parse(&to)) else {
+            return new_syntax_error();
+        };
+
+        match self.move_list(&source, &destination, from, to) {
+            Ok(item) => Object::BulkString(item),
+            Err(e) => e,
+        }
+    }
+
+    /// Do an `RPOPLPUSH` command, which is `LMOVE` from the right to the
+    /// left.
+    pub(super) fn do_rpoplpush(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(source), Some(destination), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("rpoplpush");
+        };
+
+        match self.move_list(&source, &destination, ListEnd::Right, ListEnd::Left) {
+            Ok(item) => Object::BulkString(item),
+            Err(e) => e,
+        }
+    }
+
+    /// Do an `LMPOP` command.
+    pub(super) fn do_lmpop(&mut self, elements: VecDeque<Vec<u8>>) -> Object {
+        let (keys, end, count) = match parse_mpop_args(elements, "lmpop", ListEnd::parse) {
+            Ok(args) => args,
+            Err(e) => return e,
+        };
+
+        match self.mpop_list(&keys, end, count) {
+            Ok(Some(reply)) => reply,
+            Ok(None) => Object::Null,
+            Err(e) => e,
+        }
+    }
+}
+
+impl Engine {
+    /// Do a blocking pop command, popping an element from the first
+    /// non-empty list of the given keys or blocking until one has elements.
+    pub(super) fn do_blocking_pop(
+        &mut self,
+        elements: VecDeque<Vec<u8>>,
+        name: &str,
+        end: ListEnd,
+    ) -> Result<Object, BlockOn> {
+        if elements.len() < 2 {
+            return Ok(new_arity_error(name));
+        }
+
+        let mut keys: Vec<Key> = elements.iter().cloned().collect();
+        let timeout = keys.pop().unwrap_or_default();
+        let timeout = match parse_timeout(&timeout) {
+            Ok(timeout) => timeout,
+            Err(e) => return Ok(e),
+        };
+
+        for key in keys.iter() {
+            match self.pop_list(key, end) {
+                Ok(Some(item)) => return Ok(new_bulk_string_array(vec![key.clone(), item])),
+                Ok(None) => (),
+                Err(e) => return Ok(e),
+            }
+        }
+
+        Err(BlockOn {
+            elements,
+            keys,
+            timeout,
+        })
+    }
+
+    /// Do a blocking move command, moving an element from one list to
+    /// another or blocking until the source list has elements.
+    pub(super) fn do_blmove(&mut self, elements: VecDeque<Vec<u8>>) -> Result<Object, BlockOn> {
+        let mut args = elements.clone();
+        let (Some(source), Some(destination), Some(from), Some(to), Some(timeout), None) = (
+            args.pop_front(),
+            args.pop_front(),
+            args.pop_front(),
+            args.pop_front(),
+            args.pop_front(),
+            args.pop_front(),
+        ) else {
+            return Ok(new_arity_error("blmove"));
+        };
+
+        let (Some(from), Some(to)) = (ListEnd::parse(&from), ListEnd::parse(&to)) else {
+            return Ok(new_syntax_error());
+        };
+
+        let timeout = match parse_timeout(&timeout) {
+            Ok(timeout) => timeout,
+            Err(e) => return Ok(e),
+        };
+
+        match self.move_list(&source, &destination, from, to) {
+            Ok(Some(item)) => Ok(Object::BulkString(Some(item))),
+            Ok(None) => Err(BlockOn {
+                elements,
+                keys: vec![source],
+                timeout,
+            }),
+            Err(e) => Ok(e),
+        }
+    }
+
+    /// Do a `BRPOPLPUSH` command, which is `BLMOVE` from the right to the
+    /// left.
+    pub(super) fn do_brpoplpush(&mut self, elements: VecDeque<Vec<u8>>) -> Result<Object, BlockOn> {
+        let mut args = elements.clone();
+        let (Some(source), Some(destination), Some(timeout), None) = (
+            args.pop_front(),
+            args.pop_front(),
+            args.pop_front(),
+            args.pop_front(),
+        ) else {
+            return Ok(new_arity_error("brpoplpush"));
+        };
+
+        let timeout = match parse_timeout(&timeout) {
+            Ok(timeout) => timeout,
+            Err(e) => return Ok(e),
+        };
+
+        match self.move_list(&source, &destination, ListEnd::Right, ListEnd::Left) {
+            Ok(Some(item)) => Ok(Object::BulkString(Some(item))),
+            Ok(None) => Err(BlockOn {
+                elements,
+                keys: vec![source],
+                timeout,
+            }),
+            Err(e) => Ok(e),
+        }
+    }
+
+    /// Do a blocking multi-pop command, popping elements from the first
+    /// non-empty list of the given keys or blocking until one has elements.
+    pub(super) fn do_blmpop(&mut self, elements: VecDeque<Vec<u8>>) -> Result<Object, BlockOn> {
+        let mut args = elements.clone();
+        let Some(timeout) = args.pop_front() else {
+            return Ok(new_arity_error("blmpop"));
+        };
+
+        let timeout = match parse_timeout(&timeout) {
+            Ok(timeout) => timeout,
+            Err(e) => return Ok(e),
+        };
+
+        let (keys, end, count) = match parse_mpop_args(args, "blmpop", ListEnd::parse) {
+            Ok(args) => args,
+            Err(e) => return Ok(e),
+        };
+
+        match self.mpop_list(&keys, end, count) {
+            Ok(Some(reply)) => Ok(reply),
+            Ok(None) => Err(BlockOn {
+                elements,
+                keys,
+                timeout,
+            }),
+            Err(e) => Ok(e),
+        }
+    }
+
+    /// Pops an element from a list, deleting the list if it becomes empty.
+    /// Returns `None` when there is no list at the key.
+    fn pop_list(&mut self, key: &[u8], end: ListEnd) -> Result<Option<Vec<u8>>, Object> {
+        let Some(entry) = self.get_entry_mut(key) else {
+            return Ok(None);
+        };
+
+        let Value::List(list) = &mut entry.value else {
+            return Err(new_wrong_type_error());
+        };
+
+        let item = end.pop(list);
+
+        if list.is_empty() {
+            self.remove_entry(key);
+        }
+
+        Ok(item)
+    }
+
+    /// Pops up to `count` elements from the first non-empty list of the
+    /// given keys. Returns the key and the popped elements as a reply, or
+    /// `None` when all the lists are empty.
+    fn mpop_list(
+        &mut self,
+        keys: &[Key],
+        end: ListEnd,
+        count: usize,
+    ) -> Result<Option<Object>, Object> {
+        for key in keys.iter() {
+            let Some(entry) = self.get_entry_mut(key) else {
+                continue;
+            };
+
+            let Value::List(list) = &mut entry.value else {
+                return Err(new_wrong_type_error());
+            };
+
+            let mut popped = Vec::new();
+            while popped.len() < count {
+                let Some(item) = end.pop(list) else {
+                    break;
+                };
+                popped.push(item);
+            }
+
+            if list.is_empty() {
+                self.remove_entry(key);
+            }
+
+            let key = Object::BulkString(Some(key.clone()));
+            return Ok(Some(Object::new_array(vec![
+                key,
+                new_bulk_string_array(popped),
+            ])));
+        }
+
+        Ok(None)
+    }
+
+    /// Moves an element from one end of a source list to one end of a
+    /// destination list, which may be the same list. Returns the element, or
+    /// `None` when there is no source list.
+    fn move_list(
+        &mut self,
+        source: &[u8],
+        destination: &[u8],
+        from: ListEnd,
+        to: ListEnd,
+    ) -> Result<Option<Vec<u8>>, Object> {
+        // Check both types before changing anything.
+        match self.get_entry(source) {
+            Some(entry) if !matches!(entry.value, Value::List(_)) => {
+                return Err(new_wrong_type_error());
+            }
+            Some(_) => (),
+            None => return Ok(None),
+        }
+        if let Some(entry) = self.get_entry(destination)
+            && !matches!(entry.value, Value::List(_))
+        {
+            return Err(new_wrong_type_error());
+        }
+
+        let Some(Value::List(list)) = self.get_entry_mut(source).map(|entry| &mut entry.value)
+        else {
+            return Ok(None);
+        };
+        let Some(item) = from.pop(list) else {
+            return Ok(None);
+        };
+
+        // Push before deleting an emptied source so that rotating a single
+        // element list keeps the list and its TTL.
+        let entry = self.get_entry_or_insert(destination.to_vec(), Value::List(QuickList::new()));
+        if let Value::List(list) = &mut entry.value {
+            to.push(list, &item);
+        }
+        self.blocked.signal_key_ready(destination);
+
+        if let Some(Value::List(list)) = self.get_entry(source).map(|entry| &entry.value)
+            && list.is_empty()
+        {
+            self.remove_entry(source);
+        }
+
+        Ok(Some(item))
+    }
+}
+
+/// Parses the arguments of `LMPOP` and `BLMPOP` following the timeout:
+/// `numkeys key [key ...] LEFT|RIGHT [COUNT count]`. The sorted set
+/// commands `ZMPOP` and `BZMPOP` take the same form with their own ends.
+pub fn parse_mpop_args<E>(
+    mut args: VecDeque<Vec<u8>>,
+    name: &str,
+    parse_end: fn(&[u8]) -> Option<E>,
+) -> Result<(Vec<Key>, E, usize), Object> {
+    let Some(num_keys) = args.pop_front() else {
+        return Err(new_arity_error(name));
+    };
+
+    let num_keys = match parse_i64(&num_keys) {
+        Some(num_keys) if num_keys > 0 => num_keys as usize,
+        Some(_) => return Err(Object::new_error(b"ERR numkeys should be greater than 0")),
+        None => return Err(new_not_integer_error()),
+    };
+
+    if args.len() <= num_keys {
+        return Err(new_syntax_error());
+    }
+    let keys: Vec<Key> = args.drain(..num_keys).collect();
+
+    let Some(end) = args.pop_front().and_then(|end| parse_end(&end)) else {
+        return Err(new_syntax_error());
+    };
+
+    let mut count = 1;
+    match (args.pop_front(), args.pop_front(), args.pop_front()) {
+        (None, _, _) => (),
+        (Some(option), Some(value), None) if option.eq_ignore_ascii_case(b"COUNT") => {
+            count = match parse_i64(&value) {
+                Some(count) if count > 0 => count as usize,
+                _ => return Err(Object::new_error(b"ERR count should be greater than 0")),
+            };
+        }
+        _ => return Err(new_syntax_error()),
+    }
+
+    Ok((keys, end, count))
+}
+
+/// Resolves an index, where negative indexes offset from the end, to a
+/// position within a list of the given length.
+fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { index + len as i64 } else { index };
+    if 0 <= index && (index as usize) < len {
+        Some(index as usize)
+    } else {
+        None
+    }
+}
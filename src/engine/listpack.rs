@@ -0,0 +1,105 @@
+//! A sequence of strings packed into a single byte buffer, in the manner of
+//! Redis' listpack.
+//!
+//! Small collections are kept in a listpack to save the per-entry overhead
+//! of a real hash table. Each entry is a varint length followed by the
+//! payload. Finding an entry means walking the buffer, so collections move
+//! to another encoding once they grow past a threshold.
+
+use super::quicklist::encode_varint;
+
+/// A packed sequence of byte strings.
+#[derive(Clone, Debug, Default)]
+pub struct ListPack {
+    bytes: Vec<u8>,
+
+    /// Number of entries in the buffer.
+    len: usize,
+}
+
+impl ListPack {
+    /// Creates a new empty listpack.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Adds an entry to the end.
+    pub fn push_back(&mut self, value: &[u8]) {
+        encode_varint(value.len(), &mut self.bytes);
+        self.bytes.extend_from_slice(value);
+        self.len += 1;
+    }
+
+    /// Returns the entry at an index.
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        self.iter().nth(index)
+    }
+
+    /// Replaces `count` entries starting at an index with the given entries.
+    pub fn splice(&mut self, index: usize, count: usize, values: &[&[u8]]) {
+        let start = self.offset_of(index);
+        let end = self.offset_of(index + count);
+        let mut bytes = Vec::new();
+        for value in values.iter() {
+            encode_varint(value.len(), &mut bytes);
+            bytes.extend_from_slice(value);
+        }
+        self.bytes.splice(start..end, bytes);
+        self.len = self.len + values.len() - count;
+    }
+
+    /// Returns an iterator over the entries.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            bytes: &self.bytes,
+            offset: 0,
+        }
+    }
+
+    /// Returns the offset of the entry at an index, or the end of the buffer
+    /// for an index past the last entry.
+    fn offset_of(&self, index: usize) -> usize {
+        let mut iter = self.iter();
+        for _ in 0..index {
+            if iter.next().is_none() {
+                break;
+            }
+        }
+        iter.offset
+    }
+}
+
+/// An iterator over the entries of a listpack.
+pub struct Iter<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.bytes.len() {
+            return None;
+        }
+        let mut len = 0;
+        let mut shift = 0;
+        loop {
+            let b = self.bytes[self.offset];
+            len |= ((b & 0x7f) as usize) << shift;
+            shift += 7;
+            self.offset += 1;
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        let value = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Some(value)
+    }
+}
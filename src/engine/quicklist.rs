@@ -0,0 +1,730 @@
+//! A list of strings stored as a deque of compact chunks, in the manner of
+//! Redis' quicklist.
+//!
+//! Each chunk packs many entries into a single byte buffer so the memory
+//! used per entry is only the entry's bytes plus a few bytes of length.
+//! Entries are laid out as a forward varint length, the payload, and then
+//! the same varint with its bytes reversed so entries can be read from
+//! either end of a chunk. Chunks are kept at a bounded size, making pushes
+//! and pops at either end of the list constant time.
+
+use std::collections::VecDeque;
+
+use super::normalize_range;
+
+/// The most bytes a chunk grows to before a new chunk is started. An entry
+/// larger than this gets a chunk to itself.
+const CHUNK_MAX_BYTES: usize = 8 * 1024;
+
+/// A list of byte strings.
+#[derive(Clone, Debug, Default)]
+pub struct QuickList {
+    chunks: VecDeque<Chunk>,
+    len: usize,
+}
+
+impl QuickList {
+    /// Create a new empty list.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of entries in the list.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the list has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds an entry to the head of the list.
+    pub fn push_front(&mut self, value: &[u8]) {
+        let size = encoded_len(value.len());
+        match self.chunks.front_mut() {
+            Some(chunk) if chunk.bytes.len() + size <= CHUNK_MAX_BYTES => {
+                chunk.push_front(value);
+            }
+            _ => {
+                let mut chunk = Chunk::new();
+                chunk.push_front(value);
+                self.chunks.push_front(chunk);
+            }
+        }
+        self.len += 1;
+    }
+
+    /// Adds an entry to the tail of the list.
+    pub fn push_back(&mut self, value: &[u8]) {
+        let size = encoded_len(value.len());
+        match self.chunks.back_mut() {
+            Some(chunk) if chunk.bytes.len() + size <= CHUNK_MAX_BYTES => {
+                chunk.push_back(value);
+            }
+            _ => {
+                let mut chunk = Chunk::new();
+                chunk.push_back(value);
+                self.chunks.push_back(chunk);
+            }
+        }
+        self.len += 1;
+    }
+
+    /// Removes and returns the entry at the head of the list.
+    pub fn pop_front(&mut self) -> Option<Vec<u8>> {
+        let chunk = self.chunks.front_mut()?;
+        let value = chunk.pop_front();
+        if chunk.len == 0 {
+            self.chunks.pop_front();
+        }
+        self.len -= 1;
+        Some(value)
+    }
+
+    /// Removes and returns the entry at the tail of the list.
+    pub fn pop_back(&mut self) -> Option<Vec<u8>> {
+        let chunk = self.chunks.back_mut()?;
+        let value = chunk.pop_back();
+        if chunk.len == 0 {
+            self.chunks.pop_back();
+        }
+        self.len -= 1;
+        Some(value)
+    }
+
+    /// Returns the entry at an index.
+    pub fn get(&self, index: usize) -> Option<Vec<u8>> {
+        if index >= self.len {
+            return None;
+        }
+        // Walk from whichever end is closer.
+        if index < self.len / 2 {
+            self.iter_at(index).next()
+        } else {
+            self.iter().nth_back(self.len - 1 - index)
+        }
+    }
+
+    /// Replaces the entry at an index. Returns whether the index was in
+    /// range.
+    pub fn set(&mut self, index: usize, value: &[u8]) -> bool {
+        if index >= self.len {
+            return false;
+        }
+        let (chunk, local) = self.locate(index);
+        let mut entries = self.chunks[chunk].entries();
+        entries[local] = value.to_vec();
+        self.replace_chunk(chunk, entries);
+        true
+    }
+
+    /// Inserts an entry so it ends up at an index. An index equal to the
+    /// length appends the entry.
+    pub fn insert(&mut self, index: usize, value: &[u8]) {
+        if index == 0 {
+            self.push_front(value);
+        } else if index >= self.len {
+            self.push_back(value);
+        } else {
+            let (chunk, local) = self.locate(index);
+            let mut entries = self.chunks[chunk].entries();
+            entries.insert(local, value.to_vec());
+            self.len += 1;
+            self.replace_chunk(chunk, entries);
+        }
+    }
+
+    /// Removes up to `count` entries equal to a value, searching from the
+    /// head or, when `from_back` is set, from the tail. Returns how many
+    /// entries were removed.
+    pub fn remove_matching(&mut self, value: &[u8], count: usize, from_back: bool) -> usize {
+        let mut removed = 0;
+        let mut i = 0;
+        while i < self.chunks.len() && removed < count {
+            let chunk = if from_back {
+                self.chunks.len() - 1 - i
+            } else {
+                i
+            };
+
+            let mut entries = self.chunks[chunk].entries();
+            let before = entries.len();
+            let mut to_remove = count - removed;
+            let mut keep = |entry: &Vec<u8>| {
+                if to_remove > 0 && entry == value {
+                    to_remove -= 1;
+                    false
+                } else {
+                    true
+                }
+            };
+            if from_back {
+                entries.reverse();
+                entries.retain(&mut keep);
+                entries.reverse();
+            } else {
+                entries.retain(&mut keep);
+            }
+
+            let removed_here = before - entries.len();
+            if removed_here == 0 {
+                i += 1;
+                continue;
+            }
+            removed += removed_here;
+            self.len -= removed_here;
+            let is_gone = entries.is_empty();
+            self.replace_chunk(chunk, entries);
+            // A removed chunk shifts the next chunk to visit into this
+            // position.
+            if !is_gone {
+                i += 1;
+            }
+        }
+        removed
+    }
+
+    /// Keeps only the entries between `start` and `stop` inclusive, with
+    /// indexes normalized like `lrange`.
+    pub fn trim(&mut self, start: i64, stop: i64) {
+        let Some((start, stop)) = normalize_range(start, stop, self.len) else {
+            self.chunks.clear();
+            self.len = 0;
+            return;
+        };
+
+        self.truncate_front(start);
+        self.truncate_back(self.len - (stop - start + 1));
+    }
+
+    /// Removes `n` entries from the head, dropping whole chunks when it can.
+    fn truncate_front(&mut self, mut n: usize) {
+        while n > 0 {
+            let Some(chunk) = self.chunks.front() else {
+                return;
+            };
+            if chunk.len <= n {
+                n -= chunk.len;
+                self.len -= chunk.len;
+                self.chunks.pop_front();
+            } else {
+                self.pop_front();
+                n -= 1;
+            }
+        }
+    }
+
+    /// Removes `n` entries from the tail, dropping whole chunks when it can.
+    fn truncate_back(&mut self, mut n: usize) {
+        while n > 0 {
+            let Some(chunk) = self.chunks.back() else {
+                return;
+            };
+            if chunk.len <= n {
+                n -= chunk.len;
+                self.len -= chunk.len;
+                self.chunks.pop_back();
+            } else {
+                self.pop_back();
+                n -= 1;
+            }
+        }
+    }
+
+    /// Finds the chunk holding an index and the index within that chunk.
+    /// The index must be in range.
+    fn locate(&self, mut index: usize) -> (usize, usize) {
+        for (i, chunk) in self.chunks.iter().enumerate() {
+            if index < chunk.len {
+                return (i, index);
+            }
+            index -= chunk.len;
+        }
+        unreachable!("index out of range of quicklist");
+    }
+
+    /// Replaces a chunk with chunks packed from the given entries, removing
+    /// it when there are none. The list's length must already account for
+    /// the entries.
+    fn replace_chunk(&mut self, chunk: usize, entries: Vec<Vec<u8>>) {
+        self.chunks.remove(chunk);
+        let mut packed = Vec::new();
+        let mut current = Chunk::new();
+        for entry in entries.iter() {
+            let size = encoded_len(entry.len());
+            if current.len > 0 && current.bytes.len() + size > CHUNK_MAX_BYTES {
+                packed.push(std::mem::replace(&mut current, Chunk::new()));
+            }
+            current.push_back(entry);
+        }
+        if current.len > 0 {
+            packed.push(current);
+        }
+        for (i, new_chunk) in packed.into_iter().enumerate() {
+            self.chunks.insert(chunk + i, new_chunk);
+        }
+    }
+
+    /// Returns an iterator over all the entries from head to tail.
+    pub fn iter(&self) -> Iter<'_> {
+        self.iter_at(0)
+    }
+
+    /// Returns an iterator over the entries starting at an index.
+    pub fn iter_at(&self, index: usize) -> Iter<'_> {
+        let mut iter = Iter {
+            chunks: &self.chunks,
+            chunk: 0,
+            offset: 0,
+            back_chunk: self.chunks.len().saturating_sub(1),
+            back_offset: self.chunks.back().map_or(0, |chunk| chunk.bytes.len()),
+            remaining: self.len.saturating_sub(index),
+        };
+
+        // Skip whole chunks before walking entries in the chunk holding the
+        // index.
+        let mut skip = index;
+        while skip > 0 && iter.chunk < self.chunks.len() {
+            let chunk = &self.chunks[iter.chunk];
+            if skip < chunk.len {
+                for _ in 0..skip {
+                    let (_, _, next) = chunk.entry_at(iter.offset);
+                    iter.offset = next;
+                }
+                break;
+            }
+            skip -= chunk.len;
+            iter.chunk += 1;
+        }
+
+        iter
+    }
+
+    /// Returns the entries between `start` and `stop` inclusive. Negative
+    /// indexes offset from the end of the list.
+    pub fn lrange(&self, start: i64, stop: i64) -> std::iter::Take<Iter<'_>> {
+        match normalize_range(start, stop, self.len) {
+            Some((start, stop)) => self.iter_at(start).take(stop - start + 1),
+            None => self.iter_at(self.len).take(0),
+        }
+    }
+}
+
+/// An iterator over the entries of a `QuickList`.
+pub struct Iter<'a> {
+    chunks: &'a VecDeque<Chunk>,
+
+    /// Index of the chunk holding the next entry.
+    chunk: usize,
+
+    /// Offset in the chunk's bytes of the next entry.
+    offset: usize,
+
+    /// Index of the chunk holding the next entry from the back.
+    back_chunk: usize,
+
+    /// Offset in the back chunk's bytes just past the next entry from the
+    /// back.
+    back_offset: usize,
+
+    /// How many entries are left to return.
+    remaining: usize,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        while self.offset >= self.chunks[self.chunk].bytes.len() {
+            self.chunk += 1;
+            self.offset = 0;
+        }
+
+        let chunk = &self.chunks[self.chunk];
+        let (start, len, next) = chunk.entry_at(self.offset);
+        self.offset = next;
+        self.remaining -= 1;
+
+        Some(chunk.bytes.range(start..start + len).copied().collect())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        while self.back_offset == 0 {
+            self.back_chunk -= 1;
+            self.back_offset = self.chunks[self.back_chunk].bytes.len();
+        }
+
+        let chunk = &self.chunks[self.back_chunk];
+        let (start, len, previous) = chunk.entry_before(self.back_offset);
+        self.back_offset = previous;
+        self.remaining -= 1;
+
+        Some(chunk.bytes.range(start..start + len).copied().collect())
+    }
+}
+
+/// A run of entries packed into one buffer.
+#[derive(Clone, Debug)]
+struct Chunk {
+    bytes: VecDeque<u8>,
+
+    /// Number of entries in the chunk.
+    len: usize,
+}
+
+impl Chunk {
+    fn new() -> Self {
+        Chunk {
+            bytes: VecDeque::new(),
+            len: 0,
+        }
+    }
+
+    fn push_back(&mut self, value: &[u8]) {
+        let mut header = Vec::new();
+        encode_varint(value.len(), &mut header);
+        self.bytes.extend(header.iter());
+        self.bytes.extend(value.iter());
+        self.bytes.extend(header.iter().rev());
+        self.len += 1;
+    }
+
+    fn push_front(&mut self, value: &[u8]) {
+        let mut header = Vec::new();
+        encode_varint(value.len(), &mut header);
+        // Pushing to the front in reverse leaves the bytes in order.
+        for b in header.iter() {
+            self.bytes.push_front(*b);
+        }
+        for b in value.iter().rev() {
+            self.bytes.push_front(*b);
+        }
+        for b in header.iter().rev() {
+            self.bytes.push_front(*b);
+        }
+        self.len += 1;
+    }
+
+    fn pop_front(&mut self) -> Vec<u8> {
+        let (start, len, next) = self.entry_at(0);
+        let value = self.bytes.range(start..start + len).copied().collect();
+        self.bytes.drain(..next);
+        self.len -= 1;
+        value
+    }
+
+    fn pop_back(&mut self) -> Vec<u8> {
+        let (start, len, previous) = self.entry_before(self.bytes.len());
+        let value = self.bytes.range(start..start + len).copied().collect();
+        self.bytes.truncate(previous);
+        self.len -= 1;
+        value
+    }
+
+    /// Decodes all the entries in the chunk.
+    fn entries(&self) -> Vec<Vec<u8>> {
+        let mut entries = Vec::with_capacity(self.len);
+        let mut offset = 0;
+        while offset < self.bytes.len() {
+            let (start, len, next) = self.entry_at(offset);
+            entries.push(self.bytes.range(start..start + len).copied().collect());
+            offset = next;
+        }
+        entries
+    }
+
+    /// Decodes the entry ending at an offset by reading its trailing length,
+    /// returning the offset of its payload, the payload's length, and the
+    /// offset of the entry itself.
+    fn entry_before(&self, end: usize) -> (usize, usize, usize) {
+        let mut len = 0;
+        let mut shift = 0;
+        let mut i = end;
+        loop {
+            i -= 1;
+            let b = self.bytes[i];
+            len |= ((b & 0x7f) as usize) << shift;
+            shift += 7;
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        let header_len = end - i;
+        let start = i - len;
+        (start, len, start - header_len)
+    }
+
+    /// Decodes the entry at an offset, returning the offset of its payload,
+    /// the payload's length, and the offset of the next entry.
+    fn entry_at(&self, offset: usize) -> (usize, usize, usize) {
+        let mut len = 0;
+        let mut shift = 0;
+        let mut i = offset;
+        loop {
+            let b = self.bytes[i];
+            len |= ((b & 0x7f) as usize) << shift;
+            shift += 7;
+            i += 1;
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        let header_len = i - offset;
+        (i, len, i + len + header_len)
+    }
+}
+
+/// Encodes a length as a little endian base 128 varint.
+pub fn encode_varint(mut n: usize, out: &mut Vec<u8>) {
+    loop {
+        let b = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+/// Returns how many bytes an entry with a payload of the given length takes
+/// in a chunk.
+fn encoded_len(len: usize) -> usize {
+    let mut header_len = 1;
+    let mut n = len >> 7;
+    while n > 0 {
+        header_len += 1;
+        n >>= 7;
+    }
+    len + 2 * header_len
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::super::Rng;
+    use super::{CHUNK_MAX_BYTES, Chunk, QuickList, encode_varint, encoded_len};
+
+    /// Checks the bookkeeping of every chunk and that the list holds the
+    /// same entries as a model.
+    fn check(list: &QuickList, model: &VecDeque<Vec<u8>>) {
+        assert_eq!(list.len(), model.len());
+        assert_eq!(
+            list.chunks.iter().map(|chunk| chunk.len).sum::<usize>(),
+            list.len()
+        );
+        for chunk in list.chunks.iter() {
+            assert!(chunk.len > 0, "empty chunks are dropped");
+            let entries = chunk.entries();
+            assert_eq!(entries.len(), chunk.len);
+            let size: usize = entries.iter().map(|entry| encoded_len(entry.len())).sum();
+            assert_eq!(size, chunk.bytes.len());
+            assert!(chunk.len == 1 || size <= CHUNK_MAX_BYTES);
+        }
+        assert!(list.iter().eq(model.iter().cloned()));
+        assert!(list.iter().rev().eq(model.iter().rev().cloned()));
+    }
+
+    #[test]
+    fn varints_use_seven_bits_a_byte() {
+        let cases: [(usize, &[u8]); 5] = [
+            (0, &[0x00]),
+            (127, &[0x7f]),
+            (128, &[0x80, 0x01]),
+            (16383, &[0xff, 0x7f]),
+            (16384, &[0x80, 0x80, 0x01]),
+        ];
+        for (n, expected) in cases {
+            let mut out = Vec::new();
+            encode_varint(n, &mut out);
+            assert_eq!(out, expected);
+            assert_eq!(encoded_len(n), n + 2 * expected.len());
+        }
+
+        let mut out = Vec::new();
+        encode_varint(usize::MAX, &mut out);
+        assert_eq!(out.len(), usize::BITS.div_ceil(7) as usize);
+    }
+
+    #[test]
+    fn chunk_entries_read_from_either_end() {
+        let values: Vec<Vec<u8>> = [0, 1, 127, 128, 300, 16384]
+            .iter()
+            .map(|&len| (0..len).map(|i| i as u8).collect())
+            .collect();
+
+        let mut chunk = Chunk::new();
+        for value in values.iter() {
+            chunk.push_back(value);
+        }
+        assert_eq!(chunk.entries(), values);
+        for value in values.iter().rev() {
+            assert_eq!(&chunk.pop_back(), value);
+        }
+        assert!(chunk.bytes.is_empty());
+
+        for value in values.iter().rev() {
+            chunk.push_front(value);
+        }
+        assert_eq!(chunk.entries(), values);
+        for value in values.iter() {
+            assert_eq!(&chunk.pop_front(), value);
+        }
+        assert!(chunk.bytes.is_empty());
+    }
+
+    #[test]
+    fn pushes_start_new_chunks_when_full() {
+        let mut list = QuickList::new();
+        let mut model = VecDeque::new();
+        let value = vec![b'x'; 100];
+        for _ in 0..1000 {
+            list.push_back(&value);
+            list.push_front(&value);
+            model.push_back(value.clone());
+            model.push_front(value.clone());
+        }
+        assert!(list.chunks.len() > 2);
+        check(&list, &model);
+
+        // An entry larger than a chunk gets one to itself.
+        let big = vec![b'y'; CHUNK_MAX_BYTES * 2];
+        list.push_back(&big);
+        model.push_back(big);
+        assert_eq!(list.chunks.back().map(|chunk| chunk.len), Some(1));
+        check(&list, &model);
+    }
+
+    #[test]
+    fn inserting_into_a_full_chunk_splits_it() {
+        let mut list = QuickList::new();
+        let mut model = VecDeque::new();
+        // Eight of these fill a chunk exactly.
+        let value = vec![b'x'; CHUNK_MAX_BYTES / 8 - 4];
+        while list.chunks.len() < 2 {
+            list.push_back(&value);
+            model.push_back(value.clone());
+        }
+        let chunks = list.chunks.len();
+        list.insert(1, b"middle");
+        model.insert(1, b"middle".to_vec());
+        assert_eq!(list.chunks.len(), chunks + 1);
+        check(&list, &model);
+
+        // Growing an entry past the chunk size splits its chunk too.
+        let big = vec![b'y'; CHUNK_MAX_BYTES];
+        assert!(list.set(2, &big));
+        model[2] = big;
+        check(&list, &model);
+    }
+
+    #[test]
+    fn removing_drops_emptied_chunks() {
+        let mut list = QuickList::new();
+        let mut model = VecDeque::new();
+        for i in 0..3000 {
+            let value = format!("{:0100}", i % 3).into_bytes();
+            list.push_back(&value);
+            model.push_back(value);
+        }
+        let zero = format!("{:0100}", 0).into_bytes();
+        assert_eq!(list.remove_matching(&zero, usize::MAX, true), 1000);
+        model.retain(|value| *value != zero);
+        check(&list, &model);
+
+        list.trim(100, -100);
+        model = model.range(100..model.len() - 99).cloned().collect();
+        check(&list, &model);
+
+        list.trim(5, 1);
+        assert!(list.chunks.is_empty());
+        check(&list, &VecDeque::new());
+    }
+
+    #[test]
+    fn operations_agree_with_a_deque() {
+        let mut rng = Rng::with_seed(7);
+        let mut list = QuickList::new();
+        let mut model: VecDeque<Vec<u8>> = VecDeque::new();
+        for _ in 0..5_000 {
+            let len = match rng.next_below(10) {
+                0 if rng.next_below(10) == 0 => rng.next_below(3 * CHUNK_MAX_BYTES / 2),
+                _ => rng.next_below(200),
+            };
+            let value = vec![b'a' + rng.next_below(3) as u8; len];
+            match rng.next_below(9) {
+                0 => {
+                    list.push_front(&value);
+                    model.push_front(value);
+                }
+                1 => {
+                    list.push_back(&value);
+                    model.push_back(value);
+                }
+                2 => assert_eq!(list.pop_front(), model.pop_front()),
+                3 => assert_eq!(list.pop_back(), model.pop_back()),
+                4 => {
+                    let index = rng.next_below(model.len() + 1);
+                    list.insert(index, &value);
+                    model.insert(index, value);
+                }
+                5 if !model.is_empty() => {
+                    let index = rng.next_below(model.len());
+                    assert!(list.set(index, &value));
+                    model[index] = value;
+                }
+                6 if !model.is_empty() => {
+                    let index = rng.next_below(model.len());
+                    assert_eq!(list.get(index).as_ref(), model.get(index));
+                    assert!(list.iter_at(index).eq(model.range(index..).cloned()));
+                }
+                7 => {
+                    let count = rng.next_below(3);
+                    let from_back = rng.next_below(2) == 0;
+                    let target = &value;
+                    let mut expected = 0;
+                    let mut kept: Vec<Vec<u8>> = model.iter().cloned().collect();
+                    if from_back {
+                        kept.reverse();
+                    }
+                    kept.retain(|entry| {
+                        if expected < count && entry == target {
+                            expected += 1;
+                            false
+                        } else {
+                            true
+                        }
+                    });
+                    if from_back {
+                        kept.reverse();
+                    }
+                    assert_eq!(list.remove_matching(target, count, from_back), expected);
+                    model = kept.into();
+                }
+                8 if model.len() > 50 => {
+                    list.trim(1, -2);
+                    model.pop_front();
+                    model.pop_back();
+                }
+                _ => (),
+            }
+            check(&list, &model);
+        }
+    }
+}
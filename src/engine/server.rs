@@ -0,0 +1,270 @@
+//! Commands about connections and the server rather than the data, such as
+//! `HELLO` and `CLIENT`.
+
+use std::collections::VecDeque;
+
+use crate::resp::Protocol;
+
+use super::{
+    ClientId, Engine, Object, convert_to_ascii_uppercase, new_arity_error, new_info_reply,
+    new_unknown_subcommand_error, parse_i64,
+};
+
+/// The Redis version whose behavior the server follows, as reported by
+/// `HELLO`.
+const SERVER_VERSION: &str = "7.4.0";
+
+/// The only user there is. No password is configured for it, so there's
+/// nothing to authenticate against.
+const DEFAULT_USER: &[u8] = b"default";
+
+/// What the engine knows about a connected client.
+#[derive(Default)]
+pub struct Client {
+    /// The protocol replies are sent in.
+    pub protocol: Protocol,
+
+    /// The name given by `CLIENT SETNAME` or `HELLO`, if any.
+    name: Option<Vec<u8>>,
+}
+
+impl Engine {
+    /// Do a `HELLO` command, which may switch the client's protocol, and
+    /// replies with facts about the server.
+    pub(super) fn do_hello(&mut self, client: ClientId, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let protocol = match elements.pop_front() {
+            Some(version) => match parse_i64(&version) {
+                Some(2) => Some(Protocol::Resp2),
+                Some(3) => Some(Protocol::Resp3),
+                Some(_) => {
+                    return Object::new_error(b"NOPROTO unsupported protocol version");
+                }
+                None => {
+                    return Object::new_error(
+                        b"ERR Protocol version is not an integer or out of range",
+                    );
+                }
+            },
+            None => None,
+        };
+
+        let mut username = None;
+        let mut name = None;
+        while let Some(option) = elements.pop_front() {
+            let mut upper = option.clone();
+            convert_to_ascii_uppercase(&mut upper);
+            match upper.as_slice() {
+                b"AUTH" if elements.len() >= 2 => {
+                    username = elements.pop_front();
+                    elements.pop_front();
+                }
+                b"SETNAME" if !elements.is_empty() => {
+                    name = elements.pop_front();
+                }
+                _ => {
+                    let message = format!(
+                        "ERR Syntax error in HELLO option '{}'",
+                        String::from_utf8_lossy(&option),
+                    );
+                    return Object::new_error(message.as_bytes());
+                }
+            }
+        }
+
+        // Authentication always fails as no password is configured, rather
+        // than letting any credentials through.
+        match username {
+            Some(username) if username == DEFAULT_USER => {
+                return Object::new_error(
+                    b"ERR AUTH <password> called without any password configured for the \
+                      default user. Are you sure your configuration is correct?",
+                );
+            }
+            Some(_) => return new_wrong_pass_error(),
+            None => (),
+        }
+
+        if let Some(name) = name
+            && let Err(e) = self.set_client_name(client, name)
+        {
+            return e;
+        }
+
+        let state = self.clients.entry(client).or_default();
+        if let Some(protocol) = protocol {
+            state.protocol = protocol;
+        }
+        let version = match state.protocol {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        };
+
+        new_info_reply(vec![
+            ("server", Object::BulkString(Some(b"redis".to_vec()))),
+            (
+                "version",
+                Object::BulkString(Some(SERVER_VERSION.as_bytes().to_vec())),
+            ),
+            ("proto", Object::Integer(version)),
+            ("id", Object::Integer(client as i64)),
+            ("mode", Object::BulkString(Some(b"standalone".to_vec()))),
+            ("role", Object::BulkString(Some(b"master".to_vec()))),
+            ("modules", Object::new_empty_array()),
+        ])
+    }
+
+    /// Do a `CLIENT` command by running its subcommand.
+    pub(super) fn do_client(
+        &mut self,
+        client: ClientId,
+        mut elements: VecDeque<Vec<u8>>,
+    ) -> Object {
+        let Some(mut subcommand) = elements.pop_front() else {
+            return new_arity_error("client");
+        };
+
+        convert_to_ascii_uppercase(&mut subcommand);
+        match subcommand.as_slice() {
+            b"ID" => match elements.pop_front() {
+                None => Object::Integer(client as i64),
+                Some(_) => new_arity_error("client|id"),
+            },
+            b"GETNAME" => match elements.pop_front() {
+                None => {
+                    let name = self
+                        .clients
+                        .get(&client)
+                        .and_then(|state| state.name.clone());
+                    Object::BulkString(name)
+                }
+                Some(_) => new_arity_error("client|getname"),
+            },
+            b"SETNAME" => {
+                let (Some(name), None) = (elements.pop_front(), elements.pop_front()) else {
+                    return new_arity_error("client|setname");
+                };
+                match self.set_client_name(client, name) {
+                    Ok(()) => Object::new_simple_string(b"OK"),
+                    Err(e) => e,
+                }
+            }
+            _ => new_unknown_subcommand_error(&subcommand, "CLIENT"),
+        }
+    }
+
+    /// Do a `DEBUG` command. Only `DEBUG PROTOCOL` is supported, which
+    /// replies with an example of a type so clients can test their parsing.
+    pub(super) fn do_debug(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(mut subcommand) = elements.pop_front() else {
+            return new_arity_error("debug");
+        };
+
+        convert_to_ascii_uppercase(&mut subcommand);
+        if subcommand != b"PROTOCOL" {
+            return new_unknown_subcommand_error(&subcommand, "DEBUG");
+        }
+
+        let (Some(mut name), None) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("debug");
+        };
+
+        name.make_ascii_lowercase();
+        let integers = || (0..3).map(Object::Integer).collect::<Vec<_>>();
+        match name.as_slice() {
+            b"string" => Object::BulkString(Some(b"Hello World".to_vec())),
+            b"integer" => Object::Integer(12345),
+            b"double" => Object::Double(1.5),
+            b"bignum" => Object::BigNumber(b"1234567999999999999999999999999999999".to_vec()),
+            b"null" => Object::Null,
+            b"array" => Object::new_array(integers()),
+            b"set" => Object::Set(integers()),
+            b"map" => Object::Map(
+                integers()
+                    .into_iter()
+                    .zip([false, true, false].map(Object::Boolean))
+                    .collect(),
+            ),
+            b"push" => Object::Push(vec![
+                Object::BulkString(Some(b"server-cpu-usage".to_vec())),
+                Object::Integer(42),
+            ]),
+            b"verbatim" => Object::VerbatimString(*b"txt", b"This is a verbatim\nstring".to_vec()),
+            b"true" => Object::Boolean(true),
+            b"false" => Object::Boolean(false),
+            _ => Object::new_error(
+                b"ERR Wrong protocol type name. Please use one of the following: \
+                  string|integer|double|bignum|null|array|set|map|push|verbatim|true|false",
+            ),
+        }
+    }
+
+    /// Names a client, or clears its name when the name is empty.
+    fn set_client_name(&mut self, client: ClientId, name: Vec<u8>) -> Result<(), Object> {
+        // Names are listed space separated, so only printable characters
+        // other than space are allowed.
+        if !name.iter().all(|b| b.is_ascii_graphic()) {
+            return Err(Object::new_error(
+                b"ERR Client names cannot contain spaces, newlines or special characters.",
+            ));
+        }
+        let state = self.clients.entry(client).or_default();
+        state.name = if name.is_empty() { None } else { Some(name) };
+        Ok(())
+    }
+}
+
+/// Creates the error returned when a user can't authenticate.
+fn new_wrong_pass_error() -> Object {
+    Object::new_error(b"WRONGPASS invalid username-password pair or user is disabled.")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::resp::Protocol;
+
+    use super::super::{Engine, Object, run};
+
+    #[test]
+    fn hello_rejects_credentials() {
+        let mut engine = Engine::new();
+        let Object::Error(message) = run(&mut engine, &["HELLO", "3", "AUTH", "default", "x"])
+        else {
+            panic!("expected an error");
+        };
+        assert!(message.starts_with(b"ERR AUTH <password> called without any password"));
+        assert_eq!(
+            run(&mut engine, &["HELLO", "3", "AUTH", "bob", "x"]),
+            Object::new_error(b"WRONGPASS invalid username-password pair or user is disabled.")
+        );
+        assert_eq!(engine.protocol(1), Protocol::Resp2);
+    }
+
+    #[test]
+    fn hello_switches_protocol_and_names_the_client() {
+        let mut engine = Engine::new();
+        assert_eq!(
+            run(&mut engine, &["HELLO", "4"]),
+            Object::new_error(b"NOPROTO unsupported protocol version")
+        );
+        let Object::Map(fields) = run(&mut engine, &["HELLO", "3", "SETNAME", "me"]) else {
+            panic!("expected a map");
+        };
+        assert!(fields.contains(&(
+            Object::BulkString(Some(b"proto".to_vec())),
+            Object::Integer(3)
+        )));
+        assert_eq!(engine.protocol(1), Protocol::Resp3);
+        assert_eq!(
+            run(&mut engine, &["CLIENT", "GETNAME"]),
+            Object::BulkString(Some(b"me".to_vec()))
+        );
+
+        run(&mut engine, &["HELLO", "2"]);
+        assert_eq!(engine.protocol(1), Protocol::Resp2);
+        engine.remove_client(1);
+        assert_eq!(
+            run(&mut engine, &["CLIENT", "GETNAME"]),
+            Object::BulkString(None)
+        );
+    }
+}
@@ -0,0 +1,712 @@
+//! The set type and its commands.
+
+use std::collections::{HashMap, VecDeque};
+use std::slice;
+
+use super::value::Value;
+use super::{
+    Engine, EntryBuilder, Key, Object, convert_to_ascii_uppercase, new_arity_error,
+    new_bulk_string_array, new_integer_array, new_not_integer_error, new_syntax_error,
+    new_wrong_type_error, parse_i64, parse_random_count,
+};
+
+/// The most members a set keeps as an intset before moving to a table.
+const INTSET_MAX_MEMBERS: usize = 512;
+
+/// An unordered set of strings.
+#[derive(Clone, Debug)]
+pub struct Set {
+    encoding: Encoding,
+}
+
+/// How a set's members are stored.
+#[derive(Clone, Debug)]
+enum Encoding {
+    /// Sorted integers, for small sets whose members are all integers.
+    IntSet(Vec<i64>),
+
+    /// Members in a vector along with each member's position in it, so
+    /// members can be picked at random and removed in constant time.
+    Table {
+        members: Vec<Vec<u8>>,
+        positions: HashMap<Vec<u8>, usize>,
+    },
+}
+
+impl Set {
+    /// Creates a new empty set.
+    pub fn new() -> Self {
+        Set {
+            encoding: Encoding::IntSet(Vec::new()),
+        }
+    }
+
+    /// Returns the number of members.
+    pub fn len(&self) -> usize {
+        match &self.encoding {
+            Encoding::IntSet(integers) => integers.len(),
+            Encoding::Table { members, .. } => members.len(),
+        }
+    }
+
+    /// Returns whether the set has no members.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns whether a member is in the set.
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match &self.encoding {
+            Encoding::IntSet(integers) => match parse_i64(member) {
+                Some(n) => integers.binary_search(&n).is_ok(),
+                None => false,
+            },
+            Encoding::Table { positions, .. } => positions.contains_key(member),
+        }
+    }
+
+    /// Adds a member. Returns whether it's new.
+    pub fn insert(&mut self, member: &[u8]) -> bool {
+        if let Encoding::IntSet(integers) = &mut self.encoding
+            && let Some(n) = parse_i64(member)
+        {
+            match integers.binary_search(&n) {
+                Ok(_) => return false,
+                Err(index) if integers.len() < INTSET_MAX_MEMBERS => {
+                    integers.insert(index, n);
+                    return true;
+                }
+                Err(_) => (),
+            }
+        }
+
+        self.convert_to_table();
+        let Encoding::Table { members, positions } = &mut self.encoding else {
+            unreachable!("set was converted to a table");
+        };
+        if positions.contains_key(member) {
+            return false;
+        }
+        positions.insert(member.to_vec(), members.len());
+        members.push(member.to_vec());
+        true
+    }
+
+    /// Removes a member. Returns whether it was in the set.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match &mut self.encoding {
+            Encoding::IntSet(integers) => {
+                let Some(n) = parse_i64(member) else {
+                    return false;
+                };
+                match integers.binary_search(&n) {
+                    Ok(index) => {
+                        integers.remove(index);
+                        true
+                    }
+                    Err(_) => false,
+                }
+            }
+            Encoding::Table { members, positions } => {
+                let Some(position) = positions.remove(member) else {
+                    return false;
+                };
+                members.swap_remove(position);
+                if let Some(moved) = members.get(position) {
+                    positions.insert(moved.clone(), position);
+                }
+                true
+            }
+        }
+    }
+
+    /// Returns the member at an index, where members are in no particular
+    /// order. Used to pick members at random.
+    pub fn get(&self, index: usize) -> Option<Vec<u8>> {
+        match &self.encoding {
+            Encoding::IntSet(integers) => integers.get(index).map(|n| n.to_string().into_bytes()),
+            Encoding::Table { members, .. } => members.get(index).cloned(),
+        }
+    }
+
+    /// Returns an iterator over the members.
+    pub fn iter(&self) -> Iter<'_> {
+        match &self.encoding {
+            Encoding::IntSet(integers) => Iter::IntSet(integers.iter()),
+            Encoding::Table { members, .. } => Iter::Table(members.iter()),
+        }
+    }
+
+    /// Moves the members from an intset into a table. Sets never move back
+    /// to an intset.
+    fn convert_to_table(&mut self) {
+        let Encoding::IntSet(integers) = &self.encoding else {
+            return;
+        };
+        let members: Vec<Vec<u8>> = integers
+            .iter()
+            .map(|n| n.to_string().into_bytes())
+            .collect();
+        let positions = members
+            .iter()
+            .enumerate()
+            .map(|(position, member)| (member.clone(), position))
+            .collect();
+        self.encoding = Encoding::Table { members, positions };
+    }
+}
+
+/// An iterator over the members of a set.
+pub enum Iter<'a> {
+    IntSet(slice::Iter<'a, i64>),
+    Table(slice::Iter<'a, Vec<u8>>),
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Iter::IntSet(iter) => iter.next().map(|n| n.to_string().into_bytes()),
+            Iter::Table(iter) => iter.next().cloned(),
+        }
+    }
+}
+
+/// How a set command combines sets.
+#[derive(Clone, Copy)]
+pub enum SetOperation {
+    Inter,
+    Union,
+    Diff,
+}
+
+impl Engine {
+    /// Do an `SADD` command.
+    pub(super) fn do_sadd(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error("sadd");
+        };
+
+        if elements.is_empty() {
+            return new_arity_error("sadd");
+        }
+
+        let entry = self.get_entry_or_insert(key, Value::Set(Set::new()));
+
+        let Value::Set(set) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let added = elements.iter().filter(|member| set.insert(member)).count();
+
+        Object::Integer(added as i64)
+    }
+
+    /// Do an `SREM` command.
+    pub(super) fn do_srem(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error("srem");
+        };
+
+        if elements.is_empty() {
+            return new_arity_error("srem");
+        }
+
+        let Some(entry) = self.get_entry_mut(&key) else {
+            return Object::Integer(0);
+        };
+
+        let Value::Set(set) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let removed = elements.iter().filter(|member| set.remove(member)).count();
+
+        if set.is_empty() {
+            self.remove_entry(&key);
+        }
+
+        Object::Integer(removed as i64)
+    }
+
+    /// Do an `SMEMBERS` command.
+    pub(super) fn do_smembers(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), None) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("smembers");
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return new_set_reply(Vec::new());
+        };
+
+        let Value::Set(set) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        new_set_reply(set.iter().collect())
+    }
+
+    /// Do an `SISMEMBER` command.
+    pub(super) fn do_sismember(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(member), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("sismember");
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::Integer(0);
+        };
+
+        let Value::Set(set) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        Object::Integer(set.contains(&member) as i64)
+    }
+
+    /// Do an `SMISMEMBER` command.
+    pub(super) fn do_smismember(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error("smismember");
+        };
+
+        if elements.is_empty() {
+            return new_arity_error("smismember");
+        }
+
+        let set = match self.get_entry(&key).map(|entry| &entry.value) {
+            Some(Value::Set(set)) => Some(set),
+            Some(_) => return new_wrong_type_error(),
+            None => None,
+        };
+
+        new_integer_array(
+            elements
+                .iter()
+                .map(|member| set.is_some_and(|set| set.contains(member)) as i64),
+        )
+    }
+
+    /// Do an `SCARD` command.
+    pub(super) fn do_scard(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), None) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("scard");
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::Integer(0);
+        };
+
+        let Value::Set(set) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        Object::Integer(set.len() as i64)
+    }
+
+    /// Do an `SPOP` command, removing random members.
+    pub(super) fn do_spop(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error("spop");
+        };
+
+        let count = match elements.pop_front() {
+            Some(count) => match parse_i64(&count) {
+                Some(count) if count >= 0 => Some(count),
+                Some(_) => {
+                    return Object::new_error(b"ERR value is out of range, must be positive");
+                }
+                None => return new_not_integer_error(),
+            },
+            None => None,
+        };
+
+        if !elements.is_empty() {
+            return new_arity_error("spop");
+        }
+
+        match self.get_entry(&key).map(|entry| &entry.value) {
+            Some(Value::Set(_)) => (),
+            Some(_) => return new_wrong_type_error(),
+            None if count.is_some() => return new_set_reply(Vec::new()),
+            None => return Object::BulkString(None),
+        }
+
+        // Borrow the set apart from the random number generator.
+        let Some(Value::Set(set)) = self.data.get_mut(&key).map(|entry| &mut entry.value) else {
+            return Object::BulkString(None);
+        };
+
+        let mut popped = Vec::new();
+        for _ in 0..count.unwrap_or(1) {
+            if set.is_empty() {
+                break;
+            }
+            let index = self.rng.next_below(set.len());
+            if let Some(member) = set.get(index) {
+                set.remove(&member);
+                popped.push(member);
+            }
+        }
+
+        if set.is_empty() {
+            self.remove_entry(&key);
+        }
+
+        match count {
+            Some(_) => new_set_reply(popped),
+            None => Object::BulkString(popped.pop()),
+        }
+    }
+
+    /// Do an `SRANDMEMBER` command.
+    pub(super) fn do_srandmember(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error("srandmember");
+        };
+
+        let count = match elements.pop_front().map(|count| parse_random_count(&count)) {
+            Some(Ok(count)) => Some(count),
+            Some(Err(e)) => return e,
+            None => None,
+        };
+
+        if !elements.is_empty() {
+            return new_arity_error("srandmember");
+        }
+
+        match self.get_entry(&key).map(|entry| &entry.value) {
+            Some(Value::Set(_)) => (),
+            Some(_) => return new_wrong_type_error(),
+            None if count.is_some() => return Object::new_empty_array(),
+            None => return Object::BulkString(None),
+        }
+
+        // Borrow the set apart from the random number generator.
+        let Some(Value::Set(set)) = self.data.get(&key).map(|entry| &entry.value) else {
+            return Object::BulkString(None);
+        };
+
+        let Some(count) = count else {
+            return Object::BulkString(set.get(self.rng.next_below(set.len())));
+        };
+
+        let members = self
+            .rng
+            .sample(set.len(), count)
+            .filter_map(|index| set.get(index))
+            .collect();
+
+        new_bulk_string_array(members)
+    }
+
+    /// Do an `SMOVE` command.
+    pub(super) fn do_smove(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(source), Some(destination), Some(member), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("smove");
+        };
+
+        // Check both types before changing anything.
+        let is_member = match self.get_entry(&source).map(|entry| &entry.value) {
+            Some(Value::Set(set)) => set.contains(&member),
+            Some(_) => return new_wrong_type_error(),
+            None => false,
+        };
+        if let Some(entry) = self.get_entry(&destination)
+            && !matches!(entry.value, Value::Set(_))
+        {
+            return new_wrong_type_error();
+        }
+
+        if !is_member {
+            return Object::Integer(0);
+        }
+
+        if source == destination {
+            return Object::Integer(1);
+        }
+
+        if let Some(Value::Set(set)) = self.get_entry_mut(&source).map(|entry| &mut entry.value) {
+            set.remove(&member);
+            if set.is_empty() {
+                self.remove_entry(&source);
+            }
+        }
+
+        let entry = self.get_entry_or_insert(destination, Value::Set(Set::new()));
+        if let Value::Set(set) = &mut entry.value {
+            set.insert(&member);
+        }
+
+        Object::Integer(1)
+    }
+
+    /// Do an `SINTER`, `SUNION` or `SDIFF` command.
+    pub(super) fn do_set_operation(
+        &mut self,
+        elements: VecDeque<Vec<u8>>,
+        name: &str,
+        operation: SetOperation,
+    ) -> Object {
+        if elements.is_empty() {
+            return new_arity_error(name);
+        }
+
+        let keys: Vec<Key> = elements.into();
+        match self.combine_sets(&keys, operation, 0) {
+            Ok(set) => new_set_reply(set.iter().collect()),
+            Err(e) => e,
+        }
+    }
+
+    /// Do an `SINTERSTORE`, `SUNIONSTORE` or `SDIFFSTORE` command, which
+    /// replaces the destination with the result.
+    pub(super) fn do_set_operation_store(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        operation: SetOperation,
+    ) -> Object {
+        let Some(destination) = elements.pop_front() else {
+            return new_arity_error(name);
+        };
+
+        if elements.is_empty() {
+            return new_arity_error(name);
+        }
+
+        let keys: Vec<Key> = elements.into();
+        let set = match self.combine_sets(&keys, operation, 0) {
+            Ok(set) => set,
+            Err(e) => return e,
+        };
+
+        let len = set.len();
+        if set.is_empty() {
+            self.remove_entry(&destination);
+        } else {
+            let entry = EntryBuilder::new(Value::Set(set)).build();
+            self.insert_entry(destination, entry);
+        }
+
+        Object::Integer(len as i64)
+    }
+
+    /// Do an `SINTERCARD` command.
+    pub(super) fn do_sintercard(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(num_keys) = elements.pop_front() else {
+            return new_arity_error("sintercard");
+        };
+
+        let num_keys = match parse_i64(&num_keys) {
+            Some(num_keys) if num_keys > 0 => num_keys as usize,
+            Some(_) => return Object::new_error(b"ERR numkeys should be greater than 0"),
+            None => return new_not_integer_error(),
+        };
+
+        if elements.len() < num_keys {
+            return Object::new_error(b"ERR Number of keys can't be greater than number of args");
+        }
+        let keys: Vec<Key> = elements.drain(..num_keys).collect();
+
+        let mut limit = 0;
+        match (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) {
+            (None, _, _) => (),
+            (Some(mut option), Some(value), None) => {
+                convert_to_ascii_uppercase(&mut option);
+                if option != b"LIMIT" {
+                    return new_syntax_error();
+                }
+                limit = match parse_i64(&value) {
+                    Some(limit) if limit >= 0 => limit as usize,
+                    Some(_) => return Object::new_error(b"ERR LIMIT can't be negative"),
+                    None => return new_not_integer_error(),
+                };
+            }
+            _ => return new_syntax_error(),
+        }
+
+        match self.combine_sets(&keys, SetOperation::Inter, limit) {
+            Ok(set) => Object::Integer(set.len() as i64),
+            Err(e) => e,
+        }
+    }
+
+    /// Combines the sets at the given keys, where missing keys are empty
+    /// sets. An intersection stops once it has `limit` members, unless the
+    /// limit is zero.
+    fn combine_sets(
+        &mut self,
+        keys: &[Key],
+        operation: SetOperation,
+        limit: usize,
+    ) -> Result<Set, Object> {
+        // Expire and type check every key before borrowing the sets.
+        for key in keys.iter() {
+            if let Some(entry) = self.get_entry(key)
+                && !matches!(entry.value, Value::Set(_))
+            {
+                return Err(new_wrong_type_error());
+            }
+        }
+        let sets: Vec<Option<&Set>> = keys
+            .iter()
+            .map(|key| match self.data.get(key).map(|entry| &entry.value) {
+                Some(Value::Set(set)) => Some(set),
+                _ => None,
+            })
+            .collect();
+
+        let mut result = Set::new();
+        match operation {
+            SetOperation::Inter => {
+                // Any empty set makes the intersection empty.
+                let Some(mut sets) = sets.into_iter().collect::<Option<Vec<&Set>>>() else {
+                    return Ok(result);
+                };
+                // Walk the smallest set, checking the others from smallest
+                // up so misses are found early.
+                sets.sort_by_key(|set| set.len());
+                let (smallest, others) = sets.split_first().expect("keys aren't empty");
+                for member in smallest.iter() {
+                    if others.iter().all(|set| set.contains(&member)) {
+                        result.insert(&member);
+                        if result.len() == limit {
+                            break;
+                        }
+                    }
+                }
+            }
+            SetOperation::Union => {
+                for set in sets.into_iter().flatten() {
+                    for member in set.iter() {
+                        result.insert(&member);
+                    }
+                }
+            }
+            SetOperation::Diff => {
+                let Some((Some(first), others)) = sets.split_first() else {
+                    return Ok(result);
+                };
+                for member in first.iter() {
+                    if !others.iter().flatten().any(|set| set.contains(&member)) {
+                        result.insert(&member);
+                    }
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// Creates a set of bulk strings.
+fn new_set_reply(members: Vec<Vec<u8>>) -> Object {
+    let members = members
+        .into_iter()
+        .map(|member| Object::BulkString(Some(member)))
+        .collect();
+    Object::Set(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Engine, Object, run};
+
+    #[test]
+    fn srandmember_limits_negative_counts() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["SADD", "s", "m"]);
+        for count in ["-9223372036854775808", "-4611686018427387903", "-1000001"] {
+            assert_eq!(
+                run(&mut engine, &["SRANDMEMBER", "s", count]),
+                Object::new_error(b"ERR value is out of range")
+            );
+        }
+        let Object::Array(picks) = run(&mut engine, &["SRANDMEMBER", "s", "-1000000"]) else {
+            panic!("expected an array");
+        };
+        assert_eq!(picks.items.len(), 1_000_000);
+
+        let member = Object::BulkString(Some(b"m".to_vec()));
+        assert_eq!(
+            run(&mut engine, &["SRANDMEMBER", "s", "-3"]),
+            Object::new_array(vec![member.clone(), member.clone(), member.clone()])
+        );
+        assert_eq!(
+            run(&mut engine, &["SRANDMEMBER", "s", "9223372036854775807"]),
+            Object::new_array(vec![member])
+        );
+    }
+
+    #[test]
+    fn sintercard_stops_at_the_limit() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["SADD", "a", "1", "2", "3", "4", "5"]);
+        run(&mut engine, &["SADD", "b", "2", "3", "4", "6"]);
+        let cases = [("0", 3), ("2", 2), ("3", 3), ("10", 3)];
+        for (limit, expected) in cases {
+            assert_eq!(
+                run(&mut engine, &["SINTERCARD", "2", "a", "b", "LIMIT", limit]),
+                Object::Integer(expected),
+                "LIMIT {limit}"
+            );
+        }
+        assert_eq!(
+            run(&mut engine, &["SINTERCARD", "2", "a", "missing"]),
+            Object::Integer(0)
+        );
+        assert_eq!(
+            run(&mut engine, &["SINTERCARD", "2", "a", "b", "LIMIT", "-1"]),
+            Object::new_error(b"ERR LIMIT can't be negative")
+        );
+        assert_eq!(
+            run(&mut engine, &["SINTERCARD", "3", "a", "b"]),
+            Object::new_error(b"ERR Number of keys can't be greater than number of args")
+        );
+    }
+
+    #[test]
+    fn empty_results_delete_the_destination() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["SADD", "a", "1", "2"]);
+        run(&mut engine, &["SADD", "b", "1", "2"]);
+        run(&mut engine, &["SADD", "c", "3"]);
+        for command in [
+            ["SDIFFSTORE", "dest", "a", "b"],
+            ["SINTERSTORE", "dest", "a", "c"],
+        ] {
+            run(&mut engine, &["SET", "dest", "old"]);
+            assert_eq!(run(&mut engine, &command), Object::Integer(0));
+            assert_eq!(run(&mut engine, &["EXISTS", "dest"]), Object::Integer(0));
+        }
+
+        assert_eq!(
+            run(&mut engine, &["SUNIONSTORE", "dest", "a", "c"]),
+            Object::Integer(3)
+        );
+        assert_eq!(run(&mut engine, &["SCARD", "dest"]), Object::Integer(3));
+        // The destination may also be a source.
+        assert_eq!(
+            run(&mut engine, &["SDIFFSTORE", "dest", "dest", "a"]),
+            Object::Integer(1)
+        );
+        assert_eq!(
+            run(&mut engine, &["SMEMBERS", "dest"]),
+            Object::Set(vec![Object::BulkString(Some(b"3".to_vec()))])
+        );
+    }
+}
@@ -0,0 +1,445 @@
+//! A skiplist of members ordered by score, in the manner of Redis'
+//! `zskiplist`.
+//!
+//! Each link records how many nodes it skips over, its span, so the rank of
+//! a node is the sum of the spans followed to reach it. That makes finding a
+//! node by rank, or the rank of a node, logarithmic like other searches.
+//! Nodes live in an arena and refer to each other by index.
+
+use std::cmp::Ordering;
+
+use super::Rng;
+
+/// The most levels a node can have.
+const MAX_LEVEL: usize = 32;
+
+/// The index of the head node, which holds no member.
+const HEAD: usize = 0;
+
+/// A list of members ordered by score, then by member for equal scores.
+#[derive(Clone, Debug)]
+pub struct SkipList {
+    /// All nodes, including the head and freed nodes awaiting reuse.
+    nodes: Vec<Node>,
+
+    /// Indexes of freed nodes.
+    free: Vec<usize>,
+
+    /// The number of levels in use.
+    level: usize,
+
+    /// The number of members.
+    len: usize,
+
+    /// The last node, if any.
+    tail: Option<usize>,
+
+    /// Picks the level of new nodes.
+    rng: Rng,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    member: Vec<u8>,
+    score: f64,
+    backward: Option<usize>,
+    levels: Vec<Link>,
+}
+
+/// A link to the next node at some level.
+#[derive(Clone, Copy, Debug)]
+struct Link {
+    forward: Option<usize>,
+
+    /// The number of nodes moved past by following the link.
+    span: usize,
+}
+
+impl SkipList {
+    /// Creates an empty skiplist.
+    pub fn new() -> Self {
+        let head = Node {
+            member: Vec::new(),
+            score: 0.0,
+            backward: None,
+            levels: vec![
+                Link {
+                    forward: None,
+                    span: 0,
+                };
+                MAX_LEVEL
+            ],
+        };
+        SkipList {
+            nodes: vec![head],
+            free: Vec::new(),
+            level: 1,
+            len: 0,
+            tail: None,
+            rng: Rng::new(),
+        }
+    }
+
+    /// Inserts a member, which must not already be in the list.
+    pub fn insert(&mut self, score: f64, member: &[u8]) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+            while let Some(next) = self.nodes[x].levels[i].forward
+                && self.compare(next, score, member) == Ordering::Less
+            {
+                rank[i] += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let level = self.random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.nodes[HEAD].levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+
+        let node = Node {
+            member: member.to_vec(),
+            score,
+            backward: if update[0] == HEAD {
+                None
+            } else {
+                Some(update[0])
+            },
+            levels: vec![
+                Link {
+                    forward: None,
+                    span: 0,
+                };
+                level
+            ],
+        };
+        let x = self.allocate(node);
+
+        for i in 0..level {
+            let previous = self.nodes[update[i]].levels[i];
+            let skipped = rank[0] - rank[i];
+            self.nodes[x].levels[i] = Link {
+                forward: previous.forward,
+                span: previous.span - skipped,
+            };
+            self.nodes[update[i]].levels[i] = Link {
+                forward: Some(x),
+                span: skipped + 1,
+            };
+        }
+        for (i, &previous) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[previous].levels[i].span += 1;
+        }
+
+        match self.nodes[x].levels[0].forward {
+            Some(next) => self.nodes[next].backward = Some(x),
+            None => self.tail = Some(x),
+        }
+        self.len += 1;
+    }
+
+    /// Removes a member with the given score. Returns whether it was found.
+    pub fn remove(&mut self, score: f64, member: &[u8]) -> bool {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward
+                && self.compare(next, score, member) == Ordering::Less
+            {
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let Some(x) = self.nodes[x].levels[0].forward else {
+            return false;
+        };
+        if self.compare(x, score, member) != Ordering::Equal {
+            return false;
+        }
+
+        for (i, &previous) in update.iter().enumerate().take(self.level) {
+            let link = self.nodes[x].levels.get(i).copied();
+            let previous = &mut self.nodes[previous].levels[i];
+            match link {
+                Some(link) if previous.forward == Some(x) => {
+                    previous.span += link.span;
+                    previous.span -= 1;
+                    previous.forward = link.forward;
+                }
+                _ => previous.span -= 1,
+            }
+        }
+        let backward = self.nodes[x].backward;
+        match self.nodes[x].levels[0].forward {
+            Some(next) => self.nodes[next].backward = backward,
+            None => self.tail = backward,
+        }
+        while self.level > 1 && self.nodes[HEAD].levels[self.level - 1].forward.is_none() {
+            self.level -= 1;
+        }
+        self.len -= 1;
+        self.release(x);
+        true
+    }
+
+    /// Returns the number of members at the start of the list for which the
+    /// predicate holds, which must hold for a prefix of the list. For
+    /// example, with a predicate of "score is below 5", this is both the
+    /// count of members scoring below 5 and the rank of the first member
+    /// scoring at least 5.
+    pub fn count_while(&self, predicate: impl Fn(f64, &[u8]) -> bool) -> usize {
+        let mut rank = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward
+                && predicate(self.nodes[next].score, &self.nodes[next].member)
+            {
+                rank += self.nodes[x].levels[i].span;
+                x = next;
+            }
+        }
+        rank
+    }
+
+    /// Returns the rank of a member with the given score.
+    pub fn rank(&self, score: f64, member: &[u8]) -> Option<usize> {
+        let rank = self.count_while(|s, m| compare(s, m, score, member) == Ordering::Less);
+        let (s, m) = self.get(rank)?;
+        (s == score && m == member).then_some(rank)
+    }
+
+    /// Returns the score and member at a rank.
+    pub fn get(&self, rank: usize) -> Option<(f64, &[u8])> {
+        let x = self.node_at(rank)?;
+        Some((self.nodes[x].score, &self.nodes[x].member))
+    }
+
+    /// Returns an iterator starting at a rank, moving toward higher scores
+    /// or, when reversed, toward lower scores.
+    pub fn iter_from(&self, rank: usize, is_reversed: bool) -> Iter<'_> {
+        Iter {
+            list: self,
+            next: self.node_at(rank),
+            is_reversed,
+        }
+    }
+
+    /// Returns the node at a rank.
+    fn node_at(&self, rank: usize) -> Option<usize> {
+        if rank >= self.len {
+            return None;
+        }
+        if rank == self.len - 1 {
+            return self.tail;
+        }
+        let target = rank + 1;
+        let mut traversed = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward
+                && traversed + self.nodes[x].levels[i].span <= target
+            {
+                traversed += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if traversed == target {
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    /// Compares a node with a score and member.
+    fn compare(&self, x: usize, score: f64, member: &[u8]) -> Ordering {
+        compare(self.nodes[x].score, &self.nodes[x].member, score, member)
+    }
+
+    /// Picks a level for a new node, where each level is a quarter as
+    /// likely as the one below.
+    fn random_level(&mut self) -> usize {
+        let mut level = 1;
+        while level < MAX_LEVEL && self.rng.next_below(4) == 0 {
+            level += 1;
+        }
+        level
+    }
+
+    /// Stores a node, reusing a freed slot if there is one.
+    fn allocate(&mut self, node: Node) -> usize {
+        match self.free.pop() {
+            Some(x) => {
+                self.nodes[x] = node;
+                x
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    /// Frees a node's slot for reuse.
+    fn release(&mut self, x: usize) {
+        self.nodes[x].member = Vec::new();
+        self.nodes[x].levels = Vec::new();
+        self.free.push(x);
+    }
+}
+
+/// An iterator over the scores and members of a skiplist.
+pub struct Iter<'a> {
+    list: &'a SkipList,
+    next: Option<usize>,
+    is_reversed: bool,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (f64, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = &self.list.nodes[self.next?];
+        self.next = if self.is_reversed {
+            node.backward
+        } else {
+            node.levels[0].forward
+        };
+        Some((node.score, &node.member))
+    }
+}
+
+/// Orders by score, then by member for equal scores.
+fn compare(score: f64, member: &[u8], other_score: f64, other_member: &[u8]) -> Ordering {
+    // Scores are never NaN, and zero and negative zero are equal.
+    score
+        .partial_cmp(&other_score)
+        .unwrap_or(Ordering::Equal)
+        .then_with(|| member.cmp(other_member))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::super::Rng;
+    use super::{HEAD, SkipList, compare};
+
+    /// Checks the links, spans and backward pointers of every level, and
+    /// that the list holds the same members as a sorted model.
+    fn check(list: &SkipList, model: &[(f64, Vec<u8>)]) {
+        assert_eq!(list.len, model.len());
+
+        // The rank of each node is its position on the bottom level.
+        let mut ranks = vec![None; list.nodes.len()];
+        let mut previous = None;
+        let mut x = list.nodes[HEAD].levels[0].forward;
+        let mut rank = 0;
+        while let Some(node) = x {
+            assert_eq!(list.nodes[node].backward, previous);
+            ranks[node] = Some(rank + 1);
+            previous = Some(node);
+            x = list.nodes[node].levels[0].forward;
+            rank += 1;
+        }
+        assert_eq!(list.tail, previous);
+        ranks[HEAD] = Some(0);
+
+        for i in 0..list.level {
+            let mut x = HEAD;
+            while let Some(next) = list.nodes[x].levels[i].forward {
+                let (Some(from), Some(to)) = (ranks[x], ranks[next]) else {
+                    panic!("level {i} links to a node not on the bottom level");
+                };
+                assert_eq!(list.nodes[x].levels[i].span, to - from, "span at level {i}");
+                x = next;
+            }
+        }
+        for i in list.level..list.nodes[HEAD].levels.len() {
+            assert_eq!(list.nodes[HEAD].levels[i].forward, None);
+        }
+
+        for (rank, (score, member)) in model.iter().enumerate() {
+            assert_eq!(list.get(rank), Some((*score, member.as_slice())));
+            assert_eq!(list.rank(*score, member), Some(rank));
+        }
+        assert_eq!(list.get(model.len()), None);
+        let forward: Vec<_> = list
+            .iter_from(0, false)
+            .map(|(s, m)| (s, m.to_vec()))
+            .collect();
+        assert_eq!(forward, model);
+        let backward: Vec<_> = list
+            .iter_from(model.len().saturating_sub(1), true)
+            .map(|(s, m)| (s, m.to_vec()))
+            .collect();
+        assert!(backward.iter().eq(model.iter().rev()));
+    }
+
+    /// Finds where a member goes in the sorted model.
+    fn position(model: &[(f64, Vec<u8>)], score: f64, member: &[u8]) -> Result<usize, usize> {
+        model.binary_search_by(|(s, m)| compare(*s, m, score, member))
+    }
+
+    #[test]
+    fn ranks_follow_score_then_member() {
+        let mut list = SkipList::new();
+        list.insert(2.0, b"b");
+        list.insert(1.0, b"z");
+        list.insert(2.0, b"a");
+        list.insert(f64::NEG_INFINITY, b"m");
+        let model = vec![
+            (f64::NEG_INFINITY, b"m".to_vec()),
+            (1.0, b"z".to_vec()),
+            (2.0, b"a".to_vec()),
+            (2.0, b"b".to_vec()),
+        ];
+        check(&list, &model);
+        assert_eq!(list.rank(2.0, b"c"), None);
+        assert_eq!(list.rank(1.0, b"a"), None);
+        assert_eq!(list.count_while(|score, _| score < 2.0), 2);
+        assert_eq!(compare(0.0, b"a", -0.0, b"a"), Ordering::Equal);
+    }
+
+    #[test]
+    fn spans_survive_inserts_and_removes() {
+        let mut rng = Rng::with_seed(11);
+        let mut list = SkipList::new();
+        let mut model: Vec<(f64, Vec<u8>)> = Vec::new();
+        for step in 0..20_000 {
+            let score = rng.next_below(50) as f64;
+            let member = format!("m{}", rng.next_below(500)).into_bytes();
+            if rng.next_below(3) == 0 {
+                let removed = list.remove(score, &member);
+                match position(&model, score, &member) {
+                    Ok(i) => {
+                        assert!(removed);
+                        model.remove(i);
+                    }
+                    Err(_) => assert!(!removed),
+                }
+            } else if let Err(i) = position(&model, score, &member) {
+                list.insert(score, &member);
+                model.insert(i, (score, member));
+            }
+            if step % 500 == 0 {
+                check(&list, &model);
+            }
+        }
+        check(&list, &model);
+
+        while let Some((score, member)) = model.pop() {
+            assert!(list.remove(score, &member));
+        }
+        check(&list, &model);
+        assert_eq!(list.level, 1);
+    }
+}
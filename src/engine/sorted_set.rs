@@ -0,0 +1,1669 @@
+//! The sorted set type and its commands.
+
+use std::collections::{HashMap, VecDeque};
+
+use super::blocking::{BlockOn, parse_timeout};
+use super::list::parse_mpop_args;
+use super::set::SetOperation;
+use super::skiplist::{self, SkipList};
+use super::string::{new_not_float_error, parse_f64};
+use super::value::Value;
+use super::{
+    Engine, EntryBuilder, Key, Object, convert_to_ascii_uppercase, new_arity_error,
+    new_not_integer_error, new_pairs_reply, new_syntax_error, new_wrong_type_error,
+    normalize_range, parse_i64,
+};
+use crate::resp::Protocol;
+
+/// A set of members ordered by score.
+#[derive(Clone, Debug)]
+pub struct SortedSet {
+    /// The score of each member.
+    scores: HashMap<Vec<u8>, f64>,
+
+    /// The members in order, for ranks and ranges.
+    list: SkipList,
+}
+
+impl SortedSet {
+    /// Creates a new empty sorted set.
+    pub fn new() -> Self {
+        SortedSet {
+            scores: HashMap::new(),
+            list: SkipList::new(),
+        }
+    }
+
+    /// Returns the number of members.
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    /// Returns whether the sorted set has no members.
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Returns the score of a member.
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Adds a member or changes its score. Returns whether it's new.
+    pub fn insert(&mut self, member: &[u8], score: f64) -> bool {
+        // Adding zero turns negative zero into zero.
+        let score = score + 0.0;
+        let old = self.scores.insert(member.to_vec(), score);
+        if let Some(old) = old {
+            self.list.remove(old, member);
+        }
+        self.list.insert(score, member);
+        old.is_none()
+    }
+
+    /// Removes a member. Returns whether it was in the sorted set.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => self.list.remove(score, member),
+            None => false,
+        }
+    }
+
+    /// Returns the rank of a member, counting from the lowest score.
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+        self.list.rank(score, member)
+    }
+
+    /// Returns an iterator over the scores and members from the lowest
+    /// score.
+    pub fn iter(&self) -> skiplist::Iter<'_> {
+        self.list.iter_from(0, false)
+    }
+
+    /// Removes and returns the member at an end.
+    fn pop(&mut self, end: ScoreEnd) -> Option<(Vec<u8>, f64)> {
+        let rank = match end {
+            ScoreEnd::Min => 0,
+            ScoreEnd::Max => self.len().checked_sub(1)?,
+        };
+        let (score, member) = self.list.get(rank)?;
+        let member = member.to_vec();
+        self.remove(&member);
+        Some((member, score))
+    }
+
+    /// Returns the first and last ranks, counting from the lowest score, of
+    /// the members in a range. A reversed rank range counts from the
+    /// highest score.
+    fn ranks(&self, range: &Range, is_reversed: bool) -> Option<(usize, usize)> {
+        let (start, end) = match range {
+            Range::Rank(start, stop) => {
+                let (start, stop) = normalize_range(*start, *stop, self.len())?;
+                let last = self.len() - 1;
+                return Some(if is_reversed {
+                    (last - stop, last - start)
+                } else {
+                    (start, stop)
+                });
+            }
+            Range::Score(min, max) => (
+                self.list.count_while(|score, _| min.is_below(score)),
+                self.list.count_while(|score, _| max.is_within(score)),
+            ),
+            Range::Lex(min, max) => (
+                self.list.count_while(|_, member| min.is_below(member)),
+                self.list.count_while(|_, member| max.is_within(member)),
+            ),
+        };
+        (start < end).then(|| (start, end - 1))
+    }
+
+    /// Returns the scores and members in a range, in the order asked for,
+    /// after skipping and limiting by an offset and count. A negative count
+    /// means no limit.
+    fn range(
+        &self,
+        range: &Range,
+        is_reversed: bool,
+        limit: Option<(i64, i64)>,
+    ) -> Vec<(f64, &[u8])> {
+        let Some((start, stop)) = self.ranks(range, is_reversed) else {
+            return Vec::new();
+        };
+
+        let (offset, count) = limit.unwrap_or((0, -1));
+        let len = stop - start + 1;
+        if offset < 0 || offset as u64 >= len as u64 {
+            return Vec::new();
+        }
+        let offset = offset as usize;
+        let count = if count < 0 {
+            len - offset
+        } else {
+            std::cmp::min(count as u64, (len - offset) as u64) as usize
+        };
+
+        let first = if is_reversed {
+            stop - offset
+        } else {
+            start + offset
+        };
+        self.list
+            .iter_from(first, is_reversed)
+            .take(count)
+            .collect()
+    }
+}
+
+/// An end of a sorted set, the lowest or highest score.
+#[derive(Clone, Copy)]
+pub enum ScoreEnd {
+    Min,
+    Max,
+}
+
+impl ScoreEnd {
+    /// Parses `MIN` or `MAX`, ignoring case.
+    pub fn parse(end: &[u8]) -> Option<ScoreEnd> {
+        if end.eq_ignore_ascii_case(b"MIN") {
+            Some(ScoreEnd::Min)
+        } else if end.eq_ignore_ascii_case(b"MAX") {
+            Some(ScoreEnd::Max)
+        } else {
+            None
+        }
+    }
+}
+
+/// How scores of the same member are combined across sorted sets.
+#[derive(Clone, Copy)]
+enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            // The sum of opposite infinities is taken as zero.
+            Aggregate::Sum => zero_if_nan(a + b),
+            Aggregate::Min => a.min(b),
+            Aggregate::Max => a.max(b),
+        }
+    }
+}
+
+/// The arguments of `ZUNION`, `ZINTER`, `ZDIFF` and their `STORE`
+/// variants, following the destination.
+struct CombineArgs {
+    keys: Vec<Key>,
+    weights: Vec<f64>,
+    aggregate: Aggregate,
+    with_scores: bool,
+}
+
+/// What the bounds of a range select by.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RangeKind {
+    Rank,
+    Score,
+    Lex,
+}
+
+/// A range of members of a sorted set.
+#[derive(Debug)]
+enum Range {
+    /// Start and stop ranks, which may be negative to count from the end.
+    Rank(i64, i64),
+    Score(ScoreBound, ScoreBound),
+    Lex(LexBound, LexBound),
+}
+
+/// One end of a score range, written `5` or `(5` when exclusive.
+#[derive(Debug)]
+struct ScoreBound {
+    score: f64,
+    is_exclusive: bool,
+}
+
+/// One end of a lexical range, written `-`, `+`, `[a` or `(a`.
+#[derive(Debug)]
+enum LexBound {
+    Min,
+    Max,
+    Inclusive(Vec<u8>),
+    Exclusive(Vec<u8>),
+}
+
+/// The arguments of a `ZRANGE` style query.
+struct RangeQuery {
+    range: Range,
+    is_reversed: bool,
+
+    /// The offset and count from a `LIMIT` option.
+    limit: Option<(i64, i64)>,
+
+    with_scores: bool,
+}
+
+impl Range {
+    /// Parses the bounds of a range of some kind.
+    fn parse(kind: RangeKind, min: &[u8], max: &[u8]) -> Result<Range, Object> {
+        match kind {
+            RangeKind::Rank => match (parse_i64(min), parse_i64(max)) {
+                (Some(start), Some(stop)) => Ok(Range::Rank(start, stop)),
+                _ => Err(new_not_integer_error()),
+            },
+            RangeKind::Score => match (ScoreBound::parse(min), ScoreBound::parse(max)) {
+                (Some(min), Some(max)) => Ok(Range::Score(min, max)),
+                _ => Err(Object::new_error(b"ERR min or max is not a float")),
+            },
+            RangeKind::Lex => match (LexBound::parse(min), LexBound::parse(max)) {
+                (Some(min), Some(max)) => Ok(Range::Lex(min, max)),
+                _ => Err(Object::new_error(
+                    b"ERR min or max not valid string range item",
+                )),
+            },
+        }
+    }
+}
+
+impl ScoreBound {
+    fn parse(s: &[u8]) -> Option<ScoreBound> {
+        let (is_exclusive, score) = match s.split_first() {
+            Some((b'(', rest)) => (true, rest),
+            _ => (false, s),
+        };
+        let score = parse_f64(score)?;
+        Some(ScoreBound {
+            score,
+            is_exclusive,
+        })
+    }
+
+    /// Returns whether a score falls below this bound as a minimum.
+    fn is_below(&self, score: f64) -> bool {
+        if self.is_exclusive {
+            score <= self.score
+        } else {
+            score < self.score
+        }
+    }
+
+    /// Returns whether a score falls within this bound as a maximum.
+    fn is_within(&self, score: f64) -> bool {
+        if self.is_exclusive {
+            score < self.score
+        } else {
+            score <= self.score
+        }
+    }
+}
+
+impl LexBound {
+    fn parse(s: &[u8]) -> Option<LexBound> {
+        match s.split_first()? {
+            (b'-', []) => Some(LexBound::Min),
+            (b'+', []) => Some(LexBound::Max),
+            (b'[', rest) => Some(LexBound::Inclusive(rest.to_vec())),
+            (b'(', rest) => Some(LexBound::Exclusive(rest.to_vec())),
+            _ => None,
+        }
+    }
+
+    /// Returns whether a member falls below this bound as a minimum.
+    fn is_below(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(bound) => member < bound.as_slice(),
+            LexBound::Exclusive(bound) => member <= bound.as_slice(),
+        }
+    }
+
+    /// Returns whether a member falls within this bound as a maximum.
+    fn is_within(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(bound) => member <= bound.as_slice(),
+            LexBound::Exclusive(bound) => member < bound.as_slice(),
+        }
+    }
+}
+
+impl RangeQuery {
+    /// Parses the arguments following the key of a range query. Only the
+    /// unified syntax of `ZRANGE` takes the kind and direction as options;
+    /// the older commands imply them.
+    fn parse(
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        mut kind: RangeKind,
+        mut is_reversed: bool,
+        is_unified: bool,
+    ) -> Result<RangeQuery, Object> {
+        let (Some(mut min), Some(mut max)) = (elements.pop_front(), elements.pop_front()) else {
+            return Err(new_arity_error(name));
+        };
+
+        let mut limit = None;
+        let mut with_scores = false;
+        while let Some(mut option) = elements.pop_front() {
+            convert_to_ascii_uppercase(&mut option);
+            match option.as_slice() {
+                b"WITHSCORES" => with_scores = true,
+                b"LIMIT" => {
+                    let (Some(offset), Some(count)) = (elements.pop_front(), elements.pop_front())
+                    else {
+                        return Err(new_syntax_error());
+                    };
+                    let (Some(offset), Some(count)) = (parse_i64(&offset), parse_i64(&count))
+                    else {
+                        return Err(new_not_integer_error());
+                    };
+                    limit = Some((offset, count));
+                }
+                b"BYSCORE" if is_unified => kind = RangeKind::Score,
+                b"BYLEX" if is_unified => kind = RangeKind::Lex,
+                b"REV" if is_unified => is_reversed = true,
+                _ => return Err(new_syntax_error()),
+            }
+        }
+
+        if limit.is_some() && kind == RangeKind::Rank {
+            return Err(Object::new_error(
+                b"ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX",
+            ));
+        }
+        if with_scores && kind == RangeKind::Lex {
+            return Err(Object::new_error(
+                b"ERR syntax error, WITHSCORES not supported in combination with BYLEX",
+            ));
+        }
+
+        // Reversed score and lexical ranges are written from max to min.
+        if is_reversed && kind != RangeKind::Rank {
+            std::mem::swap(&mut min, &mut max);
+        }
+
+        Ok(RangeQuery {
+            range: Range::parse(kind, &min, &max)?,
+            is_reversed,
+            limit,
+            with_scores,
+        })
+    }
+}
+
+impl Engine {
+    /// Do a `ZADD` command.
+    pub(super) fn do_zadd(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        if elements.len() < 3 {
+            return new_arity_error("zadd");
+        }
+
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error("zadd");
+        };
+
+        let (mut nx, mut xx, mut gt, mut lt, mut ch, mut incr) =
+            (false, false, false, false, false, false);
+        while let Some(option) = elements.front() {
+            let mut option = option.clone();
+            convert_to_ascii_uppercase(&mut option);
+            match option.as_slice() {
+                b"NX" => nx = true,
+                b"XX" => xx = true,
+                b"GT" => gt = true,
+                b"LT" => lt = true,
+                b"CH" => ch = true,
+                b"INCR" => incr = true,
+                _ => break,
+            }
+            elements.pop_front();
+        }
+
+        if elements.is_empty() || !elements.len().is_multiple_of(2) {
+            return new_syntax_error();
+        }
+        if nx && xx {
+            return Object::new_error(b"ERR XX and NX options at the same time are not compatible");
+        }
+        if (nx && (gt || lt)) || (gt && lt) {
+            return Object::new_error(
+                b"ERR GT, LT, and/or NX options at the same time are not compatible",
+            );
+        }
+        if incr && elements.len() > 2 {
+            return Object::new_error(b"ERR INCR option supports a single increment-element pair");
+        }
+
+        // Parse every score before changing anything.
+        let mut pairs = Vec::with_capacity(elements.len() / 2);
+        while let (Some(score), Some(member)) = (elements.pop_front(), elements.pop_front()) {
+            let Some(score) = parse_f64(&score) else {
+                return new_not_float_error();
+            };
+            pairs.push((score, member));
+        }
+
+        match self.get_entry(&key).map(|entry| &entry.value) {
+            Some(Value::SortedSet(_)) => (),
+            Some(_) => return new_wrong_type_error(),
+            // Nothing would be added, so don't create the key.
+            None if xx => {
+                return if incr {
+                    Object::BulkString(None)
+                } else {
+                    Object::Integer(0)
+                };
+            }
+            None => (),
+        }
+
+        let entry = self.get_entry_or_insert(key.clone(), Value::SortedSet(SortedSet::new()));
+
+        let Value::SortedSet(zset) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let (mut added, mut changed) = (0, 0);
+        let mut incr_score = None;
+        for (score, member) in pairs {
+            match zset.score(&member) {
+                Some(current) => {
+                    if nx {
+                        continue;
+                    }
+                    let score = if incr { current + score } else { score };
+                    if score.is_nan() {
+                        return new_nan_score_error();
+                    }
+                    if (gt && score <= current) || (lt && score >= current) {
+                        continue;
+                    }
+                    if score != current {
+                        zset.insert(&member, score);
+                        changed += 1;
+                    }
+                    incr_score = Some(score);
+                }
+                None => {
+                    if xx {
+                        continue;
+                    }
+                    zset.insert(&member, score);
+                    added += 1;
+                    incr_score = Some(score);
+                }
+            }
+        }
+
+        if added > 0 {
+            self.blocked.signal_key_ready(&key);
+        }
+
+        if incr {
+            new_score_reply(incr_score)
+        } else if ch {
+            Object::Integer(added + changed)
+        } else {
+            Object::Integer(added)
+        }
+    }
+
+    /// Do a `ZINCRBY` command.
+    pub(super) fn do_zincrby(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(amount), Some(member), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("zincrby");
+        };
+
+        let Some(amount) = parse_f64(&amount) else {
+            return new_not_float_error();
+        };
+
+        let entry = self.get_entry_or_insert(key.clone(), Value::SortedSet(SortedSet::new()));
+
+        let Value::SortedSet(zset) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let score = zset.score(&member).unwrap_or(0.0) + amount;
+        if score.is_nan() {
+            return new_nan_score_error();
+        }
+
+        if zset.insert(&member, score) {
+            self.blocked.signal_key_ready(&key);
+        }
+
+        Object::Double(score)
+    }
+
+    /// Do a `ZREM` command.
+    pub(super) fn do_zrem(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error("zrem");
+        };
+
+        if elements.is_empty() {
+            return new_arity_error("zrem");
+        }
+
+        let Some(entry) = self.get_entry_mut(&key) else {
+            return Object::Integer(0);
+        };
+
+        let Value::SortedSet(zset) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let removed = elements.iter().filter(|member| zset.remove(member)).count();
+
+        if zset.is_empty() {
+            self.remove_entry(&key);
+        }
+
+        Object::Integer(removed as i64)
+    }
+
+    /// Do a `ZSCORE` command.
+    pub(super) fn do_zscore(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(member), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("zscore");
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::BulkString(None);
+        };
+
+        let Value::SortedSet(zset) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        new_score_reply(zset.score(&member))
+    }
+
+    /// Do a `ZMSCORE` command.
+    pub(super) fn do_zmscore(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error("zmscore");
+        };
+
+        if elements.is_empty() {
+            return new_arity_error("zmscore");
+        }
+
+        let zset = match self.get_entry(&key).map(|entry| &entry.value) {
+            Some(Value::SortedSet(zset)) => Some(zset),
+            Some(_) => return new_wrong_type_error(),
+            None => None,
+        };
+
+        let items = elements
+            .iter()
+            .map(|member| new_score_reply(zset.and_then(|zset| zset.score(member))))
+            .collect();
+
+        Object::new_array(items)
+    }
+
+    /// Do a `ZCARD` command.
+    pub(super) fn do_zcard(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), None) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("zcard");
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::Integer(0);
+        };
+
+        let Value::SortedSet(zset) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        Object::Integer(zset.len() as i64)
+    }
+
+    /// Do a `ZRANK` or `ZREVRANK` command. Reversed ranks count from the
+    /// highest score.
+    pub(super) fn do_zrank(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        is_reversed: bool,
+    ) -> Object {
+        let (Some(key), Some(member)) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error(name);
+        };
+
+        let with_score = match (elements.pop_front(), elements.pop_front()) {
+            (None, _) => false,
+            (Some(option), None) if option.eq_ignore_ascii_case(b"WITHSCORE") => true,
+            _ => return new_syntax_error(),
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::BulkString(None);
+        };
+
+        let Value::SortedSet(zset) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let Some(rank) = zset.rank(&member) else {
+            return Object::BulkString(None);
+        };
+
+        let rank = if is_reversed {
+            zset.len() - 1 - rank
+        } else {
+            rank
+        };
+
+        if with_score {
+            Object::new_array(vec![
+                Object::Integer(rank as i64),
+                new_score_reply(zset.score(&member)),
+            ])
+        } else {
+            Object::Integer(rank as i64)
+        }
+    }
+
+    /// Do a `ZRANGE` command, or one of the older commands for a particular
+    /// kind and direction of range.
+    pub(super) fn do_zrange(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        kind: RangeKind,
+        is_reversed: bool,
+    ) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error(name);
+        };
+
+        let is_unified = name == "zrange";
+        let query = match RangeQuery::parse(elements, name, kind, is_reversed, is_unified) {
+            Ok(query) => query,
+            Err(e) => return e,
+        };
+
+        let protocol = self.reply_protocol;
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::new_array(Vec::new());
+        };
+
+        let Value::SortedSet(zset) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let items = zset.range(&query.range, query.is_reversed, query.limit);
+        new_range_reply(&items, query.with_scores, protocol)
+    }
+
+    /// Do a `ZRANGESTORE` command.
+    pub(super) fn do_zrangestore(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(destination), Some(source)) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("zrangestore");
+        };
+
+        let query = match RangeQuery::parse(elements, "zrangestore", RangeKind::Rank, false, true) {
+            Ok(query) if query.with_scores => return new_syntax_error(),
+            Ok(query) => query,
+            Err(e) => return e,
+        };
+
+        let mut result = SortedSet::new();
+        match self.get_entry(&source).map(|entry| &entry.value) {
+            Some(Value::SortedSet(zset)) => {
+                for (score, member) in zset.range(&query.range, query.is_reversed, query.limit) {
+                    result.insert(member, score);
+                }
+            }
+            Some(_) => return new_wrong_type_error(),
+            None => (),
+        }
+
+        let len = result.len();
+        if result.is_empty() {
+            self.remove_entry(&destination);
+        } else {
+            let entry = EntryBuilder::new(Value::SortedSet(result)).build();
+            self.insert_entry(destination, entry);
+        }
+
+        Object::Integer(len as i64)
+    }
+
+    /// Do a `ZCOUNT` or `ZLEXCOUNT` command.
+    pub(super) fn do_zcount(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        kind: RangeKind,
+    ) -> Object {
+        let (Some(key), Some(min), Some(max), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error(name);
+        };
+
+        let range = match Range::parse(kind, &min, &max) {
+            Ok(range) => range,
+            Err(e) => return e,
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::Integer(0);
+        };
+
+        let Value::SortedSet(zset) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let count = match zset.ranks(&range, false) {
+            Some((start, stop)) => stop - start + 1,
+            None => 0,
+        };
+
+        Object::Integer(count as i64)
+    }
+
+    /// Do a `ZREMRANGEBYRANK`, `ZREMRANGEBYSCORE` or `ZREMRANGEBYLEX`
+    /// command.
+    pub(super) fn do_zremrange(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        kind: RangeKind,
+    ) -> Object {
+        let (Some(key), Some(min), Some(max), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error(name);
+        };
+
+        let range = match Range::parse(kind, &min, &max) {
+            Ok(range) => range,
+            Err(e) => return e,
+        };
+
+        let Some(entry) = self.get_entry_mut(&key) else {
+            return Object::Integer(0);
+        };
+
+        let Value::SortedSet(zset) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let members: Vec<Vec<u8>> = zset
+            .range(&range, false, None)
+            .into_iter()
+            .map(|(_, member)| member.to_vec())
+            .collect();
+        for member in members.iter() {
+            zset.remove(member);
+        }
+
+        if zset.is_empty() {
+            self.remove_entry(&key);
+        }
+
+        Object::Integer(members.len() as i64)
+    }
+
+    /// Do a `ZUNION`, `ZINTER` or `ZDIFF` command.
+    pub(super) fn do_zset_operation(
+        &mut self,
+        elements: VecDeque<Vec<u8>>,
+        name: &str,
+        operation: SetOperation,
+    ) -> Object {
+        let args = match CombineArgs::parse(elements, name, operation, false) {
+            Ok(args) => args,
+            Err(e) => return e,
+        };
+
+        let zset = match self.combine_sorted_sets(&args, operation) {
+            Ok(zset) => zset,
+            Err(e) => return e,
+        };
+
+        let items: Vec<(f64, &[u8])> = zset.iter().collect();
+        new_range_reply(&items, args.with_scores, self.reply_protocol)
+    }
+
+    /// Do a `ZUNIONSTORE`, `ZINTERSTORE` or `ZDIFFSTORE` command.
+    pub(super) fn do_zset_operation_store(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        operation: SetOperation,
+    ) -> Object {
+        let Some(destination) = elements.pop_front() else {
+            return new_arity_error(name);
+        };
+
+        let args = match CombineArgs::parse(elements, name, operation, true) {
+            Ok(args) => args,
+            Err(e) => return e,
+        };
+
+        let zset = match self.combine_sorted_sets(&args, operation) {
+            Ok(zset) => zset,
+            Err(e) => return e,
+        };
+
+        let len = zset.len();
+        if zset.is_empty() {
+            self.remove_entry(&destination);
+        } else {
+            let entry = EntryBuilder::new(Value::SortedSet(zset)).build();
+            self.insert_entry(destination, entry);
+        }
+
+        Object::Integer(len as i64)
+    }
+
+    /// Do a `ZPOPMIN` or `ZPOPMAX` command.
+    pub(super) fn do_zpop(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        end: ScoreEnd,
+    ) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error(name);
+        };
+
+        let has_count = !elements.is_empty();
+        let count = match (elements.pop_front(), elements.pop_front()) {
+            (None, _) => 1,
+            (Some(count), None) => match parse_i64(&count) {
+                Some(count) if count >= 0 => count as usize,
+                Some(_) => {
+                    return Object::new_error(b"ERR value is out of range, must be positive");
+                }
+                None => return new_not_integer_error(),
+            },
+            _ => return new_syntax_error(),
+        };
+
+        match self.pop_sorted_set(&key, end, count) {
+            // Without a count there's at most one member, which isn't paired.
+            Ok(popped) if !has_count => Object::new_array(new_popped_reply(popped)),
+            Ok(popped) => {
+                let pairs = popped
+                    .into_iter()
+                    .map(|(member, score)| {
+                        (
+                            Object::BulkString(Some(member)),
+                            new_score_reply(Some(score)),
+                        )
+                    })
+                    .collect();
+                new_pairs_reply(pairs, self.reply_protocol)
+            }
+            Err(e) => e,
+        }
+    }
+
+    /// Do a `BZPOPMIN` or `BZPOPMAX` command, popping from the first
+    /// non-empty sorted set of the given keys or blocking until one has
+    /// members.
+    pub(super) fn do_bzpop(
+        &mut self,
+        elements: VecDeque<Vec<u8>>,
+        name: &str,
+        end: ScoreEnd,
+    ) -> Result<Object, BlockOn> {
+        if elements.len() < 2 {
+            return Ok(new_arity_error(name));
+        }
+
+        let mut keys: Vec<Key> = elements.iter().cloned().collect();
+        let timeout = keys.pop().unwrap_or_default();
+        let timeout = match parse_timeout(&timeout) {
+            Ok(timeout) => timeout,
+            Err(e) => return Ok(e),
+        };
+
+        for key in keys.iter() {
+            match self.pop_sorted_set(key, end, 1) {
+                Ok(popped) if !popped.is_empty() => {
+                    let mut items = vec![Object::BulkString(Some(key.clone()))];
+                    items.extend(new_popped_reply(popped));
+                    return Ok(Object::new_array(items));
+                }
+                Ok(_) => (),
+                Err(e) => return Ok(e),
+            }
+        }
+
+        Err(BlockOn {
+            elements,
+            keys,
+            timeout,
+        })
+    }
+
+    /// Do a `ZMPOP` command.
+    pub(super) fn do_zmpop(&mut self, elements: VecDeque<Vec<u8>>) -> Object {
+        let (keys, end, count) = match parse_mpop_args(elements, "zmpop", ScoreEnd::parse) {
+            Ok(args) => args,
+            Err(e) => return e,
+        };
+
+        match self.mpop_sorted_set(&keys, end, count) {
+            Ok(Some(reply)) => reply,
+            Ok(None) => Object::Null,
+            Err(e) => e,
+        }
+    }
+
+    /// Do a `BZMPOP` command, popping from the first non-empty sorted set
+    /// of the given keys or blocking until one has members.
+    pub(super) fn do_bzmpop(&mut self, elements: VecDeque<Vec<u8>>) -> Result<Object, BlockOn> {
+        let mut args = elements.clone();
+        let Some(timeout) = args.pop_front() else {
+            return Ok(new_arity_error("bzmpop"));
+        };
+
+        let timeout = match parse_timeout(&timeout) {
+            Ok(timeout) => timeout,
+            Err(e) => return Ok(e),
+        };
+
+        let (keys, end, count) = match parse_mpop_args(args, "bzmpop", ScoreEnd::parse) {
+            Ok(args) => args,
+            Err(e) => return Ok(e),
+        };
+
+        match self.mpop_sorted_set(&keys, end, count) {
+            Ok(Some(reply)) => Ok(reply),
+            Ok(None) => Err(BlockOn {
+                elements,
+                keys,
+                timeout,
+            }),
+            Err(e) => Ok(e),
+        }
+    }
+
+    /// Pops up to `count` members from an end of a sorted set, deleting it
+    /// if it becomes empty.
+    fn pop_sorted_set(
+        &mut self,
+        key: &[u8],
+        end: ScoreEnd,
+        count: usize,
+    ) -> Result<Vec<(Vec<u8>, f64)>, Object> {
+        let Some(entry) = self.get_entry_mut(key) else {
+            return Ok(Vec::new());
+        };
+
+        let Value::SortedSet(zset) = &mut entry.value else {
+            return Err(new_wrong_type_error());
+        };
+
+        let mut popped = Vec::new();
+        while popped.len() < count {
+            let Some(item) = zset.pop(end) else {
+                break;
+            };
+            popped.push(item);
+        }
+
+        if zset.is_empty() {
+            self.remove_entry(key);
+        }
+
+        Ok(popped)
+    }
+
+    /// Pops up to `count` members from the first non-empty sorted set of the
+    /// given keys. Returns the key and the popped members with their scores
+    /// as a reply, or `None` when all the sorted sets are empty.
+    fn mpop_sorted_set(
+        &mut self,
+        keys: &[Key],
+        end: ScoreEnd,
+        count: usize,
+    ) -> Result<Option<Object>, Object> {
+        for key in keys.iter() {
+            let popped = self.pop_sorted_set(key, end, count)?;
+            if popped.is_empty() {
+                continue;
+            }
+
+            let items = popped
+                .into_iter()
+                .map(|(member, score)| {
+                    Object::new_array(vec![
+                        Object::BulkString(Some(member)),
+                        new_score_reply(Some(score)),
+                    ])
+                })
+                .collect();
+            return Ok(Some(Object::new_array(vec![
+                Object::BulkString(Some(key.clone())),
+                Object::new_array(items),
+            ])));
+        }
+
+        Ok(None)
+    }
+
+    /// Combines the sorted sets, or sets, at the given keys. Missing keys
+    /// count as empty.
+    fn combine_sorted_sets(
+        &mut self,
+        args: &CombineArgs,
+        operation: SetOperation,
+    ) -> Result<SortedSet, Object> {
+        // Expire and check every key before borrowing their values together.
+        for key in args.keys.iter() {
+            match self.get_entry(key).map(|entry| &entry.value) {
+                Some(Value::SortedSet(_) | Value::Set(_)) | None => (),
+                Some(_) => return Err(new_wrong_type_error()),
+            }
+        }
+        let values: Vec<Option<&Value>> = args
+            .keys
+            .iter()
+            .map(|key| self.data.get(key).map(|entry| &entry.value))
+            .collect();
+
+        let mut result = SortedSet::new();
+        match operation {
+            SetOperation::Union => {
+                let mut scores: HashMap<Vec<u8>, f64> = HashMap::new();
+                for (value, weight) in values.iter().zip(args.weights.iter()) {
+                    let Some(value) = value else {
+                        continue;
+                    };
+                    for (member, score) in scored_members(value) {
+                        let score = zero_if_nan(score * weight);
+                        scores
+                            .entry(member)
+                            .and_modify(|total| *total = args.aggregate.apply(*total, score))
+                            .or_insert(score);
+                    }
+                }
+                for (member, score) in scores {
+                    result.insert(&member, score);
+                }
+            }
+            SetOperation::Inter => {
+                let Some(values) = values.into_iter().collect::<Option<Vec<&Value>>>() else {
+                    return Ok(result);
+                };
+                // Walk the smallest input, looking members up in the rest.
+                let Some(smallest) = values.iter().min_by_key(|value| value_len(value)) else {
+                    return Ok(result);
+                };
+                'members: for (member, _) in scored_members(smallest) {
+                    let mut total = None;
+                    for (value, weight) in values.iter().zip(args.weights.iter()) {
+                        let Some(score) = score_in(value, &member) else {
+                            continue 'members;
+                        };
+                        let score = zero_if_nan(score * weight);
+                        total = Some(match total {
+                            Some(total) => args.aggregate.apply(total, score),
+                            None => score,
+                        });
+                    }
+                    if let Some(total) = total {
+                        result.insert(&member, total);
+                    }
+                }
+            }
+            SetOperation::Diff => {
+                let Some(Some(first)) = values.first() else {
+                    return Ok(result);
+                };
+                for (member, score) in scored_members(first) {
+                    let is_elsewhere = values[1..]
+                        .iter()
+                        .flatten()
+                        .any(|value| score_in(value, &member).is_some());
+                    if !is_elsewhere {
+                        result.insert(&member, score);
+                    }
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+impl CombineArgs {
+    /// Parses `numkeys key [key ...]` followed by the options. Only
+    /// unions and intersections take weights and an aggregate, and only
+    /// the commands that reply with the result take `WITHSCORES`.
+    fn parse(
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        operation: SetOperation,
+        is_store: bool,
+    ) -> Result<CombineArgs, Object> {
+        let Some(num_keys) = elements.pop_front() else {
+            return Err(new_arity_error(name));
+        };
+
+        let num_keys = match parse_i64(&num_keys) {
+            Some(num_keys) if num_keys > 0 => num_keys as usize,
+            Some(_) => {
+                let message = format!("ERR at least 1 input key is needed for '{name}' command");
+                return Err(Object::new_error(message.as_bytes()));
+            }
+            None => return Err(new_not_integer_error()),
+        };
+
+        if elements.len() < num_keys {
+            return Err(new_syntax_error());
+        }
+        let keys: Vec<Key> = elements.drain(..num_keys).collect();
+
+        let is_weighted = !matches!(operation, SetOperation::Diff);
+        let mut weights = vec![1.0; num_keys];
+        let mut aggregate = Aggregate::Sum;
+        let mut with_scores = false;
+        while let Some(mut option) = elements.pop_front() {
+            convert_to_ascii_uppercase(&mut option);
+            match option.as_slice() {
+                b"WEIGHTS" if is_weighted => {
+                    if elements.len() < num_keys {
+                        return Err(new_syntax_error());
+                    }
+                    for (weight, arg) in weights.iter_mut().zip(elements.drain(..num_keys)) {
+                        *weight = parse_f64(&arg)
+                            .ok_or_else(|| Object::new_error(b"ERR weight value is not a float"))?;
+                    }
+                }
+                b"AGGREGATE" if is_weighted => {
+                    let mut value = elements.pop_front().unwrap_or_default();
+                    convert_to_ascii_uppercase(&mut value);
+                    aggregate = match value.as_slice() {
+                        b"SUM" => Aggregate::Sum,
+                        b"MIN" => Aggregate::Min,
+                        b"MAX" => Aggregate::Max,
+                        _ => return Err(new_syntax_error()),
+                    };
+                }
+                b"WITHSCORES" if !is_store => with_scores = true,
+                _ => return Err(new_syntax_error()),
+            }
+        }
+
+        Ok(CombineArgs {
+            keys,
+            weights,
+            aggregate,
+            with_scores,
+        })
+    }
+}
+
+/// Creates the reply for a range of members, each paired with its score if
+/// asked for.
+fn new_range_reply(items: &[(f64, &[u8])], with_scores: bool, protocol: Protocol) -> Object {
+    let members = items
+        .iter()
+        .map(|&(_, member)| Object::BulkString(Some(member.to_vec())));
+    if !with_scores {
+        return Object::new_array(members.collect());
+    }
+    let scores = items.iter().map(|&(score, _)| new_score_reply(Some(score)));
+    new_pairs_reply(members.zip(scores).collect(), protocol)
+}
+
+/// Creates the reply for popped members, each followed by its score.
+fn new_popped_reply(popped: Vec<(Vec<u8>, f64)>) -> Vec<Object> {
+    let mut replies = Vec::new();
+    for (member, score) in popped {
+        replies.push(Object::BulkString(Some(member)));
+        replies.push(new_score_reply(Some(score)));
+    }
+    replies
+}
+
+/// Returns the score of a member of a sorted set, or of a set, whose members
+/// all score 1.
+fn score_in(value: &Value, member: &[u8]) -> Option<f64> {
+    match value {
+        Value::SortedSet(zset) => zset.score(member),
+        Value::Set(set) => set.contains(member).then_some(1.0),
+        _ => None,
+    }
+}
+
+/// Returns the members and scores of a sorted set, or of a set, whose
+/// members all score 1.
+fn scored_members(value: &Value) -> Vec<(Vec<u8>, f64)> {
+    match value {
+        Value::SortedSet(zset) => zset
+            .iter()
+            .map(|(score, member)| (member.to_vec(), score))
+            .collect(),
+        Value::Set(set) => set.iter().map(|member| (member, 1.0)).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the number of members of a sorted set or a set.
+fn value_len(value: &Value) -> usize {
+    match value {
+        Value::SortedSet(zset) => zset.len(),
+        Value::Set(set) => set.len(),
+        _ => 0,
+    }
+}
+
+/// Returns zero in place of NaN, such as from multiplying an infinite score
+/// by a zero weight.
+fn zero_if_nan(score: f64) -> f64 {
+    if score.is_nan() { 0.0 } else { score }
+}
+
+/// Creates the reply for a score, which is null when there's no score.
+fn new_score_reply(score: Option<f64>) -> Object {
+    match score {
+        Some(score) => Object::Double(score),
+        None => Object::BulkString(None),
+    }
+}
+
+/// Creates the error returned when incrementing a score gives NaN.
+fn new_nan_score_error() -> Object {
+    Object::new_error(b"ERR resulting score is not a number (NaN)")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Engine, Object, run, send};
+
+    fn member(member: &str) -> Object {
+        Object::BulkString(Some(member.as_bytes().to_vec()))
+    }
+
+    #[test]
+    fn scores_are_flat_for_resp2() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["ZADD", "z", "1", "a", "2.5", "b"]);
+        let flat = vec![
+            member("a"),
+            Object::Double(1.0),
+            member("b"),
+            Object::Double(2.5),
+        ];
+        assert_eq!(
+            run(&mut engine, &["ZRANGE", "z", "0", "-1", "WITHSCORES"]),
+            Object::new_array(flat.clone())
+        );
+        assert_eq!(
+            run(&mut engine, &["ZUNION", "1", "z", "WITHSCORES"]),
+            Object::new_array(flat.clone())
+        );
+        assert_eq!(
+            run(&mut engine, &["ZPOPMIN", "z", "2"]),
+            Object::new_array(flat)
+        );
+    }
+
+    #[test]
+    fn scores_are_paired_for_resp3() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["HELLO", "3"]);
+        run(&mut engine, &["ZADD", "z", "1", "a", "2.5", "b", "3", "c"]);
+        let pair = |name, score| Object::new_array(vec![member(name), Object::Double(score)]);
+        assert_eq!(
+            run(
+                &mut engine,
+                &["ZRANGEBYSCORE", "z", "1", "2.5", "WITHSCORES"]
+            ),
+            Object::new_array(vec![pair("a", 1.0), pair("b", 2.5)])
+        );
+        assert_eq!(
+            run(&mut engine, &["ZDIFF", "1", "z", "WITHSCORES"]),
+            Object::new_array(vec![pair("a", 1.0), pair("b", 2.5), pair("c", 3.0)])
+        );
+        assert_eq!(
+            run(&mut engine, &["ZRANGE", "z", "0", "0"]),
+            Object::new_array(vec![member("a")])
+        );
+        assert_eq!(
+            run(&mut engine, &["ZPOPMAX", "z", "1"]),
+            Object::new_array(vec![pair("c", 3.0)])
+        );
+        assert_eq!(
+            run(&mut engine, &["ZPOPMIN", "z"]),
+            Object::new_array(vec![member("a"), Object::Double(1.0)])
+        );
+    }
+
+    fn members(members: &[&str]) -> Object {
+        Object::new_array(members.iter().map(|name| member(name)).collect())
+    }
+
+    #[test]
+    fn zrange_reads_lex_and_score_bounds_in_either_direction() {
+        let mut engine = Engine::new();
+        run(
+            &mut engine,
+            &[
+                "ZADD", "lex", "0", "a", "0", "b", "0", "c", "0", "d", "0", "e",
+            ],
+        );
+        run(
+            &mut engine,
+            &[
+                "ZADD", "z", "1", "a", "2", "b", "3", "c", "4", "d", "5", "e",
+            ],
+        );
+        let cases: &[(&[&str], &[&str])] = &[
+            (&["lex", "-", "+", "BYLEX"], &["a", "b", "c", "d", "e"]),
+            (&["lex", "[b", "(d", "BYLEX"], &["b", "c"]),
+            (&["lex", "(a", "[c", "BYLEX"], &["b", "c"]),
+            (&["lex", "(c", "(c", "BYLEX"], &[]),
+            (
+                &["lex", "+", "-", "BYLEX", "REV"],
+                &["e", "d", "c", "b", "a"],
+            ),
+            (
+                &["lex", "[d", "-", "BYLEX", "REV", "LIMIT", "1", "2"],
+                &["c", "b"],
+            ),
+            (&["lex", "-", "+", "BYLEX", "LIMIT", "3", "-1"], &["d", "e"]),
+            (&["z", "(1", "3", "BYSCORE"], &["b", "c"]),
+            (
+                &["z", "-inf", "+inf", "BYSCORE", "LIMIT", "4", "10"],
+                &["e"],
+            ),
+            (
+                &["z", "+inf", "-inf", "BYSCORE", "REV", "LIMIT", "1", "2"],
+                &["d", "c"],
+            ),
+            (&["z", "(5", "(1", "BYSCORE", "REV"], &["d", "c", "b"]),
+            (&["z", "0", "1", "REV"], &["e", "d"]),
+            (&["z", "-2", "-1"], &["d", "e"]),
+        ];
+        for (args, expected) in cases {
+            let command = [&["ZRANGE"][..], args].concat();
+            assert_eq!(run(&mut engine, &command), members(expected), "{args:?}");
+        }
+        assert_eq!(
+            run(
+                &mut engine,
+                &["ZRANGEBYLEX", "lex", "-", "+", "LIMIT", "1", "2"]
+            ),
+            members(&["b", "c"])
+        );
+        assert_eq!(
+            run(&mut engine, &["ZREVRANGEBYLEX", "lex", "[c", "[a"]),
+            members(&["c", "b", "a"])
+        );
+
+        assert_eq!(
+            run(&mut engine, &["ZRANGE", "lex", "b", "d", "BYLEX"]),
+            Object::new_error(b"ERR min or max not valid string range item")
+        );
+        assert_eq!(
+            run(&mut engine, &["ZRANGE", "z", "0", "1", "REV", "LIMIT", "0", "1"]),
+            Object::new_error(
+                b"ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+            )
+        );
+    }
+
+    #[test]
+    fn zrangestore_and_zremrangebyrank_use_ranks_from_either_end() {
+        let mut engine = Engine::new();
+        run(
+            &mut engine,
+            &[
+                "ZADD", "z", "1", "a", "2", "b", "3", "c", "4", "d", "5", "e",
+            ],
+        );
+        assert_eq!(
+            run(&mut engine, &["ZRANGESTORE", "dst", "z", "1", "-2"]),
+            Object::Integer(3)
+        );
+        assert_eq!(
+            run(&mut engine, &["ZRANGE", "dst", "0", "-1", "WITHSCORES"]),
+            Object::new_array(vec![
+                member("b"),
+                Object::Double(2.0),
+                member("c"),
+                Object::Double(3.0),
+                member("d"),
+                Object::Double(4.0),
+            ])
+        );
+        assert_eq!(
+            run(
+                &mut engine,
+                &["ZRANGESTORE", "dst", "z", "(3", "+inf", "BYSCORE", "REV"]
+            ),
+            Object::Integer(0)
+        );
+        assert_eq!(
+            run(
+                &mut engine,
+                &["ZRANGESTORE", "dst", "z", "+inf", "(3", "BYSCORE", "REV"]
+            ),
+            Object::Integer(2)
+        );
+        assert_eq!(
+            run(&mut engine, &["ZRANGE", "dst", "0", "-1"]),
+            members(&["d", "e"])
+        );
+        assert_eq!(
+            run(&mut engine, &["ZRANGESTORE", "dst", "z", "10", "20"]),
+            Object::Integer(0)
+        );
+        assert_eq!(run(&mut engine, &["EXISTS", "dst"]), Object::Integer(0));
+
+        let cases: &[(&str, &str, i64, &[&str])] = &[
+            ("-2", "-1", 2, &["a", "b", "c"]),
+            ("5", "10", 0, &["a", "b", "c"]),
+            ("-1", "-2", 0, &["a", "b", "c"]),
+            ("-100", "0", 1, &["b", "c"]),
+        ];
+        for &(start, stop, removed, left) in cases {
+            assert_eq!(
+                run(&mut engine, &["ZREMRANGEBYRANK", "z", start, stop]),
+                Object::Integer(removed),
+                "{start} {stop}"
+            );
+            assert_eq!(run(&mut engine, &["ZRANGE", "z", "0", "-1"]), members(left));
+        }
+        run(&mut engine, &["ZREMRANGEBYRANK", "z", "0", "-1"]);
+        assert_eq!(run(&mut engine, &["EXISTS", "z"]), Object::Integer(0));
+    }
+
+    /// The flat RESP2 reply of members with their scores.
+    fn scored(items: &[(&str, f64)]) -> Object {
+        let items = items
+            .iter()
+            .flat_map(|&(name, score)| [member(name), Object::Double(score)])
+            .collect();
+        Object::new_array(items)
+    }
+
+    #[test]
+    fn combining_applies_weights_and_aggregates() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["ZADD", "a", "1", "x", "2", "y"]);
+        run(&mut engine, &["ZADD", "b", "10", "y", "20", "z"]);
+        run(&mut engine, &["SADD", "s", "y", "w"]);
+
+        assert_eq!(
+            run(
+                &mut engine,
+                &["ZUNIONSTORE", "out", "2", "a", "b", "WEIGHTS", "2", "3"]
+            ),
+            Object::Integer(3)
+        );
+        assert_eq!(
+            run(&mut engine, &["ZRANGE", "out", "0", "-1", "WITHSCORES"]),
+            scored(&[("x", 2.0), ("y", 34.0), ("z", 60.0)])
+        );
+        // Each case is a command and the members and scores it replies with.
+        type Case = (&'static [&'static str], &'static [(&'static str, f64)]);
+        let cases: &[Case] = &[
+            (
+                &["ZUNION", "2", "a", "b", "AGGREGATE", "MAX"],
+                &[("x", 1.0), ("y", 10.0), ("z", 20.0)],
+            ),
+            (
+                &[
+                    "ZUNION",
+                    "2",
+                    "a",
+                    "b",
+                    "WEIGHTS",
+                    "1",
+                    "-1",
+                    "AGGREGATE",
+                    "min",
+                ],
+                &[("z", -20.0), ("y", -10.0), ("x", 1.0)],
+            ),
+            (
+                &["ZINTER", "2", "a", "b", "AGGREGATE", "MIN"],
+                &[("y", 2.0)],
+            ),
+            (&["ZINTER", "2", "a", "s"], &[("y", 3.0)]),
+            (&["ZINTER", "2", "a", "missing"], &[]),
+            (&["ZDIFF", "2", "a", "b"], &[("x", 1.0)]),
+            (&["ZDIFF", "2", "b", "s"], &[("z", 20.0)]),
+        ];
+        for (args, expected) in cases {
+            let command = [args, &["WITHSCORES"][..]].concat();
+            assert_eq!(run(&mut engine, &command), scored(expected), "{args:?}");
+        }
+
+        assert_eq!(
+            run(&mut engine, &["ZINTERSTORE", "out", "2", "a", "missing"]),
+            Object::Integer(0)
+        );
+        assert_eq!(run(&mut engine, &["EXISTS", "out"]), Object::Integer(0));
+        assert_eq!(
+            run(&mut engine, &["ZDIFFSTORE", "out", "2", "b", "a"]),
+            Object::Integer(1)
+        );
+
+        let syntax = Object::new_error(b"ERR syntax error");
+        for args in [
+            &["ZUNION", "2", "a", "b", "WEIGHTS", "1"][..],
+            &["ZUNION", "2", "a", "b", "AGGREGATE", "AVG"],
+            &["ZDIFF", "2", "a", "b", "WEIGHTS", "1", "1"],
+            &["ZUNIONSTORE", "out", "2", "a", "b", "WITHSCORES"],
+            &["ZUNION", "3", "a", "b"],
+        ] {
+            assert_eq!(run(&mut engine, args), syntax, "{args:?}");
+        }
+        assert_eq!(
+            run(&mut engine, &["ZUNION", "2", "a", "b", "WEIGHTS", "1", "x"]),
+            Object::new_error(b"ERR weight value is not a float")
+        );
+        assert_eq!(
+            run(&mut engine, &["ZUNION", "0", "a"]),
+            Object::new_error(b"ERR at least 1 input key is needed for 'zunion' command")
+        );
+    }
+
+    #[test]
+    fn zmpop_replies_with_the_key_and_pairs() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["ZADD", "z", "1", "a", "2", "b", "3", "c"]);
+        let pair = |name, score| Object::new_array(vec![member(name), Object::Double(score)]);
+        assert_eq!(
+            run(
+                &mut engine,
+                &["ZMPOP", "2", "missing", "z", "MAX", "COUNT", "2"]
+            ),
+            Object::new_array(vec![
+                member("z"),
+                Object::new_array(vec![pair("c", 3.0), pair("b", 2.0)]),
+            ])
+        );
+        assert_eq!(
+            run(&mut engine, &["ZMPOP", "1", "z", "MIN", "COUNT", "5"]),
+            Object::new_array(vec![member("z"), Object::new_array(vec![pair("a", 1.0)])])
+        );
+        assert_eq!(run(&mut engine, &["EXISTS", "z"]), Object::Integer(0));
+        assert_eq!(run(&mut engine, &["ZMPOP", "1", "z", "MIN"]), Object::Null);
+    }
+
+    #[test]
+    fn zadd_wakes_blocked_pops() {
+        let mut engine = Engine::new();
+        assert_eq!(send(&mut engine, 2, &["BZPOPMIN", "y", "z", "0"]), None);
+        assert_eq!(
+            send(
+                &mut engine,
+                3,
+                &["BZMPOP", "0", "1", "z", "MAX", "COUNT", "2"]
+            ),
+            None
+        );
+        assert_eq!(send(&mut engine, 4, &["BZPOPMAX", "z", "0"]), None);
+
+        // A key of another type doesn't serve the waiters.
+        assert_eq!(send(&mut engine, 5, &["BZPOPMIN", "w", "0"]), None);
+        run(&mut engine, &["RPUSH", "w", "a"]);
+        assert!(engine.take_replies().is_empty());
+        assert_eq!(engine.blocked.waiters(b"w"), vec![5]);
+
+        run(
+            &mut engine,
+            &["ZADD", "z", "1", "a", "2", "b", "3", "c", "4", "d"],
+        );
+        let pair = |name, score| Object::new_array(vec![member(name), Object::Double(score)]);
+        assert_eq!(
+            engine.take_replies(),
+            vec![
+                (
+                    2,
+                    Object::new_array(vec![member("z"), member("a"), Object::Double(1.0)])
+                ),
+                (
+                    3,
+                    Object::new_array(vec![
+                        member("z"),
+                        Object::new_array(vec![pair("d", 4.0), pair("c", 3.0)]),
+                    ])
+                ),
+                (
+                    4,
+                    Object::new_array(vec![member("z"), member("b"), Object::Double(2.0)])
+                ),
+            ]
+        );
+        assert_eq!(run(&mut engine, &["EXISTS", "z"]), Object::Integer(0));
+    }
+}
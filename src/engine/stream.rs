@@ -0,0 +1,2450 @@
+//! The stream type and its commands.
+//!
+//! A stream is an append-only log of entries, each a list of field/value
+//! pairs under a unique ID. IDs are `ms-seq`, a time in milliseconds and a
+//! sequence number within that millisecond, and only ever increase.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::time;
+
+use super::blocking::BlockOn;
+use super::listpack::ListPack;
+use super::value::Value;
+use super::{
+    Engine, Key, Object, convert_to_ascii_uppercase, new_arity_error, new_info_reply,
+    new_not_integer_error, new_syntax_error, new_unknown_subcommand_error, new_wrong_type_error,
+    parse_i64, to_unix_ms,
+};
+
+/// Approximate trimming removes entries only in blocks of this many, as
+/// Redis only removes whole nodes of its radix tree. Trimming with `~` on
+/// every add is then cheap.
+const TRIM_BLOCK_ENTRIES: usize = 100;
+
+/// The most entries approximate trimming removes without a `LIMIT`.
+const TRIM_DEFAULT_LIMIT: usize = 100 * TRIM_BLOCK_ENTRIES;
+
+/// How many pending entries `XAUTOCLAIM` claims without a `COUNT`.
+const XAUTOCLAIM_DEFAULT_COUNT: usize = 100;
+
+/// `XAUTOCLAIM` looks at up to this many pending entries per entry it may
+/// claim.
+const XAUTOCLAIM_ATTEMPTS_FACTOR: usize = 10;
+
+/// How many entries and pending entries `XINFO STREAM FULL` shows without a
+/// `COUNT`.
+const XINFO_FULL_DEFAULT_COUNT: usize = 10;
+
+/// The ID of a stream entry.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct StreamId {
+    ms: u64,
+    seq: u64,
+}
+
+impl StreamId {
+    const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// Parses `ms-seq`, or `ms` alone with the given sequence number.
+    fn parse(s: &[u8], default_seq: u64) -> Option<StreamId> {
+        match s.iter().position(|&b| b == b'-') {
+            Some(i) => Some(StreamId {
+                ms: parse_u64(&s[..i])?,
+                seq: parse_u64(&s[i + 1..])?,
+            }),
+            None => Some(StreamId {
+                ms: parse_u64(s)?,
+                seq: default_seq,
+            }),
+        }
+    }
+
+    /// Parses one end of a range, which is `-` or `+` for the lowest or
+    /// highest ID, and may start with `(` to exclude the ID itself. A start
+    /// without a sequence number starts at the first in its millisecond,
+    /// and an end ends at the last.
+    fn parse_bound(s: &[u8], is_start: bool) -> Result<StreamId, Object> {
+        match s {
+            b"-" => return Ok(StreamId::MIN),
+            b"+" => return Ok(StreamId::MAX),
+            _ => (),
+        }
+
+        let default_seq = if is_start { 0 } else { u64::MAX };
+        let Some(rest) = s.strip_prefix(b"(") else {
+            return StreamId::parse(s, default_seq).ok_or_else(new_invalid_id_error);
+        };
+
+        let id = StreamId::parse(rest, default_seq).ok_or_else(new_invalid_id_error)?;
+        let id = if is_start { id.next() } else { id.previous() };
+        id.ok_or_else(|| {
+            let message: &[u8] = if is_start {
+                b"ERR invalid start ID for the interval"
+            } else {
+                b"ERR invalid end ID for the interval"
+            };
+            Object::new_error(message)
+        })
+    }
+
+    /// Returns the ID right after this one.
+    fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => Some(StreamId {
+                ms: self.ms.checked_add(1)?,
+                seq: 0,
+            }),
+        }
+    }
+
+    /// Returns the ID right before this one.
+    fn previous(self) -> Option<StreamId> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => Some(StreamId {
+                ms: self.ms.checked_sub(1)?,
+                seq: u64::MAX,
+            }),
+        }
+    }
+
+    fn to_object(self) -> Object {
+        Object::BulkString(Some(self.to_string().into_bytes()))
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// An append-only log of entries ordered by ID.
+#[derive(Clone, Debug)]
+pub struct Stream {
+    /// The field/value pairs of each entry, packed one after the other.
+    entries: BTreeMap<StreamId, ListPack>,
+
+    /// The ID of the last entry ever added, which may since be deleted.
+    last_id: StreamId,
+
+    /// The highest ID removed by `XDEL`, which leaves a gap in the count of
+    /// entries added so far.
+    max_deleted_id: StreamId,
+
+    /// The number of entries ever added.
+    entries_added: u64,
+
+    /// Consumer groups by name.
+    groups: BTreeMap<Vec<u8>, ConsumerGroup>,
+}
+
+/// A consumer group, which hands each entry to one of its consumers and
+/// tracks the entries delivered but not yet acknowledged.
+#[derive(Clone, Debug)]
+struct ConsumerGroup {
+    /// The ID of the last entry delivered to any consumer.
+    last_id: StreamId,
+
+    /// How many entries the group has read, when known, for working out its
+    /// lag.
+    entries_read: Option<u64>,
+
+    /// The pending entries list, of entries delivered but not acknowledged.
+    pending: BTreeMap<StreamId, PendingEntry>,
+
+    /// Consumers by name.
+    consumers: BTreeMap<Vec<u8>, Consumer>,
+}
+
+/// An entry delivered to a consumer but not acknowledged.
+#[derive(Clone, Debug)]
+struct PendingEntry {
+    consumer: Vec<u8>,
+
+    /// When the entry was last delivered, in milliseconds since the Unix
+    /// epoch.
+    delivery_time: i64,
+
+    /// How many times the entry was delivered.
+    delivery_count: u64,
+}
+
+/// A consumer in a consumer group.
+#[derive(Clone, Debug)]
+struct Consumer {
+    /// When the consumer last tried to read or claim entries, in
+    /// milliseconds since the Unix epoch.
+    seen_time: i64,
+
+    /// When the consumer last read or claimed any entries, if ever.
+    active_time: Option<i64>,
+
+    /// The IDs of its pending entries.
+    pending: BTreeSet<StreamId>,
+}
+
+/// Where to read a stream from for `XREAD` and `XREADGROUP`.
+#[derive(Clone, Copy)]
+enum ReadFrom {
+    /// The entries after an ID, or for a consumer group the consumer's
+    /// pending entries after an ID.
+    After(StreamId),
+
+    /// The entries never delivered to the consumer group, given as `>`.
+    New,
+}
+
+impl Stream {
+    /// Creates a new empty stream.
+    pub fn new() -> Self {
+        Stream {
+            entries: BTreeMap::new(),
+            last_id: StreamId::MIN,
+            max_deleted_id: StreamId::MIN,
+            entries_added: 0,
+            groups: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Works out the ID of a new entry from the ID given to `XADD`, which
+    /// is `*` to generate one from the current time, `ms-*` to generate the
+    /// sequence number only, or an explicit ID.
+    fn next_id(&self, id: &[u8]) -> Result<StreamId, Object> {
+        if id == b"*" {
+            let now = now_ms().max(0) as u64;
+            return if now > self.last_id.ms {
+                Ok(StreamId { ms: now, seq: 0 })
+            } else {
+                self.last_id.next().ok_or_else(|| {
+                    Object::new_error(
+                        b"ERR The stream has exhausted the last possible ID, unable to add more items",
+                    )
+                })
+            };
+        }
+
+        let id = match id.strip_suffix(b"-*") {
+            Some(ms) => {
+                let ms = parse_u64(ms).ok_or_else(new_invalid_id_error)?;
+                match self.last_id.seq.checked_add(1) {
+                    Some(seq) if ms == self.last_id.ms => StreamId { ms, seq },
+                    _ => StreamId { ms, seq: 0 },
+                }
+            }
+            None => StreamId::parse(id, 0).ok_or_else(new_invalid_id_error)?,
+        };
+
+        if id == StreamId::MIN {
+            return Err(Object::new_error(
+                b"ERR The ID specified in XADD must be greater than 0-0",
+            ));
+        }
+        if id <= self.last_id {
+            return Err(Object::new_error(
+                b"ERR The ID specified in XADD is equal or smaller than the target stream top item",
+            ));
+        }
+        Ok(id)
+    }
+
+    /// Adds an entry, whose ID must be greater than any before.
+    fn add(&mut self, id: StreamId, fields: VecDeque<Vec<u8>>) {
+        let mut pairs = ListPack::new();
+        for item in fields.iter() {
+            pairs.push_back(item);
+        }
+        self.entries.insert(id, pairs);
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    /// Removes an entry. Returns whether it was in the stream.
+    fn remove(&mut self, id: StreamId) -> bool {
+        if self.entries.remove(&id).is_none() {
+            return false;
+        }
+        self.max_deleted_id = self.max_deleted_id.max(id);
+        true
+    }
+
+    /// Returns the ID of the first entry, or 0-0 when there are none.
+    fn first_id(&self) -> StreamId {
+        self.entries
+            .first_key_value()
+            .map_or(StreamId::MIN, |(id, _)| *id)
+    }
+
+    /// Returns whether an entry at or after an ID was removed by `XDEL`.
+    fn has_tombstones_from(&self, start: StreamId) -> bool {
+        !self.entries.is_empty()
+            && self.max_deleted_id != StreamId::MIN
+            && start <= self.max_deleted_id
+    }
+
+    /// Returns how many entries were ever added up to an ID, when that can
+    /// be known without counting, in the same manner as Redis.
+    fn entries_added_until(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 || id == self.last_id {
+            return Some(self.entries_added);
+        }
+        if self.entries.is_empty() && id < self.last_id {
+            return Some(self.entries_added);
+        }
+        if id > self.last_id {
+            return None;
+        }
+
+        // Without gaps from `XDEL`, entries before the first were all
+        // trimmed away.
+        let first_id = self.first_id();
+        if self.max_deleted_id == StreamId::MIN || self.max_deleted_id < first_id {
+            let trimmed = self.entries_added - self.len() as u64;
+            if id < first_id {
+                return Some(trimmed);
+            }
+            if id == first_id {
+                return Some(trimmed + 1);
+            }
+        }
+        None
+    }
+
+    /// Returns how many entries a consumer group has yet to read, when that
+    /// can be known.
+    fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        let entries_read = match group.entries_read {
+            Some(entries_read) if !self.has_tombstones_from(group.last_id) => Some(entries_read),
+            _ => self.entries_added_until(group.last_id),
+        };
+        entries_read.map(|entries_read| self.entries_added.saturating_sub(entries_read))
+    }
+
+    /// Reads entries never delivered to a consumer group, up to `count`,
+    /// delivering them to a consumer.
+    fn read_new(
+        &mut self,
+        group_name: &[u8],
+        consumer: &[u8],
+        count: Option<usize>,
+        is_noack: bool,
+        now: i64,
+    ) -> Vec<Object> {
+        // Take the group out so the stream can be looked at while updating
+        // it.
+        let Some(mut group) = self.groups.remove(group_name) else {
+            return Vec::new();
+        };
+        group.see_consumer(consumer, now);
+
+        let ids: Vec<StreamId> = match group.last_id.next() {
+            Some(start) => self
+                .entries
+                .range(start..)
+                .take(count.unwrap_or(usize::MAX))
+                .map(|(id, _)| *id)
+                .collect(),
+            None => Vec::new(),
+        };
+        for id in ids.iter().copied() {
+            if group.entries_read.is_some() && !self.has_tombstones_from(id) {
+                group.entries_read = group.entries_read.map(|entries_read| entries_read + 1);
+            } else if self.entries_added > 0 {
+                group.entries_read = self.entries_added_until(id);
+            }
+            group.last_id = id;
+            if !is_noack {
+                group.assign(id, consumer, now).delivery_count = 1;
+            }
+        }
+        if !ids.is_empty() {
+            group.see_consumer(consumer, now).active_time = Some(now);
+        }
+        self.groups.insert(group_name.to_vec(), group);
+
+        ids.iter()
+            .filter_map(|id| self.entries.get_key_value(id))
+            .map(new_entry_reply)
+            .collect()
+    }
+
+    /// Reads the pending entries of a consumer after an ID, up to `count`,
+    /// delivering them again. Entries since deleted have no fields.
+    fn read_history(
+        &mut self,
+        group_name: &[u8],
+        consumer: &[u8],
+        after: StreamId,
+        count: Option<usize>,
+        now: i64,
+    ) -> Vec<Object> {
+        let Some(group) = self.groups.get_mut(group_name) else {
+            return Vec::new();
+        };
+        let Some(start) = after.next() else {
+            return Vec::new();
+        };
+
+        let ids: Vec<StreamId> = group
+            .see_consumer(consumer, now)
+            .pending
+            .range(start..)
+            .take(count.unwrap_or(usize::MAX))
+            .copied()
+            .collect();
+
+        let mut items = Vec::with_capacity(ids.len());
+        for id in ids {
+            match self.entries.get_key_value(&id) {
+                Some(entry) => {
+                    items.push(new_entry_reply(entry));
+                    if let Some(pending) = group.pending.get_mut(&id) {
+                        pending.delivery_time = now;
+                        pending.delivery_count += 1;
+                    }
+                }
+                None => items.push(Object::new_array(vec![
+                    id.to_object(),
+                    Object::BulkString(None),
+                ])),
+            }
+        }
+        items
+    }
+
+    /// Removes the oldest entries as the trimming options say. Returns the
+    /// number of entries removed.
+    fn trim(&mut self, trim: &Trim) -> usize {
+        let mut count = match trim.threshold {
+            Threshold::MaxLen(max_len) => self.len().saturating_sub(max_len),
+            Threshold::MinId(min_id) => self.entries.range(..min_id).count(),
+        };
+        if trim.is_approximate {
+            if trim.limit > 0 {
+                count = count.min(trim.limit);
+            }
+            count -= count % TRIM_BLOCK_ENTRIES;
+        }
+
+        for _ in 0..count {
+            self.entries.pop_first();
+        }
+        count
+    }
+
+    /// Returns the entries with IDs in an inclusive range, from the lowest
+    /// ID or, when reversed, from the highest.
+    fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+        is_reversed: bool,
+        count: Option<usize>,
+    ) -> Vec<Object> {
+        if start > end {
+            return Vec::new();
+        }
+        let count = count.unwrap_or(usize::MAX);
+        let range = self.entries.range(start..=end);
+        if is_reversed {
+            range.rev().take(count).map(new_entry_reply).collect()
+        } else {
+            range.take(count).map(new_entry_reply).collect()
+        }
+    }
+}
+
+impl ConsumerGroup {
+    fn new(last_id: StreamId, entries_read: Option<u64>) -> Self {
+        ConsumerGroup {
+            last_id,
+            entries_read,
+            pending: BTreeMap::new(),
+            consumers: BTreeMap::new(),
+        }
+    }
+
+    /// Returns a consumer, creating it if needed, and records that it was
+    /// seen.
+    fn see_consumer(&mut self, name: &[u8], now: i64) -> &mut Consumer {
+        let consumer = self
+            .consumers
+            .entry(name.to_vec())
+            .or_insert_with(|| Consumer {
+                seen_time: now,
+                active_time: None,
+                pending: BTreeSet::new(),
+            });
+        consumer.seen_time = now;
+        consumer
+    }
+
+    /// Makes a consumer, which must exist, the owner of an entry as of a
+    /// delivery time, adding the entry to the pending entries if needed.
+    fn assign(&mut self, id: StreamId, consumer: &[u8], delivery_time: i64) -> &mut PendingEntry {
+        let pending = self.pending.entry(id).or_insert_with(|| PendingEntry {
+            consumer: consumer.to_vec(),
+            delivery_time,
+            delivery_count: 0,
+        });
+        if pending.consumer != consumer {
+            if let Some(owner) = self.consumers.get_mut(&pending.consumer) {
+                owner.pending.remove(&id);
+            }
+            pending.consumer = consumer.to_vec();
+        }
+        pending.delivery_time = delivery_time;
+        if let Some(owner) = self.consumers.get_mut(consumer) {
+            owner.pending.insert(id);
+        }
+        pending
+    }
+
+    /// Removes a pending entry. Returns whether it was pending.
+    fn acknowledge(&mut self, id: StreamId) -> bool {
+        let Some(pending) = self.pending.remove(&id) else {
+            return false;
+        };
+        if let Some(owner) = self.consumers.get_mut(&pending.consumer) {
+            owner.pending.remove(&id);
+        }
+        true
+    }
+
+    /// Removes a consumer and its pending entries. Returns how many pending
+    /// entries it had, or `None` if there's no such consumer.
+    fn remove_consumer(&mut self, name: &[u8]) -> Option<usize> {
+        let consumer = self.consumers.remove(name)?;
+        for id in consumer.pending.iter() {
+            self.pending.remove(id);
+        }
+        Some(consumer.pending.len())
+    }
+}
+
+/// What trimming keeps.
+#[derive(Clone, Copy)]
+enum Threshold {
+    /// Keep at most this many entries.
+    MaxLen(usize),
+
+    /// Keep entries with at least this ID.
+    MinId(StreamId),
+}
+
+/// The trimming options of `XADD` and `XTRIM`:
+/// `MAXLEN|MINID [=|~] threshold [LIMIT count]`.
+struct Trim {
+    threshold: Threshold,
+    is_approximate: bool,
+
+    /// The most entries approximate trimming removes, or 0 for no limit.
+    limit: usize,
+}
+
+impl Trim {
+    /// Parses the trimming options following `MAXLEN` or `MINID`.
+    fn parse(strategy: &[u8], args: &mut VecDeque<Vec<u8>>) -> Result<Trim, Object> {
+        let is_approximate = match args.front().map(|arg| arg.as_slice()) {
+            Some(b"~") => {
+                args.pop_front();
+                true
+            }
+            Some(b"=") => {
+                args.pop_front();
+                false
+            }
+            _ => false,
+        };
+
+        let Some(threshold) = args.pop_front() else {
+            return Err(new_syntax_error());
+        };
+        let threshold = if strategy == b"MAXLEN" {
+            match parse_i64(&threshold) {
+                Some(max_len) if max_len >= 0 => Threshold::MaxLen(max_len as usize),
+                _ => {
+                    return Err(Object::new_error(b"ERR The MAXLEN argument must be >= 0."));
+                }
+            }
+        } else {
+            Threshold::MinId(StreamId::parse(&threshold, 0).ok_or_else(new_invalid_id_error)?)
+        };
+
+        let mut limit = if is_approximate {
+            TRIM_DEFAULT_LIMIT
+        } else {
+            0
+        };
+        if args
+            .front()
+            .is_some_and(|arg| arg.eq_ignore_ascii_case(b"LIMIT"))
+        {
+            args.pop_front();
+            limit = match args.pop_front().map(|limit| parse_i64(&limit)) {
+                Some(Some(limit)) if limit >= 0 => limit as usize,
+                Some(_) => {
+                    return Err(Object::new_error(b"ERR The LIMIT argument must be >= 0."));
+                }
+                None => return Err(new_syntax_error()),
+            };
+            if !is_approximate {
+                return Err(Object::new_error(
+                    b"ERR syntax error, LIMIT cannot be used without the special ~ option",
+                ));
+            }
+        }
+
+        Ok(Trim {
+            threshold,
+            is_approximate,
+            limit,
+        })
+    }
+}
+
+impl Engine {
+    /// Do an `XADD` command.
+    pub(super) fn do_xadd(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error("xadd");
+        };
+
+        let mut no_mkstream = false;
+        let mut trim = None;
+        while let Some(option) = elements.front() {
+            let mut option = option.clone();
+            convert_to_ascii_uppercase(&mut option);
+            match option.as_slice() {
+                b"NOMKSTREAM" => {
+                    elements.pop_front();
+                    no_mkstream = true;
+                }
+                b"MAXLEN" | b"MINID" => {
+                    elements.pop_front();
+                    trim = match Trim::parse(&option, &mut elements) {
+                        Ok(trim) => Some(trim),
+                        Err(e) => return e,
+                    };
+                }
+                _ => break,
+            }
+        }
+
+        let Some(id) = elements.pop_front() else {
+            return new_arity_error("xadd");
+        };
+        if elements.is_empty() || !elements.len().is_multiple_of(2) {
+            return new_arity_error("xadd");
+        }
+
+        // Work out the ID before creating the key, as it may be invalid.
+        let id = match self.get_entry(&key).map(|entry| &entry.value) {
+            Some(Value::Stream(stream)) => stream.next_id(&id),
+            Some(_) => return new_wrong_type_error(),
+            None if no_mkstream => return Object::BulkString(None),
+            None => Stream::new().next_id(&id),
+        };
+        let id = match id {
+            Ok(id) => id,
+            Err(e) => return e,
+        };
+
+        let entry = self.get_entry_or_insert(key.clone(), Value::Stream(Stream::new()));
+
+        let Value::Stream(stream) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        stream.add(id, elements);
+        if let Some(trim) = trim {
+            stream.trim(&trim);
+        }
+        self.blocked.signal_key_ready(&key);
+
+        id.to_object()
+    }
+
+    /// Do an `XRANGE` or `XREVRANGE` command. Reversed ranges are given end
+    /// first.
+    pub(super) fn do_xrange(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        is_reversed: bool,
+    ) -> Object {
+        let (Some(key), Some(first), Some(second)) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error(name);
+        };
+
+        let (start, end) = if is_reversed {
+            (second, first)
+        } else {
+            (first, second)
+        };
+        let start = match StreamId::parse_bound(&start, true) {
+            Ok(start) => start,
+            Err(e) => return e,
+        };
+        let end = match StreamId::parse_bound(&end, false) {
+            Ok(end) => end,
+            Err(e) => return e,
+        };
+
+        let count = match (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) {
+            (None, _, _) => None,
+            (Some(option), Some(count), None) if option.eq_ignore_ascii_case(b"COUNT") => {
+                match parse_i64(&count) {
+                    Some(count) => Some(count.max(0) as usize),
+                    None => return new_not_integer_error(),
+                }
+            }
+            _ => return new_syntax_error(),
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::new_array(Vec::new());
+        };
+
+        let Value::Stream(stream) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        Object::new_array(stream.range(start, end, is_reversed, count))
+    }
+
+    /// Do an `XREAD` or `XREADGROUP` command, reading the entries after the
+    /// given IDs from each stream, or blocking until one of the streams has
+    /// some when asked to. A consumer group instead reads entries never
+    /// delivered to the group, or its consumer's pending entries.
+    pub(super) fn do_xread(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        is_group: bool,
+    ) -> Result<Object, BlockOn> {
+        let mut args = elements.clone();
+        let mut count = None;
+        let mut timeout = None;
+        let mut is_blocking = false;
+        let mut group = None;
+        let mut is_noack = false;
+        loop {
+            let Some(mut option) = args.pop_front() else {
+                return Ok(new_arity_error(name));
+            };
+            convert_to_ascii_uppercase(&mut option);
+            match option.as_slice() {
+                b"COUNT" => {
+                    let Some(value) = args.pop_front().and_then(|value| parse_i64(&value)) else {
+                        return Ok(new_not_integer_error());
+                    };
+                    // A count of zero or less doesn't limit anything.
+                    count = (value > 0).then_some(value as usize);
+                }
+                b"BLOCK" => {
+                    let Some(value) = args.pop_front().and_then(|value| parse_i64(&value)) else {
+                        return Ok(Object::new_error(
+                            b"ERR timeout is not an integer or out of range",
+                        ));
+                    };
+                    if value < 0 {
+                        return Ok(Object::new_error(b"ERR timeout is negative"));
+                    }
+                    // A timeout of zero means to wait forever.
+                    timeout = (value > 0).then(|| time::Duration::from_millis(value as u64));
+                    is_blocking = true;
+                }
+                b"GROUP" if is_group => {
+                    let (Some(group_name), Some(consumer)) = (args.pop_front(), args.pop_front())
+                    else {
+                        return Ok(new_syntax_error());
+                    };
+                    group = Some((group_name, consumer));
+                }
+                b"GROUP" => {
+                    return Ok(Object::new_error(
+                        b"ERR The GROUP option is only supported by XREADGROUP. You called XREAD instead.",
+                    ));
+                }
+                b"NOACK" if is_group => is_noack = true,
+                b"STREAMS" => break,
+                _ => return Ok(new_syntax_error()),
+            }
+        }
+
+        if is_group && group.is_none() {
+            return Ok(Object::new_error(
+                b"ERR Missing GROUP option for XREADGROUP",
+            ));
+        }
+        if args.is_empty() || !args.len().is_multiple_of(2) {
+            let message = format!(
+                "ERR Unbalanced '{name}' list of streams: for each stream key an ID or '{}' must be specified.",
+                if is_group { ">" } else { "$" },
+            );
+            return Ok(Object::new_error(message.as_bytes()));
+        }
+        let ids = args.split_off(args.len() / 2);
+        let keys: Vec<Key> = args.into();
+
+        // Resolve `$` and `+` against the streams as they are now, so that
+        // running the command again after blocking reads what came since.
+        let mut positions = Vec::with_capacity(ids.len());
+        for (key, id) in keys.iter().zip(ids.iter()) {
+            let stream = match self.get_entry(key).map(|entry| &entry.value) {
+                Some(Value::Stream(stream)) => Some(stream),
+                Some(_) => return Ok(new_wrong_type_error()),
+                None => None,
+            };
+            if let Some((group_name, _)) = &group
+                && !stream.is_some_and(|stream| stream.groups.contains_key(group_name))
+            {
+                let message = format!(
+                    "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                    String::from_utf8_lossy(key),
+                    String::from_utf8_lossy(group_name),
+                );
+                return Ok(Object::new_error(message.as_bytes()));
+            }
+
+            let last_id = stream.map_or(StreamId::MIN, |stream| stream.last_id);
+            let position = match id.as_slice() {
+                b">" if is_group => ReadFrom::New,
+                b">" => {
+                    return Ok(Object::new_error(
+                        b"ERR The > ID can be specified only when calling XREADGROUP using the GROUP <group> <consumer> option.",
+                    ));
+                }
+                b"$" if is_group => {
+                    return Ok(Object::new_error(
+                        b"ERR The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set.",
+                    ));
+                }
+                b"$" => ReadFrom::After(last_id),
+                // Read the last entry, or what comes next if there is none.
+                b"+" if !is_group => {
+                    match stream.and_then(|stream| stream.entries.last_key_value()) {
+                        Some((id, _)) => ReadFrom::After(id.previous().unwrap_or(StreamId::MIN)),
+                        None => ReadFrom::After(last_id),
+                    }
+                }
+                id => match StreamId::parse(id, 0) {
+                    Some(id) => ReadFrom::After(id),
+                    None => return Ok(new_invalid_id_error()),
+                },
+            };
+            positions.push(position);
+        }
+
+        let now = now_ms();
+        let mut items = Vec::new();
+        for (key, position) in keys.iter().zip(positions.iter()) {
+            let Some(Value::Stream(stream)) = self.get_entry_mut(key).map(|entry| &mut entry.value)
+            else {
+                continue;
+            };
+            // A consumer's pending entries are always replied, even if none.
+            let (entries, is_history) = match (&group, *position) {
+                (Some((group_name, consumer)), ReadFrom::New) => (
+                    stream.read_new(group_name, consumer, count, is_noack, now),
+                    false,
+                ),
+                (Some((group_name, consumer)), ReadFrom::After(id)) => (
+                    stream.read_history(group_name, consumer, id, count, now),
+                    true,
+                ),
+                (None, ReadFrom::After(id)) => match id.next() {
+                    Some(start) => (stream.range(start, StreamId::MAX, false, count), false),
+                    None => (Vec::new(), false),
+                },
+                (None, ReadFrom::New) => (Vec::new(), false),
+            };
+            if !entries.is_empty() || is_history {
+                items.push(Object::new_array(vec![
+                    Object::BulkString(Some(key.clone())),
+                    Object::new_array(entries),
+                ]));
+            }
+        }
+
+        if !items.is_empty() {
+            return Ok(Object::new_array(items));
+        }
+        if !is_blocking {
+            return Ok(Object::Null);
+        }
+
+        let first_id = elements.len() - positions.len();
+        for (element, position) in elements.iter_mut().skip(first_id).zip(positions) {
+            if let ReadFrom::After(id) = position {
+                *element = id.to_string().into_bytes();
+            }
+        }
+        Err(BlockOn {
+            elements,
+            keys,
+            timeout,
+        })
+    }
+
+    /// Do an `XLEN` command.
+    pub(super) fn do_xlen(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), None) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("xlen");
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::Integer(0);
+        };
+
+        let Value::Stream(stream) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        Object::Integer(stream.len() as i64)
+    }
+
+    /// Do an `XDEL` command.
+    pub(super) fn do_xdel(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error("xdel");
+        };
+
+        if elements.is_empty() {
+            return new_arity_error("xdel");
+        }
+
+        // Parse every ID before deleting anything.
+        let mut ids = Vec::with_capacity(elements.len());
+        for id in elements.iter() {
+            let Some(id) = StreamId::parse(id, 0) else {
+                return new_invalid_id_error();
+            };
+            ids.push(id);
+        }
+
+        let Some(entry) = self.get_entry_mut(&key) else {
+            return Object::Integer(0);
+        };
+
+        let Value::Stream(stream) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let removed = ids.into_iter().filter(|id| stream.remove(*id)).count();
+
+        Object::Integer(removed as i64)
+    }
+
+    /// Do an `XTRIM` command.
+    pub(super) fn do_xtrim(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(mut strategy)) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("xtrim");
+        };
+
+        convert_to_ascii_uppercase(&mut strategy);
+        if strategy != b"MAXLEN" && strategy != b"MINID" {
+            return new_syntax_error();
+        }
+
+        let trim = match Trim::parse(&strategy, &mut elements) {
+            Ok(trim) => trim,
+            Err(e) => return e,
+        };
+        if !elements.is_empty() {
+            return new_syntax_error();
+        }
+
+        let Some(entry) = self.get_entry_mut(&key) else {
+            return Object::Integer(0);
+        };
+
+        let Value::Stream(stream) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        Object::Integer(stream.trim(&trim) as i64)
+    }
+
+    /// Do an `XGROUP` command.
+    pub(super) fn do_xgroup(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(mut subcommand) = elements.pop_front() else {
+            return new_arity_error("xgroup");
+        };
+
+        convert_to_ascii_uppercase(&mut subcommand);
+        match subcommand.as_slice() {
+            b"CREATE" => self.do_xgroup_create(elements),
+            b"SETID" => self.do_xgroup_setid(elements),
+            b"DESTROY" => self.do_xgroup_destroy(elements),
+            b"CREATECONSUMER" => self.do_xgroup_createconsumer(elements),
+            b"DELCONSUMER" => self.do_xgroup_delconsumer(elements),
+            _ => new_unknown_subcommand_error(&subcommand, "XGROUP"),
+        }
+    }
+
+    /// Do an `XGROUP CREATE` command.
+    fn do_xgroup_create(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(group_name), Some(id)) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("xgroup|create");
+        };
+
+        let mut is_mkstream = false;
+        let mut entries_read = None;
+        while let Some(mut option) = elements.pop_front() {
+            convert_to_ascii_uppercase(&mut option);
+            match option.as_slice() {
+                b"MKSTREAM" => is_mkstream = true,
+                b"ENTRIESREAD" => {
+                    entries_read = match parse_entries_read(elements.pop_front()) {
+                        Ok(entries_read) => entries_read,
+                        Err(e) => return e,
+                    };
+                }
+                _ => return new_syntax_error(),
+            }
+        }
+
+        // `$` is the last ID of the stream, which may not exist yet.
+        let id = match id.as_slice() {
+            b"$" => None,
+            id => match StreamId::parse(id, 0) {
+                Some(id) => Some(id),
+                None => return new_invalid_id_error(),
+            },
+        };
+
+        match self.get_entry(&key).map(|entry| &entry.value) {
+            Some(Value::Stream(_)) => (),
+            Some(_) => return new_wrong_type_error(),
+            None if is_mkstream => (),
+            None => return new_xgroup_no_key_error(),
+        }
+
+        let entry = self.get_entry_or_insert(key, Value::Stream(Stream::new()));
+
+        let Value::Stream(stream) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        if stream.groups.contains_key(&group_name) {
+            return Object::new_error(b"BUSYGROUP Consumer Group name already exists");
+        }
+
+        let id = id.unwrap_or(stream.last_id);
+        stream
+            .groups
+            .insert(group_name, ConsumerGroup::new(id, entries_read));
+
+        Object::new_simple_string(b"OK")
+    }
+
+    /// Do an `XGROUP SETID` command.
+    fn do_xgroup_setid(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(group_name), Some(id)) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("xgroup|setid");
+        };
+
+        let entries_read = match (elements.pop_front(), elements.pop_front()) {
+            (None, _) => None,
+            (Some(option), entries_read) if option.eq_ignore_ascii_case(b"ENTRIESREAD") => {
+                match parse_entries_read(entries_read) {
+                    Ok(entries_read) => entries_read,
+                    Err(e) => return e,
+                }
+            }
+            _ => return new_syntax_error(),
+        };
+        if !elements.is_empty() {
+            return new_syntax_error();
+        }
+
+        let stream = match self.get_stream_for_xgroup(&key) {
+            Ok(stream) => stream,
+            Err(e) => return e,
+        };
+
+        let id = match id.as_slice() {
+            b"$" => stream.last_id,
+            id => match StreamId::parse(id, 0) {
+                Some(id) => id,
+                None => return new_invalid_id_error(),
+            },
+        };
+
+        let Some(group) = stream.groups.get_mut(&group_name) else {
+            return new_no_group_error(&key, &group_name);
+        };
+
+        group.last_id = id;
+        group.entries_read = entries_read;
+
+        Object::new_simple_string(b"OK")
+    }
+
+    /// Do an `XGROUP DESTROY` command.
+    fn do_xgroup_destroy(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(group_name), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("xgroup|destroy");
+        };
+
+        let stream = match self.get_stream_for_xgroup(&key) {
+            Ok(stream) => stream,
+            Err(e) => return e,
+        };
+
+        let is_removed = stream.groups.remove(&group_name).is_some();
+
+        Object::Integer(is_removed as i64)
+    }
+
+    /// Do an `XGROUP CREATECONSUMER` command.
+    fn do_xgroup_createconsumer(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(group_name), Some(consumer), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("xgroup|createconsumer");
+        };
+
+        let stream = match self.get_stream_for_xgroup(&key) {
+            Ok(stream) => stream,
+            Err(e) => return e,
+        };
+
+        let Some(group) = stream.groups.get_mut(&group_name) else {
+            return new_no_group_error(&key, &group_name);
+        };
+
+        let is_new = !group.consumers.contains_key(&consumer);
+        if is_new {
+            group.see_consumer(&consumer, now_ms());
+        }
+
+        Object::Integer(is_new as i64)
+    }
+
+    /// Do an `XGROUP DELCONSUMER` command.
+    fn do_xgroup_delconsumer(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(group_name), Some(consumer), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("xgroup|delconsumer");
+        };
+
+        let stream = match self.get_stream_for_xgroup(&key) {
+            Ok(stream) => stream,
+            Err(e) => return e,
+        };
+
+        let Some(group) = stream.groups.get_mut(&group_name) else {
+            return new_no_group_error(&key, &group_name);
+        };
+
+        let pending = group.remove_consumer(&consumer).unwrap_or(0);
+
+        Object::Integer(pending as i64)
+    }
+
+    /// Do an `XACK` command.
+    pub(super) fn do_xack(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(group_name)) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("xack");
+        };
+
+        if elements.is_empty() {
+            return new_arity_error("xack");
+        }
+
+        // Parse every ID before acknowledging anything.
+        let mut ids = Vec::with_capacity(elements.len());
+        for id in elements.iter() {
+            let Some(id) = StreamId::parse(id, 0) else {
+                return new_invalid_id_error();
+            };
+            ids.push(id);
+        }
+
+        let Some(entry) = self.get_entry_mut(&key) else {
+            return Object::Integer(0);
+        };
+
+        let Value::Stream(stream) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let Some(group) = stream.groups.get_mut(&group_name) else {
+            return Object::Integer(0);
+        };
+
+        let acknowledged = ids.into_iter().filter(|id| group.acknowledge(*id)).count();
+
+        Object::Integer(acknowledged as i64)
+    }
+
+    /// Do an `XPENDING` command, which summarizes a consumer group's pending
+    /// entries or, given a range, lists them.
+    pub(super) fn do_xpending(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(group_name)) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("xpending");
+        };
+
+        let mut range = None;
+        if !elements.is_empty() {
+            let mut min_idle = 0;
+            if elements
+                .front()
+                .is_some_and(|option| option.eq_ignore_ascii_case(b"IDLE"))
+            {
+                elements.pop_front();
+                min_idle = match elements.pop_front().map(|idle| parse_i64(&idle)) {
+                    Some(Some(idle)) => idle,
+                    Some(None) => return new_not_integer_error(),
+                    None => return new_syntax_error(),
+                };
+            }
+
+            let (Some(start), Some(end), Some(count)) = (
+                elements.pop_front(),
+                elements.pop_front(),
+                elements.pop_front(),
+            ) else {
+                return new_syntax_error();
+            };
+            let consumer = elements.pop_front();
+            if !elements.is_empty() {
+                return new_syntax_error();
+            }
+
+            let start = match StreamId::parse_bound(&start, true) {
+                Ok(start) => start,
+                Err(e) => return e,
+            };
+            let end = match StreamId::parse_bound(&end, false) {
+                Ok(end) => end,
+                Err(e) => return e,
+            };
+            let Some(count) = parse_i64(&count) else {
+                return new_not_integer_error();
+            };
+            range = Some((min_idle, start, end, count.max(0) as usize, consumer));
+        }
+
+        let group = match self.get_stream_group(&key, &group_name) {
+            Ok(group) => group,
+            Err(e) => return e,
+        };
+
+        let Some((min_idle, start, end, count, consumer)) = range else {
+            let (Some((first, _)), Some((last, _))) = (
+                group.pending.first_key_value(),
+                group.pending.last_key_value(),
+            ) else {
+                return Object::new_array(vec![
+                    Object::Integer(0),
+                    Object::BulkString(None),
+                    Object::BulkString(None),
+                    Object::BulkString(None),
+                ]);
+            };
+            let consumers = group
+                .consumers
+                .iter()
+                .filter(|(_, consumer)| !consumer.pending.is_empty())
+                .map(|(name, consumer)| {
+                    Object::new_array(vec![
+                        Object::BulkString(Some(name.clone())),
+                        Object::BulkString(Some(consumer.pending.len().to_string().into_bytes())),
+                    ])
+                })
+                .collect();
+            return Object::new_array(vec![
+                Object::Integer(group.pending.len() as i64),
+                first.to_object(),
+                last.to_object(),
+                Object::new_array(consumers),
+            ]);
+        };
+
+        if start > end {
+            return Object::new_array(Vec::new());
+        }
+
+        let now = now_ms();
+        let items = group
+            .pending
+            .range(start..=end)
+            .filter(|(_, pending)| {
+                consumer
+                    .as_ref()
+                    .is_none_or(|consumer| pending.consumer == *consumer)
+            })
+            .filter(|(_, pending)| now - pending.delivery_time >= min_idle)
+            .take(count)
+            .map(|(id, pending)| {
+                Object::new_array(vec![
+                    id.to_object(),
+                    Object::BulkString(Some(pending.consumer.clone())),
+                    Object::Integer(now - pending.delivery_time),
+                    Object::Integer(pending.delivery_count as i64),
+                ])
+            })
+            .collect();
+
+        Object::new_array(items)
+    }
+
+    /// Do an `XCLAIM` command, which gives pending entries idle for long
+    /// enough to another consumer.
+    pub(super) fn do_xclaim(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(group_name), Some(consumer), Some(min_idle)) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("xclaim");
+        };
+
+        let Some(min_idle) = parse_i64(&min_idle) else {
+            return Object::new_error(b"ERR Invalid min-idle-time argument for XCLAIM");
+        };
+
+        // IDs come first, up to the first argument that isn't one.
+        let mut ids = Vec::new();
+        while let Some(id) = elements.front().and_then(|id| StreamId::parse(id, 0)) {
+            elements.pop_front();
+            ids.push(id);
+        }
+        if ids.is_empty() {
+            return new_arity_error("xclaim");
+        }
+
+        let now = now_ms();
+        let mut delivery_time = None;
+        let mut retry_count = None;
+        let mut is_force = false;
+        let mut is_just_id = false;
+        let mut last_id = None;
+        while let Some(option) = elements.pop_front() {
+            let mut name = option.clone();
+            convert_to_ascii_uppercase(&mut name);
+            match name.as_slice() {
+                b"IDLE" | b"TIME" | b"RETRYCOUNT" => {
+                    let Some(value) = elements.pop_front().and_then(|value| parse_i64(&value))
+                    else {
+                        let message = format!(
+                            "ERR Invalid {} option argument for XCLAIM",
+                            String::from_utf8_lossy(&name),
+                        );
+                        return Object::new_error(message.as_bytes());
+                    };
+                    match name.as_slice() {
+                        b"IDLE" => delivery_time = Some(now - value),
+                        b"TIME" => delivery_time = Some(value),
+                        _ => retry_count = Some(value.max(0) as u64),
+                    }
+                }
+                b"FORCE" => is_force = true,
+                b"JUSTID" => is_just_id = true,
+                b"LASTID" => {
+                    let Some(id) = elements.pop_front().and_then(|id| StreamId::parse(&id, 0))
+                    else {
+                        return new_invalid_id_error();
+                    };
+                    last_id = Some(id);
+                }
+                _ => {
+                    let message = format!(
+                        "ERR Unrecognized XCLAIM option '{}'",
+                        String::from_utf8_lossy(&option),
+                    );
+                    return Object::new_error(message.as_bytes());
+                }
+            }
+        }
+        // Delivery times in the future are taken as now.
+        let delivery_time = delivery_time
+            .filter(|time| (0..=now).contains(time))
+            .unwrap_or(now);
+
+        let (group, entries) = match self.get_stream_group_mut(&key, &group_name) {
+            Ok(found) => found,
+            Err(e) => return e,
+        };
+
+        if let Some(last_id) = last_id
+            && last_id > group.last_id
+        {
+            group.last_id = last_id;
+        }
+        group.see_consumer(&consumer, now);
+
+        let mut items = Vec::new();
+        for id in ids {
+            let is_pending = group.pending.contains_key(&id);
+            let entry = entries.get_key_value(&id);
+            if !(is_pending || (is_force && entry.is_some())) {
+                continue;
+            }
+            // Entries deleted since delivery can't be claimed.
+            let Some(entry) = entry else {
+                group.acknowledge(id);
+                continue;
+            };
+            if let Some(pending) = group.pending.get(&id)
+                && min_idle > 0
+                && now - pending.delivery_time < min_idle
+            {
+                continue;
+            }
+
+            let pending = group.assign(id, &consumer, delivery_time);
+            if !is_pending {
+                // A forced claim counts as the first delivery.
+                pending.delivery_count = 1;
+            }
+            match retry_count {
+                Some(retry_count) => pending.delivery_count = retry_count,
+                None if !is_just_id => pending.delivery_count += 1,
+                None => (),
+            }
+            items.push(if is_just_id {
+                id.to_object()
+            } else {
+                new_entry_reply(entry)
+            });
+        }
+        if !items.is_empty() {
+            group.see_consumer(&consumer, now).active_time = Some(now);
+        }
+
+        Object::new_array(items)
+    }
+
+    /// Do an `XAUTOCLAIM` command, which gives another consumer the pending
+    /// entries from an ID on that were idle for long enough.
+    pub(super) fn do_xautoclaim(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(group_name), Some(consumer), Some(min_idle), Some(start)) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("xautoclaim");
+        };
+
+        let Some(min_idle) = parse_i64(&min_idle) else {
+            return Object::new_error(b"ERR Invalid min-idle-time argument for XAUTOCLAIM");
+        };
+        let start = match StreamId::parse_bound(&start, true) {
+            Ok(start) => start,
+            Err(e) => return e,
+        };
+
+        let mut count = XAUTOCLAIM_DEFAULT_COUNT;
+        let mut is_just_id = false;
+        while let Some(mut option) = elements.pop_front() {
+            convert_to_ascii_uppercase(&mut option);
+            match option.as_slice() {
+                b"COUNT" => {
+                    let Some(value) = elements.pop_front().and_then(|value| parse_i64(&value))
+                    else {
+                        return new_not_integer_error();
+                    };
+                    if value < 1 || value > i64::MAX / XAUTOCLAIM_ATTEMPTS_FACTOR as i64 {
+                        return Object::new_error(b"ERR COUNT must be > 0");
+                    }
+                    count = value as usize;
+                }
+                b"JUSTID" => is_just_id = true,
+                _ => return new_syntax_error(),
+            }
+        }
+
+        let (group, entries) = match self.get_stream_group_mut(&key, &group_name) {
+            Ok(found) => found,
+            Err(e) => return e,
+        };
+
+        let now = now_ms();
+        group.see_consumer(&consumer, now);
+
+        // Look at a bounded number of pending entries so a long list of
+        // busy entries doesn't stall the server.
+        let mut attempts = count * XAUTOCLAIM_ATTEMPTS_FACTOR;
+        let mut claimed = Vec::new();
+        let mut deleted = Vec::new();
+        let mut next = StreamId::MIN;
+        let candidates: Vec<(StreamId, i64)> = group
+            .pending
+            .range(start..)
+            .take(attempts + 1)
+            .map(|(id, pending)| (*id, pending.delivery_time))
+            .collect();
+        for (id, last_delivery) in candidates {
+            if attempts == 0 || claimed.len() == count {
+                next = id;
+                break;
+            }
+            attempts -= 1;
+
+            let Some(entry) = entries.get_key_value(&id) else {
+                group.acknowledge(id);
+                deleted.push(id.to_object());
+                continue;
+            };
+            if min_idle > 0 && now - last_delivery < min_idle {
+                continue;
+            }
+
+            let pending = group.assign(id, &consumer, now);
+            if !is_just_id {
+                pending.delivery_count += 1;
+            }
+            claimed.push(if is_just_id {
+                id.to_object()
+            } else {
+                new_entry_reply(entry)
+            });
+        }
+        if !claimed.is_empty() {
+            group.see_consumer(&consumer, now).active_time = Some(now);
+        }
+
+        Object::new_array(vec![
+            next.to_object(),
+            Object::new_array(claimed),
+            Object::new_array(deleted),
+        ])
+    }
+
+    /// Do an `XINFO` command.
+    pub(super) fn do_xinfo(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(mut subcommand) = elements.pop_front() else {
+            return new_arity_error("xinfo");
+        };
+
+        convert_to_ascii_uppercase(&mut subcommand);
+        match subcommand.as_slice() {
+            b"STREAM" => self.do_xinfo_stream(elements),
+            b"GROUPS" => self.do_xinfo_groups(elements),
+            b"CONSUMERS" => self.do_xinfo_consumers(elements),
+            _ => new_unknown_subcommand_error(&subcommand, "XINFO"),
+        }
+    }
+
+    /// Do an `XINFO STREAM` command. With `FULL` the reply has entries and
+    /// the details of every consumer group, up to a count of each.
+    fn do_xinfo_stream(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error("xinfo|stream");
+        };
+
+        let full_count = match (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) {
+            (None, _, _) => None,
+            (Some(full), None, _) if full.eq_ignore_ascii_case(b"FULL") => {
+                Some(XINFO_FULL_DEFAULT_COUNT)
+            }
+            (Some(full), Some(option), Some(count))
+                if full.eq_ignore_ascii_case(b"FULL") && option.eq_ignore_ascii_case(b"COUNT") =>
+            {
+                match parse_i64(&count) {
+                    // A count of zero means everything.
+                    Some(count) if count > 0 => Some(count as usize),
+                    Some(_) => Some(usize::MAX),
+                    None => return new_not_integer_error(),
+                }
+            }
+            _ => return new_syntax_error(),
+        };
+
+        let stream = match self.get_stream_for_xinfo(&key) {
+            Ok(stream) => stream,
+            Err(e) => return e,
+        };
+
+        let mut fields = vec![
+            ("length", Object::Integer(stream.len() as i64)),
+            ("last-generated-id", stream.last_id.to_object()),
+            ("max-deleted-entry-id", stream.max_deleted_id.to_object()),
+            (
+                "entries-added",
+                Object::Integer(stream.entries_added as i64),
+            ),
+            ("recorded-first-entry-id", stream.first_id().to_object()),
+        ];
+
+        let Some(count) = full_count else {
+            let first = stream.entries.first_key_value().map(new_entry_reply);
+            let last = stream.entries.last_key_value().map(new_entry_reply);
+            fields.extend([
+                ("groups", Object::Integer(stream.groups.len() as i64)),
+                ("first-entry", first.unwrap_or(Object::BulkString(None))),
+                ("last-entry", last.unwrap_or(Object::BulkString(None))),
+            ]);
+            return new_info_reply(fields);
+        };
+
+        let entries = stream.range(StreamId::MIN, StreamId::MAX, false, Some(count));
+        let groups = stream
+            .groups
+            .iter()
+            .map(|(name, group)| {
+                let pending = group
+                    .pending
+                    .iter()
+                    .take(count)
+                    .map(|(id, pending)| {
+                        Object::new_array(vec![
+                            id.to_object(),
+                            Object::BulkString(Some(pending.consumer.clone())),
+                            Object::Integer(pending.delivery_time),
+                            Object::Integer(pending.delivery_count as i64),
+                        ])
+                    })
+                    .collect();
+                let consumers = group
+                    .consumers
+                    .iter()
+                    .map(|(name, consumer)| {
+                        let pending = consumer
+                            .pending
+                            .iter()
+                            .take(count)
+                            .filter_map(|id| Some((id, group.pending.get(id)?)))
+                            .map(|(id, pending)| {
+                                Object::new_array(vec![
+                                    id.to_object(),
+                                    Object::Integer(pending.delivery_time),
+                                    Object::Integer(pending.delivery_count as i64),
+                                ])
+                            })
+                            .collect();
+                        new_info_reply(vec![
+                            ("name", Object::BulkString(Some(name.clone()))),
+                            ("seen-time", Object::Integer(consumer.seen_time)),
+                            (
+                                "active-time",
+                                Object::Integer(consumer.active_time.unwrap_or(-1)),
+                            ),
+                            ("pel-count", Object::Integer(consumer.pending.len() as i64)),
+                            ("pending", Object::new_array(pending)),
+                        ])
+                    })
+                    .collect();
+                new_info_reply(vec![
+                    ("name", Object::BulkString(Some(name.clone()))),
+                    ("last-delivered-id", group.last_id.to_object()),
+                    ("entries-read", new_optional_integer(group.entries_read)),
+                    ("lag", new_optional_integer(stream.lag(group))),
+                    ("pel-count", Object::Integer(group.pending.len() as i64)),
+                    ("pending", Object::new_array(pending)),
+                    ("consumers", Object::new_array(consumers)),
+                ])
+            })
+            .collect();
+        fields.extend([
+            ("entries", Object::new_array(entries)),
+            ("groups", Object::new_array(groups)),
+        ]);
+        new_info_reply(fields)
+    }
+
+    /// Do an `XINFO GROUPS` command.
+    fn do_xinfo_groups(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), None) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("xinfo|groups");
+        };
+
+        let stream = match self.get_stream_for_xinfo(&key) {
+            Ok(stream) => stream,
+            Err(e) => return e,
+        };
+
+        let groups = stream
+            .groups
+            .iter()
+            .map(|(name, group)| {
+                new_info_reply(vec![
+                    ("name", Object::BulkString(Some(name.clone()))),
+                    ("consumers", Object::Integer(group.consumers.len() as i64)),
+                    ("pending", Object::Integer(group.pending.len() as i64)),
+                    ("last-delivered-id", group.last_id.to_object()),
+                    ("entries-read", new_optional_integer(group.entries_read)),
+                    ("lag", new_optional_integer(stream.lag(group))),
+                ])
+            })
+            .collect();
+
+        Object::new_array(groups)
+    }
+
+    /// Do an `XINFO CONSUMERS` command.
+    fn do_xinfo_consumers(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(group_name), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("xinfo|consumers");
+        };
+
+        let stream = match self.get_stream_for_xinfo(&key) {
+            Ok(stream) => stream,
+            Err(e) => return e,
+        };
+
+        let Some(group) = stream.groups.get(&group_name) else {
+            return new_no_group_error(&key, &group_name);
+        };
+
+        let now = now_ms();
+        let consumers = group
+            .consumers
+            .iter()
+            .map(|(name, consumer)| {
+                let inactive = consumer.active_time.map_or(-1, |active| now - active);
+                new_info_reply(vec![
+                    ("name", Object::BulkString(Some(name.clone()))),
+                    ("pending", Object::Integer(consumer.pending.len() as i64)),
+                    ("idle", Object::Integer(now - consumer.seen_time)),
+                    ("inactive", Object::Integer(inactive)),
+                ])
+            })
+            .collect();
+
+        Object::new_array(consumers)
+    }
+
+    /// Returns the stream at a key for an `XGROUP` subcommand, which needs
+    /// the key to exist.
+    fn get_stream_for_xgroup(&mut self, key: &[u8]) -> Result<&mut Stream, Object> {
+        match self.get_entry_mut(key).map(|entry| &mut entry.value) {
+            Some(Value::Stream(stream)) => Ok(stream),
+            Some(_) => Err(new_wrong_type_error()),
+            None => Err(new_xgroup_no_key_error()),
+        }
+    }
+
+    /// Returns the stream at a key for an `XINFO` subcommand.
+    fn get_stream_for_xinfo(&mut self, key: &[u8]) -> Result<&Stream, Object> {
+        match self.get_entry(key).map(|entry| &entry.value) {
+            Some(Value::Stream(stream)) => Ok(stream),
+            Some(_) => Err(new_wrong_type_error()),
+            None => Err(Object::new_error(b"ERR no such key")),
+        }
+    }
+
+    /// Returns a consumer group of the stream at a key for changing, along
+    /// with the stream's entries.
+    fn get_stream_group_mut(
+        &mut self,
+        key: &[u8],
+        group_name: &[u8],
+    ) -> Result<(&mut ConsumerGroup, &BTreeMap<StreamId, ListPack>), Object> {
+        match self.get_entry_mut(key).map(|entry| &mut entry.value) {
+            Some(Value::Stream(stream)) => match stream.groups.get_mut(group_name) {
+                Some(group) => Ok((group, &stream.entries)),
+                None => Err(new_no_key_or_group_error(key, group_name)),
+            },
+            Some(_) => Err(new_wrong_type_error()),
+            None => Err(new_no_key_or_group_error(key, group_name)),
+        }
+    }
+
+    /// Returns a consumer group of the stream at a key.
+    fn get_stream_group(
+        &mut self,
+        key: &[u8],
+        group_name: &[u8],
+    ) -> Result<&ConsumerGroup, Object> {
+        match self.get_entry(key).map(|entry| &entry.value) {
+            Some(Value::Stream(stream)) => stream
+                .groups
+                .get(group_name)
+                .ok_or_else(|| new_no_key_or_group_error(key, group_name)),
+            Some(_) => Err(new_wrong_type_error()),
+            None => Err(new_no_key_or_group_error(key, group_name)),
+        }
+    }
+}
+
+/// Creates the reply for an entry, its ID and its fields and values.
+fn new_entry_reply((id, pairs): (&StreamId, &ListPack)) -> Object {
+    let pairs = pairs
+        .iter()
+        .map(|item| Object::BulkString(Some(item.to_vec())))
+        .collect();
+    Object::new_array(vec![id.to_object(), Object::new_array(pairs)])
+}
+
+/// Creates the reply for a count that may be unknown, which is null.
+fn new_optional_integer(n: Option<u64>) -> Object {
+    match n {
+        Some(n) => Object::Integer(n as i64),
+        None => Object::BulkString(None),
+    }
+}
+
+/// Parses the argument of `ENTRIESREAD`, where -1 means unknown.
+fn parse_entries_read(arg: Option<Vec<u8>>) -> Result<Option<u64>, Object> {
+    let Some(arg) = arg else {
+        return Err(new_syntax_error());
+    };
+    match parse_i64(&arg) {
+        Some(-1) => Ok(None),
+        Some(entries_read) if entries_read >= 0 => Ok(Some(entries_read as u64)),
+        Some(_) => Err(Object::new_error(
+            b"ERR value for ENTRIESREAD must be positive or -1",
+        )),
+        None => Err(new_not_integer_error()),
+    }
+}
+
+/// Creates the error returned when an `XGROUP` subcommand's key is missing.
+fn new_xgroup_no_key_error() -> Object {
+    Object::new_error(
+        b"ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.",
+    )
+}
+
+/// Creates the error returned for a missing consumer group of a stream.
+fn new_no_group_error(key: &[u8], group_name: &[u8]) -> Object {
+    let message = format!(
+        "NOGROUP No such consumer group '{}' for key name '{}'",
+        String::from_utf8_lossy(group_name),
+        String::from_utf8_lossy(key),
+    );
+    Object::new_error(message.as_bytes())
+}
+
+/// Creates the error returned when either a stream or its consumer group is
+/// missing.
+fn new_no_key_or_group_error(key: &[u8], group_name: &[u8]) -> Object {
+    let message = format!(
+        "NOGROUP No such key '{}' or consumer group '{}'",
+        String::from_utf8_lossy(key),
+        String::from_utf8_lossy(group_name),
+    );
+    Object::new_error(message.as_bytes())
+}
+
+/// Creates the error returned for a malformed ID.
+fn new_invalid_id_error() -> Object {
+    Object::new_error(b"ERR Invalid stream ID specified as stream command argument")
+}
+
+/// Returns the current time in milliseconds since the Unix epoch.
+fn now_ms() -> i64 {
+    to_unix_ms(time::SystemTime::now())
+}
+
+/// Parses a u64 written in decimal digits only.
+fn parse_u64(s: &[u8]) -> Option<u64> {
+    if s.is_empty() || !s.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    std::str::from_utf8(s).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Engine, Object, Value, run, send};
+    use super::StreamId;
+
+    fn bulk(s: &str) -> Object {
+        Object::BulkString(Some(s.as_bytes().to_vec()))
+    }
+
+    fn xadd(engine: &mut Engine, key: &str, id: &str) -> Object {
+        run(engine, &["XADD", key, id, "f", "v"])
+    }
+
+    /// Creates the reply of an entry with one field.
+    fn entry(id: &str, field: &str, value: &str) -> Object {
+        Object::new_array(vec![
+            bulk(id),
+            Object::new_array(vec![bulk(field), bulk(value)]),
+        ])
+    }
+
+    /// Creates the reply of one stream's entries, each with one field.
+    fn read_reply(key: &str, entries: &[(&str, &str, &str)]) -> Object {
+        let entries = entries
+            .iter()
+            .map(|(id, field, value)| entry(id, field, value))
+            .collect();
+        Object::new_array(vec![Object::new_array(vec![
+            bulk(key),
+            Object::new_array(entries),
+        ])])
+    }
+
+    /// Returns the owner and delivery count of each pending entry of a
+    /// consumer group, leaving out the idle times, which vary.
+    fn pending(engine: &mut Engine, key: &str, group: &str) -> Vec<(Object, Object, Object)> {
+        let Object::Array(array) = run(engine, &["XPENDING", key, group, "-", "+", "100"]) else {
+            panic!("XPENDING didn't reply with an array");
+        };
+        array
+            .items
+            .into_iter()
+            .map(|item| match item {
+                Object::Array(item) => {
+                    let mut fields = item.items.into_iter();
+                    let id = fields.next().unwrap();
+                    let consumer = fields.next().unwrap();
+                    let count = fields.nth(1).unwrap();
+                    (id, consumer, count)
+                }
+                _ => panic!("XPENDING didn't reply with arrays"),
+            })
+            .collect()
+    }
+
+    /// Returns a field of the first consumer group of `XINFO STREAM FULL`.
+    fn group_info(engine: &mut Engine, key: &str, field: &str) -> Object {
+        let Object::Map(fields) = run(engine, &["XINFO", "STREAM", key, "FULL"]) else {
+            panic!("XINFO STREAM didn't reply with a map");
+        };
+        let Some((_, Object::Array(groups))) =
+            fields.into_iter().find(|(name, _)| *name == bulk("groups"))
+        else {
+            panic!("XINFO STREAM didn't reply with groups");
+        };
+        let Some(Object::Map(group)) = groups.items.into_iter().next() else {
+            panic!("XINFO STREAM didn't reply with a group");
+        };
+        group
+            .into_iter()
+            .find(|(name, _)| *name == bulk(field))
+            .map(|(_, value)| value)
+            .unwrap()
+    }
+
+    fn max_deleted_id(engine: &Engine, key: &str) -> StreamId {
+        match engine.data.get(key.as_bytes()).map(|entry| &entry.value) {
+            Some(Value::Stream(stream)) => stream.max_deleted_id,
+            _ => panic!("{key} isn't a stream"),
+        }
+    }
+
+    #[test]
+    fn ids_are_generated_after_the_last_one() {
+        let mut engine = Engine::new();
+        assert_eq!(xadd(&mut engine, "s", "5-*"), bulk("5-0"));
+        assert_eq!(xadd(&mut engine, "s", "5-*"), bulk("5-1"));
+        assert_eq!(xadd(&mut engine, "s", "7-*"), bulk("7-0"));
+
+        // An ID from the future leaves the clock behind, so `*` counts on
+        // from the last ID instead.
+        assert_eq!(
+            xadd(&mut engine, "s", "99999999999999-5"),
+            bulk("99999999999999-5")
+        );
+        assert_eq!(xadd(&mut engine, "s", "*"), bulk("99999999999999-6"));
+        assert_eq!(
+            xadd(&mut engine, "s", "99999999999999-18446744073709551615"),
+            bulk("99999999999999-18446744073709551615")
+        );
+        assert_eq!(xadd(&mut engine, "s", "*"), bulk("100000000000000-0"));
+
+        let Object::BulkString(Some(id)) = xadd(&mut engine, "now", "*") else {
+            panic!("XADD didn't reply with an ID");
+        };
+        let id = StreamId::parse(&id, 0).unwrap();
+        assert!(id.ms > 0);
+        assert_eq!(id.seq, 0);
+    }
+
+    #[test]
+    fn ids_must_grow() {
+        let too_small = Object::new_error(
+            b"ERR The ID specified in XADD is equal or smaller than the target stream top item",
+        );
+        let mut engine = Engine::new();
+        assert_eq!(
+            xadd(&mut engine, "s", "0-0"),
+            Object::new_error(b"ERR The ID specified in XADD must be greater than 0-0")
+        );
+        assert_eq!(run(&mut engine, &["EXISTS", "s"]), Object::Integer(0));
+
+        assert_eq!(xadd(&mut engine, "s", "3-3"), bulk("3-3"));
+        for id in ["3-3", "3-2", "2-9", "3", "2-*"] {
+            assert_eq!(xadd(&mut engine, "s", id), too_small, "{id}");
+        }
+        assert_eq!(xadd(&mut engine, "s", "4"), bulk("4-0"));
+
+        // A sequence that can't grow any more needs a greater time.
+        assert_eq!(
+            xadd(&mut engine, "s", "5-18446744073709551615"),
+            bulk("5-18446744073709551615")
+        );
+        assert_eq!(xadd(&mut engine, "s", "5-*"), too_small);
+        assert_eq!(xadd(&mut engine, "s", "6-*"), bulk("6-0"));
+
+        // Deleting the top item doesn't free its ID.
+        run(&mut engine, &["XDEL", "s", "6-0"]);
+        assert_eq!(xadd(&mut engine, "s", "6-0"), too_small);
+
+        assert_eq!(
+            xadd(
+                &mut engine,
+                "full",
+                "18446744073709551615-18446744073709551615"
+            ),
+            bulk("18446744073709551615-18446744073709551615")
+        );
+        assert_eq!(
+            xadd(&mut engine, "full", "*"),
+            Object::new_error(
+                b"ERR The stream has exhausted the last possible ID, unable to add more items"
+            )
+        );
+    }
+
+    #[test]
+    fn approximate_trimming_removes_whole_blocks() {
+        let mut engine = Engine::new();
+        for ms in 1..=250 {
+            xadd(&mut engine, "s", &format!("{ms}-0"));
+        }
+
+        let cases: &[(&[&str], i64, i64)] = &[
+            (&["MAXLEN", "~", "100"], 100, 150),
+            (&["MAXLEN", "~", "0", "LIMIT", "99"], 0, 150),
+            (&["MAXLEN", "~", "0", "LIMIT", "0"], 100, 50),
+            (&["MAXLEN", "~", "10"], 0, 50),
+            (&["MAXLEN", "10"], 40, 10),
+            (&["MINID", "~", "300-0"], 0, 10),
+            (&["MINID", "=", "245-0"], 4, 6),
+            (&["MINID", "245-0"], 0, 6),
+        ];
+        for (args, removed, len) in cases {
+            let command = [&["XTRIM", "s"][..], args].concat();
+            assert_eq!(
+                run(&mut engine, &command),
+                Object::Integer(*removed),
+                "{args:?}"
+            );
+            assert_eq!(
+                run(&mut engine, &["XLEN", "s"]),
+                Object::Integer(*len),
+                "{args:?}"
+            );
+        }
+
+        assert_eq!(
+            run(&mut engine, &["XTRIM", "s", "MAXLEN", "0", "LIMIT", "10"]),
+            Object::new_error(
+                b"ERR syntax error, LIMIT cannot be used without the special ~ option"
+            )
+        );
+        assert_eq!(
+            run(&mut engine, &["XTRIM", "s", "MAXLEN", "-1"]),
+            Object::new_error(b"ERR The MAXLEN argument must be >= 0.")
+        );
+        run(&mut engine, &["XADD", "s", "MAXLEN", "2", "*", "f", "v"]);
+        assert_eq!(run(&mut engine, &["XLEN", "s"]), Object::Integer(2));
+    }
+
+    #[test]
+    fn approximate_trimming_is_limited_by_default() {
+        let mut engine = Engine::new();
+        for ms in 1..=10_250 {
+            xadd(&mut engine, "s", &format!("{ms}-0"));
+        }
+        assert_eq!(
+            run(&mut engine, &["XTRIM", "s", "MAXLEN", "~", "0"]),
+            Object::Integer(10_000)
+        );
+        assert_eq!(
+            run(&mut engine, &["XTRIM", "s", "MAXLEN", "0"]),
+            Object::Integer(250)
+        );
+    }
+
+    #[test]
+    fn xdel_keeps_the_greatest_deleted_id() {
+        let mut engine = Engine::new();
+        for id in ["1-0", "2-0", "3-0"] {
+            xadd(&mut engine, "s", id);
+        }
+        assert_eq!(max_deleted_id(&engine, "s"), StreamId::MIN);
+
+        assert_eq!(
+            run(&mut engine, &["XDEL", "s", "2-0", "9-0"]),
+            Object::Integer(1)
+        );
+        assert_eq!(max_deleted_id(&engine, "s"), StreamId { ms: 2, seq: 0 });
+        assert_eq!(run(&mut engine, &["XDEL", "s", "1-0"]), Object::Integer(1));
+        assert_eq!(max_deleted_id(&engine, "s"), StreamId { ms: 2, seq: 0 });
+        assert_eq!(run(&mut engine, &["XDEL", "s", "3-0"]), Object::Integer(1));
+        assert_eq!(max_deleted_id(&engine, "s"), StreamId { ms: 3, seq: 0 });
+
+        let Object::Map(fields) = run(&mut engine, &["XINFO", "STREAM", "s"]) else {
+            panic!("XINFO STREAM didn't reply with a map");
+        };
+        assert!(fields.contains(&(bulk("max-deleted-entry-id"), bulk("3-0"))));
+        assert!(fields.contains(&(bulk("entries-added"), Object::Integer(3))));
+        assert!(fields.contains(&(bulk("length"), Object::Integer(0))));
+    }
+
+    #[test]
+    fn xadd_wakes_readers_with_only_the_new_entries() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["XADD", "s", "1-0", "a", "1"]);
+        let read = ["XREAD", "BLOCK", "0", "STREAMS", "s", "$"];
+        assert_eq!(send(&mut engine, 2, &read), None);
+        assert_eq!(
+            send(
+                &mut engine,
+                3,
+                &["XREAD", "BLOCK", "0", "STREAMS", "new", "$"]
+            ),
+            None
+        );
+
+        run(&mut engine, &["XADD", "other", "1-0", "a", "1"]);
+        assert!(engine.take_replies().is_empty());
+        assert_eq!(engine.blocked.waiters(b"s"), vec![2]);
+
+        run(&mut engine, &["XADD", "s", "2-0", "b", "2"]);
+        run(&mut engine, &["XADD", "new", "5-0", "c", "3"]);
+        assert_eq!(
+            engine.take_replies(),
+            vec![
+                (2, read_reply("s", &[("2-0", "b", "2")])),
+                (3, read_reply("new", &[("5-0", "c", "3")])),
+            ]
+        );
+        assert!(engine.blocked.waiters(b"s").is_empty());
+    }
+
+    #[test]
+    fn blocked_reads_time_out_with_a_null_reply() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["XADD", "s", "1-0", "a", "1"]);
+        assert_eq!(
+            run(&mut engine, &["XREAD", "STREAMS", "s", "1-0"]),
+            Object::Null
+        );
+        assert_eq!(
+            send(
+                &mut engine,
+                2,
+                &["XREAD", "BLOCK", "10", "STREAMS", "s", "1-0"]
+            ),
+            None
+        );
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        engine.time_out_blocked_clients();
+        assert_eq!(engine.take_replies(), vec![(2, Object::Null)]);
+        assert!(engine.blocked.waiters(b"s").is_empty());
+    }
+
+    #[test]
+    fn plus_reads_the_last_entry() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["XADD", "s", "1-0", "a", "1"]);
+        run(&mut engine, &["XADD", "s", "2-0", "b", "2"]);
+        assert_eq!(
+            run(&mut engine, &["XREAD", "STREAMS", "s", "+"]),
+            read_reply("s", &[("2-0", "b", "2")])
+        );
+
+        // With nothing to read yet, `+` waits for the next entry.
+        run(&mut engine, &["XDEL", "s", "1-0", "2-0"]);
+        assert_eq!(
+            send(
+                &mut engine,
+                2,
+                &["XREAD", "BLOCK", "0", "STREAMS", "s", "+"]
+            ),
+            None
+        );
+        run(&mut engine, &["XADD", "s", "3-0", "c", "3"]);
+        assert_eq!(
+            engine.take_replies(),
+            vec![(2, read_reply("s", &[("3-0", "c", "3")]))]
+        );
+    }
+
+    #[test]
+    fn xreadgroup_delivers_new_entries_and_keeps_them_pending() {
+        let mut engine = Engine::new();
+        for (id, field) in [("1-0", "a"), ("2-0", "b"), ("3-0", "c")] {
+            run(&mut engine, &["XADD", "s", id, field, "v"]);
+        }
+        assert_eq!(
+            run(&mut engine, &["XGROUP", "CREATE", "s", "g", "0"]),
+            Object::new_simple_string(b"OK")
+        );
+
+        let read = |consumer| {
+            [
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                consumer,
+                "COUNT",
+                "2",
+                "STREAMS",
+                "s",
+                ">",
+            ]
+        };
+        assert_eq!(
+            run(&mut engine, &read("alice")),
+            read_reply("s", &[("1-0", "a", "v"), ("2-0", "b", "v")])
+        );
+        assert_eq!(
+            run(&mut engine, &read("bob")),
+            read_reply("s", &[("3-0", "c", "v")])
+        );
+        assert_eq!(run(&mut engine, &read("bob")), Object::Null);
+
+        let owners = |consumers: &[(&str, &str)]| {
+            let consumers = consumers
+                .iter()
+                .map(|(name, count)| Object::new_array(vec![bulk(name), bulk(count)]))
+                .collect();
+            Object::new_array(consumers)
+        };
+        assert_eq!(
+            run(&mut engine, &["XPENDING", "s", "g"]),
+            Object::new_array(vec![
+                Object::Integer(3),
+                bulk("1-0"),
+                bulk("3-0"),
+                owners(&[("alice", "2"), ("bob", "1")]),
+            ])
+        );
+
+        assert_eq!(
+            run(&mut engine, &["XACK", "s", "g", "1-0", "9-0"]),
+            Object::Integer(1)
+        );
+        // Reading a consumer's history delivers its pending entries again.
+        assert_eq!(
+            run(
+                &mut engine,
+                &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", "0"]
+            ),
+            read_reply("s", &[("2-0", "b", "v")])
+        );
+        assert_eq!(
+            pending(&mut engine, "s", "g"),
+            vec![
+                (bulk("2-0"), bulk("alice"), Object::Integer(2)),
+                (bulk("3-0"), bulk("bob"), Object::Integer(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn xclaim_takes_entries_idle_long_enough_or_forced() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["XADD", "s", "1-0", "a", "v"]);
+        run(&mut engine, &["XADD", "s", "2-0", "b", "v"]);
+        run(&mut engine, &["XGROUP", "CREATE", "s", "g", "0"]);
+        run(
+            &mut engine,
+            &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"],
+        );
+        run(&mut engine, &["XACK", "s", "g", "2-0"]);
+
+        let cases: &[(&[&str], Object)] = &[
+            (&["bob", "100000", "1-0"], Object::new_empty_array()),
+            (
+                &["bob", "0", "1-0"],
+                Object::new_array(vec![entry("1-0", "a", "v")]),
+            ),
+            (&["bob", "0", "2-0"], Object::new_empty_array()),
+            (
+                &["bob", "0", "2-0", "FORCE"],
+                Object::new_array(vec![entry("2-0", "b", "v")]),
+            ),
+            (&["bob", "0", "9-0", "FORCE"], Object::new_empty_array()),
+        ];
+        for (args, expected) in cases {
+            let command = [&["XCLAIM", "s", "g"][..], args].concat();
+            assert_eq!(run(&mut engine, &command), *expected, "{args:?}");
+        }
+        // A forced claim counts as a first delivery before the claim itself.
+        assert_eq!(
+            pending(&mut engine, "s", "g"),
+            vec![
+                (bulk("1-0"), bulk("bob"), Object::Integer(2)),
+                (bulk("2-0"), bulk("bob"), Object::Integer(2)),
+            ]
+        );
+
+        assert_eq!(
+            run(
+                &mut engine,
+                &["XCLAIM", "s", "g", "carol", "0", "1-0", "2-0", "JUSTID"]
+            ),
+            Object::new_array(vec![bulk("1-0"), bulk("2-0")])
+        );
+        run(
+            &mut engine,
+            &["XCLAIM", "s", "g", "carol", "0", "2-0", "RETRYCOUNT", "7"],
+        );
+        assert_eq!(
+            pending(&mut engine, "s", "g"),
+            vec![
+                (bulk("1-0"), bulk("carol"), Object::Integer(2)),
+                (bulk("2-0"), bulk("carol"), Object::Integer(7)),
+            ]
+        );
+    }
+
+    #[test]
+    fn xautoclaim_acknowledges_deleted_entries() {
+        let mut engine = Engine::new();
+        for (id, field) in [("1-0", "a"), ("2-0", "b"), ("3-0", "c"), ("4-0", "d")] {
+            run(&mut engine, &["XADD", "s", id, field, "v"]);
+        }
+        run(&mut engine, &["XGROUP", "CREATE", "s", "g", "0"]);
+        run(
+            &mut engine,
+            &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"],
+        );
+        run(&mut engine, &["XDEL", "s", "2-0"]);
+
+        assert_eq!(
+            run(
+                &mut engine,
+                &["XAUTOCLAIM", "s", "g", "bob", "0", "0", "COUNT", "2"]
+            ),
+            Object::new_array(vec![
+                bulk("4-0"),
+                Object::new_array(vec![entry("1-0", "a", "v"), entry("3-0", "c", "v")]),
+                Object::new_array(vec![bulk("2-0")]),
+            ])
+        );
+        assert_eq!(
+            run(&mut engine, &["XAUTOCLAIM", "s", "g", "bob", "0", "4-0"]),
+            Object::new_array(vec![
+                bulk("0-0"),
+                Object::new_array(vec![entry("4-0", "d", "v")]),
+                Object::new_empty_array(),
+            ])
+        );
+        assert_eq!(
+            run(
+                &mut engine,
+                &["XAUTOCLAIM", "s", "g", "carol", "100000", "0", "JUSTID"]
+            ),
+            Object::new_array(vec![
+                bulk("0-0"),
+                Object::new_empty_array(),
+                Object::new_empty_array(),
+            ])
+        );
+        assert_eq!(
+            pending(&mut engine, "s", "g"),
+            vec![
+                (bulk("1-0"), bulk("bob"), Object::Integer(2)),
+                (bulk("3-0"), bulk("bob"), Object::Integer(2)),
+                (bulk("4-0"), bulk("bob"), Object::Integer(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn xinfo_stream_reports_entries_read_and_lag() {
+        let mut engine = Engine::new();
+        for ms in 1..=5 {
+            xadd(&mut engine, "s", &format!("{ms}-0"));
+        }
+        run(&mut engine, &["XGROUP", "CREATE", "s", "g", "0"]);
+        assert_eq!(
+            group_info(&mut engine, "s", "entries-read"),
+            Object::BulkString(None)
+        );
+        assert_eq!(group_info(&mut engine, "s", "lag"), Object::Integer(5));
+
+        let read = |count| {
+            [
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "c",
+                "COUNT",
+                count,
+                "STREAMS",
+                "s",
+                ">",
+            ]
+        };
+        run(&mut engine, &read("2"));
+        assert_eq!(
+            group_info(&mut engine, "s", "entries-read"),
+            Object::Integer(2)
+        );
+        assert_eq!(group_info(&mut engine, "s", "lag"), Object::Integer(3));
+
+        // A deleted entry not yet read makes the lag unknown until the group
+        // reads past it.
+        run(&mut engine, &["XDEL", "s", "4-0"]);
+        assert_eq!(
+            group_info(&mut engine, "s", "lag"),
+            Object::BulkString(None)
+        );
+        run(&mut engine, &read("10"));
+        assert_eq!(
+            group_info(&mut engine, "s", "entries-read"),
+            Object::Integer(5)
+        );
+        assert_eq!(group_info(&mut engine, "s", "lag"), Object::Integer(0));
+    }
+}
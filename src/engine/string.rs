@@ -0,0 +1,736 @@
+//! String commands.
+
+use std::collections::VecDeque;
+
+use super::value::Value;
+use super::{
+    Engine, EntryBuilder, Key, Object, convert_to_ascii_uppercase, new_arity_error,
+    new_not_integer_error, new_syntax_error, new_wrong_type_error, normalize_range,
+    parse_expiration_option, parse_i64,
+};
+
+/// The largest a string may grow to with `SETRANGE` or `APPEND`.
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+impl Engine {
+    /// Do a `GETSET` command, which is `SET` with the `GET` option.
+    pub(super) fn do_getset(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        if elements.len() != 2 {
+            return new_arity_error("getset");
+        }
+
+        elements.push_back(b"GET".to_vec());
+        self.do_set(elements)
+    }
+
+    /// Do a `GETDEL` command.
+    pub(super) fn do_getdel(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), None) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("getdel");
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::BulkString(None);
+        };
+
+        if !matches!(entry.value, Value::String(_)) {
+            return new_wrong_type_error();
+        }
+
+        match self.remove_entry(&key).map(|entry| entry.value) {
+            Some(Value::String(string)) => Object::BulkString(Some(string)),
+            _ => Object::BulkString(None),
+        }
+    }
+
+    /// Do a `GETEX` command, which gets a string and optionally sets or
+    /// removes its TTL.
+    pub(super) fn do_getex(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error("getex");
+        };
+
+        // `None` leaves the TTL alone, and `Some(None)` removes it.
+        let mut expiration = None;
+        while let Some(mut option) = elements.pop_front() {
+            convert_to_ascii_uppercase(&mut option);
+            match option.as_slice() {
+                b"PERSIST" if expiration.is_none() => expiration = Some(None),
+                b"EX" | b"PX" | b"EXAT" | b"PXAT" if expiration.is_none() => {
+                    let Some(amount) = elements.pop_front() else {
+                        return new_syntax_error();
+                    };
+                    match parse_expiration_option(&option, &amount, "getex") {
+                        Ok(expires_at) => expiration = Some(Some(expires_at)),
+                        Err(e) => return e,
+                    }
+                }
+                _ => return new_syntax_error(),
+            }
+        }
+
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::BulkString(None);
+        };
+
+        let Value::String(string) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let reply = Object::BulkString(Some(string.clone()));
+        if let Some(expires_at) = expiration {
+            self.set_expiration(&key, expires_at);
+        }
+
+        reply
+    }
+
+    /// Do a `SETNX` command.
+    pub(super) fn do_setnx(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(value), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("setnx");
+        };
+
+        if self.get_entry(&key).is_some() {
+            return Object::Integer(0);
+        }
+
+        let entry = EntryBuilder::new(Value::String(value)).build();
+        self.insert_entry(key, entry);
+
+        Object::Integer(1)
+    }
+
+    /// Do an `MGET` command. Missing keys and keys that aren't strings get
+    /// null replies.
+    pub(super) fn do_mget(&mut self, elements: VecDeque<Vec<u8>>) -> Object {
+        if elements.is_empty() {
+            return new_arity_error("mget");
+        }
+
+        let mut items = Vec::with_capacity(elements.len());
+        for key in elements.iter() {
+            let string = match self.get_entry(key).map(|entry| &entry.value) {
+                Some(Value::String(string)) => Some(string.clone()),
+                _ => None,
+            };
+            items.push(Object::BulkString(string));
+        }
+
+        Object::new_array(items)
+    }
+
+    /// Do an `MSET` or `MSETNX` command. With `is_nx`, nothing is set if any
+    /// of the keys exists.
+    pub(super) fn do_mset(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        is_nx: bool,
+    ) -> Object {
+        if elements.is_empty() || !elements.len().is_multiple_of(2) {
+            return new_arity_error(name);
+        }
+
+        if is_nx {
+            let keys: Vec<Key> = elements.iter().step_by(2).cloned().collect();
+            if keys.iter().any(|key| self.get_entry(key).is_some()) {
+                return Object::Integer(0);
+            }
+        }
+
+        while let (Some(key), Some(value)) = (elements.pop_front(), elements.pop_front()) {
+            let entry = EntryBuilder::new(Value::String(value)).build();
+            self.insert_entry(key, entry);
+        }
+
+        if is_nx {
+            Object::Integer(1)
+        } else {
+            Object::new_simple_string(b"OK")
+        }
+    }
+
+    /// Do a `SETEX` or `PSETEX` command, which sets a string with a TTL
+    /// given by the uppercase `EX` or `PX` option.
+    pub(super) fn do_setex(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        option: &[u8],
+    ) -> Object {
+        let (Some(key), Some(amount), Some(value), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error(name);
+        };
+
+        let expires_at = match parse_expiration_option(option, &amount, name) {
+            Ok(expires_at) => expires_at,
+            Err(e) => return e,
+        };
+
+        let mut entry_builder = EntryBuilder::new(Value::String(value));
+        entry_builder.expires_at(expires_at);
+        self.insert_entry(key, entry_builder.build());
+
+        Object::new_simple_string(b"OK")
+    }
+
+    /// Do an `APPEND` command.
+    pub(super) fn do_append(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(value), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("append");
+        };
+
+        if let Some(entry) = self.get_entry(&key) {
+            let Value::String(string) = &entry.value else {
+                return new_wrong_type_error();
+            };
+            if string.len() + value.len() > MAX_STRING_LEN {
+                return new_too_long_error();
+            }
+        }
+
+        let entry = self.get_entry_or_insert(key, Value::String(Vec::new()));
+
+        let Value::String(string) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        string.extend_from_slice(&value);
+
+        Object::Integer(string.len() as i64)
+    }
+
+    /// Do a `STRLEN` command.
+    pub(super) fn do_strlen(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), None) = (elements.pop_front(), elements.pop_front()) else {
+            return new_arity_error("strlen");
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::Integer(0);
+        };
+
+        let Value::String(string) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        Object::Integer(string.len() as i64)
+    }
+
+    /// Do a `GETRANGE` command.
+    pub(super) fn do_getrange(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(start), Some(stop), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("getrange");
+        };
+
+        let (Some(start), Some(stop)) = (parse_i64(&start), parse_i64(&stop)) else {
+            return new_not_integer_error();
+        };
+
+        let Some(entry) = self.get_entry(&key) else {
+            return Object::BulkString(Some(Vec::new()));
+        };
+
+        let Value::String(string) = &entry.value else {
+            return new_wrong_type_error();
+        };
+
+        // Unlike list ranges, a stop before the start of the string clamps
+        // to the first byte, unless both indexes are negative and reversed.
+        if start < 0 && stop < 0 && start > stop {
+            return Object::BulkString(Some(Vec::new()));
+        }
+        let stop = if stop < 0 {
+            std::cmp::max(stop + string.len() as i64, 0)
+        } else {
+            stop
+        };
+
+        match normalize_range(start, stop, string.len()) {
+            Some((start, stop)) => Object::BulkString(Some(string[start..=stop].to_vec())),
+            None => Object::BulkString(Some(Vec::new())),
+        }
+    }
+
+    /// Do a `SETRANGE` command, which overwrites part of a string, padding
+    /// it with zero bytes if the offset is past its end.
+    pub(super) fn do_setrange(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(offset), Some(value), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("setrange");
+        };
+
+        let Some(offset) = parse_i64(&offset) else {
+            return new_not_integer_error();
+        };
+
+        let Ok(offset) = usize::try_from(offset) else {
+            return Object::new_error(b"ERR offset is out of range");
+        };
+
+        let len = match self.get_entry(&key) {
+            Some(entry) => {
+                let Value::String(string) = &entry.value else {
+                    return new_wrong_type_error();
+                };
+                string.len()
+            }
+            None => 0,
+        };
+
+        // An empty value changes nothing, so a missing key isn't created.
+        if value.is_empty() {
+            return Object::Integer(len as i64);
+        }
+
+        if offset.saturating_add(value.len()) > MAX_STRING_LEN {
+            return new_too_long_error();
+        }
+
+        let entry = self.get_entry_or_insert(key, Value::String(Vec::new()));
+
+        let Value::String(string) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let end = offset + value.len();
+        if string.len() < end {
+            string.resize(end, 0);
+        }
+        string[offset..end].copy_from_slice(&value);
+
+        Object::Integer(string.len() as i64)
+    }
+
+    /// Do an increment command, adding `sign` times the amount to an integer
+    /// stored as a string. The amount is one when `has_amount` is false.
+    pub(super) fn do_incr(
+        &mut self,
+        mut elements: VecDeque<Vec<u8>>,
+        name: &str,
+        sign: i64,
+        has_amount: bool,
+    ) -> Object {
+        let Some(key) = elements.pop_front() else {
+            return new_arity_error(name);
+        };
+
+        let amount = if has_amount {
+            let Some(amount) = elements.pop_front() else {
+                return new_arity_error(name);
+            };
+            let Some(amount) = parse_i64(&amount) else {
+                return new_not_integer_error();
+            };
+            amount
+        } else {
+            1
+        };
+
+        if !elements.is_empty() {
+            return new_arity_error(name);
+        }
+
+        // Negating the smallest integer overflows.
+        let Some(amount) = amount.checked_mul(sign) else {
+            return new_not_integer_error();
+        };
+
+        let entry = self.get_entry_or_insert(key, Value::String(b"0".to_vec()));
+
+        let Value::String(string) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let Some(n) = parse_i64(string) else {
+            return new_not_integer_error();
+        };
+
+        let Some(n) = n.checked_add(amount) else {
+            return Object::new_error(b"ERR increment or decrement would overflow");
+        };
+
+        *string = n.to_string().into_bytes();
+
+        Object::Integer(n)
+    }
+
+    /// Do an `INCRBYFLOAT` command.
+    pub(super) fn do_incrbyfloat(&mut self, mut elements: VecDeque<Vec<u8>>) -> Object {
+        let (Some(key), Some(amount), None) = (
+            elements.pop_front(),
+            elements.pop_front(),
+            elements.pop_front(),
+        ) else {
+            return new_arity_error("incrbyfloat");
+        };
+
+        let Some(amount) = parse_f64(&amount) else {
+            return new_not_float_error();
+        };
+
+        // Checked up front so a missing key isn't created on error.
+        if !amount.is_finite() {
+            return new_not_finite_error();
+        }
+
+        let entry = self.get_entry_or_insert(key, Value::String(b"0".to_vec()));
+
+        let Value::String(string) = &mut entry.value else {
+            return new_wrong_type_error();
+        };
+
+        let Some(n) = parse_f64(string) else {
+            return new_not_float_error();
+        };
+
+        let n = n + amount;
+        if !n.is_finite() {
+            return new_not_finite_error();
+        }
+
+        *string = format_f64(n).into_bytes();
+
+        Object::BulkString(Some(string.clone()))
+    }
+}
+
+/// Creates the error returned when a string would grow too long.
+fn new_too_long_error() -> Object {
+    Object::new_error(b"ERR string exceeds maximum allowed size (proto-max-bulk-len)")
+}
+
+/// Creates the error returned when an argument isn't a valid float.
+pub fn new_not_float_error() -> Object {
+    Object::new_error(b"ERR value is not a valid float")
+}
+
+/// Creates the error returned when a float increment isn't finite.
+pub fn new_not_finite_error() -> Object {
+    Object::new_error(b"ERR increment would produce NaN or Infinity")
+}
+
+/// Parses a float, rejecting NaN.
+pub fn parse_f64(s: &[u8]) -> Option<f64> {
+    std::str::from_utf8(s)
+        .ok()?
+        .parse::<f64>()
+        .ok()
+        .filter(|n| !n.is_nan())
+}
+
+/// Formats a float as a double reply, such as a score, the way Redis does.
+/// Whole numbers within half the largest integer are written as integers.
+/// Otherwise the fewest digits that parse back to the same value are
+/// written, with an exponent for very large or very small numbers.
+pub fn format_double(n: f64) -> String {
+    if n == 0.0 {
+        return "0".to_string();
+    }
+    if n.is_infinite() {
+        return format_f64(n);
+    }
+    if n.fract() == 0.0 && n.abs() <= (i64::MAX / 2) as f64 {
+        return (n as i64).to_string();
+    }
+
+    // Take the shortest digits and exponent of the first digit from the
+    // scientific form, which is written like `-1.25e-7`.
+    let scientific = format!("{:e}", n.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let exponent: i64 = exponent.parse().unwrap_or(0);
+    let digits = mantissa.replace('.', "");
+    let len = digits.len() as i64;
+    // The exponent of the last digit.
+    let last = exponent - len + 1;
+
+    let mut result = String::new();
+    if n < 0.0 {
+        result.push('-');
+    }
+    if last >= 0 && exponent < len + 7 {
+        result.push_str(&digits);
+        result.push_str(&"0".repeat(last as usize));
+    } else if last < 0 && (last > -7 || exponent.abs() < 4) {
+        if exponent < 0 {
+            result.push_str("0.");
+            result.push_str(&"0".repeat((-exponent - 1) as usize));
+            result.push_str(&digits);
+        } else {
+            let (whole, fraction) = digits.split_at(exponent as usize + 1);
+            result.push_str(whole);
+            result.push('.');
+            result.push_str(fraction);
+        }
+    } else {
+        result.push_str(mantissa);
+        result.push_str(if exponent < 0 { "e-" } else { "e+" });
+        result.push_str(&exponent.abs().to_string());
+    }
+    result
+}
+
+/// Formats a float with the fewest digits that parse back to the same
+/// value, without an exponent, and without a fraction for whole numbers.
+/// This is how `INCRBYFLOAT` stores its result.
+pub fn format_f64(n: f64) -> String {
+    // Display is already the shortest round trip representation.
+    if n == 0.0 {
+        // Avoid "-0".
+        return "0".to_string();
+    }
+    n.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Engine, Object, run};
+    use super::{format_double, format_f64, parse_f64};
+
+    fn error(message: &str) -> Object {
+        Object::new_error(message.as_bytes())
+    }
+
+    #[test]
+    fn incrby_stops_at_the_integer_limits() {
+        let mut engine = Engine::new();
+        let overflow = error("ERR increment or decrement would overflow");
+        run(&mut engine, &["SET", "n", "9223372036854775806"]);
+        assert_eq!(run(&mut engine, &["INCR", "n"]), Object::Integer(i64::MAX));
+        assert_eq!(run(&mut engine, &["INCR", "n"]), overflow);
+        assert_eq!(
+            run(&mut engine, &["INCRBY", "n", "-1"]),
+            Object::Integer(i64::MAX - 1)
+        );
+
+        run(&mut engine, &["SET", "n", "-9223372036854775807"]);
+        assert_eq!(run(&mut engine, &["DECR", "n"]), Object::Integer(i64::MIN));
+        assert_eq!(run(&mut engine, &["DECRBY", "n", "1"]), overflow);
+        assert_eq!(
+            run(&mut engine, &["GET", "n"]),
+            Object::BulkString(Some(b"-9223372036854775808".to_vec()))
+        );
+    }
+
+    #[test]
+    fn incrby_rejects_amounts_it_cannot_negate_or_parse() {
+        let mut engine = Engine::new();
+        let not_integer = error("ERR value is not an integer or out of range");
+        run(&mut engine, &["SET", "n", "0"]);
+        assert_eq!(
+            run(&mut engine, &["DECRBY", "n", "-9223372036854775808"]),
+            not_integer
+        );
+        assert_eq!(
+            run(&mut engine, &["INCRBY", "n", "-9223372036854775808"]),
+            Object::Integer(i64::MIN)
+        );
+        assert_eq!(
+            run(&mut engine, &["INCRBY", "n", "9223372036854775808"]),
+            not_integer
+        );
+
+        run(&mut engine, &["SET", "n", "9223372036854775808"]);
+        assert_eq!(run(&mut engine, &["INCR", "n"]), not_integer);
+        run(&mut engine, &["SET", "n", "007"]);
+        assert_eq!(run(&mut engine, &["INCR", "n"]), not_integer);
+    }
+
+    #[test]
+    fn incrbyfloat_rejects_infinite_results() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["SET", "f", "1.7976931348623157e308"]);
+        assert_eq!(
+            run(&mut engine, &["INCRBYFLOAT", "f", "1e308"]),
+            error("ERR increment would produce NaN or Infinity")
+        );
+        assert_eq!(
+            run(&mut engine, &["INCRBYFLOAT", "missing", "inf"]),
+            error("ERR increment would produce NaN or Infinity")
+        );
+        assert_eq!(run(&mut engine, &["EXISTS", "missing"]), Object::Integer(0));
+    }
+
+    #[test]
+    fn floats_format_in_the_shortest_form() {
+        assert_eq!(format_f64(0.0), "0");
+        assert_eq!(format_f64(-0.0), "0");
+        assert_eq!(format_f64(3.0), "3");
+        assert_eq!(format_f64(0.1 + 0.2), "0.30000000000000004");
+        assert_eq!(format_f64(1e21), "1000000000000000000000");
+        assert_eq!(format_f64(f64::INFINITY), "inf");
+        assert_eq!(parse_f64(b"nan"), None);
+        assert_eq!(parse_f64(b"-inf"), Some(f64::NEG_INFINITY));
+        assert_eq!(
+            parse_f64(format_f64(f64::MIN_POSITIVE).as_bytes()),
+            Some(f64::MIN_POSITIVE)
+        );
+    }
+
+    #[test]
+    fn doubles_format_like_redis() {
+        assert_eq!(format_double(0.0), "0");
+        assert_eq!(format_double(-3.0), "-3");
+        assert_eq!(format_double(100000000.0), "100000000");
+        assert_eq!(format_double(4611686018427387904.0), "4611686018427387904");
+        assert_eq!(format_double(1e19), "1e+19");
+        assert_eq!(format_double(1e300), "1e+300");
+        assert_eq!(format_double(-1.5e300), "-1.5e+300");
+        assert_eq!(format_double(1.5), "1.5");
+        assert_eq!(format_double(-123.456), "-123.456");
+        assert_eq!(format_double(0.1 + 0.2), "0.30000000000000004");
+        assert_eq!(format_double(0.000001), "0.000001");
+        assert_eq!(format_double(1.5e-7), "1.5e-7");
+        assert_eq!(format_double(1e-300), "1e-300");
+        assert_eq!(format_double(f64::NEG_INFINITY), "-inf");
+        for n in [1e300, 1.5e-7, 0.1 + 0.2, f64::MIN_POSITIVE, f64::MAX] {
+            assert_eq!(parse_f64(format_double(n).as_bytes()), Some(n));
+        }
+    }
+
+    fn bulk(string: &[u8]) -> Object {
+        Object::BulkString(Some(string.to_vec()))
+    }
+
+    #[test]
+    fn setrange_pads_with_zero_bytes() {
+        let mut engine = Engine::new();
+        assert_eq!(
+            run(&mut engine, &["SETRANGE", "k", "3", "ab"]),
+            Object::Integer(5)
+        );
+        assert_eq!(run(&mut engine, &["GET", "k"]), bulk(b"\0\0\0ab"));
+        assert_eq!(
+            run(&mut engine, &["SETRANGE", "k", "1", "xyz"]),
+            Object::Integer(5)
+        );
+        assert_eq!(run(&mut engine, &["GET", "k"]), bulk(b"\0xyzb"));
+
+        // An empty value neither creates nor grows a string.
+        assert_eq!(
+            run(&mut engine, &["SETRANGE", "missing", "10", ""]),
+            Object::Integer(0)
+        );
+        assert_eq!(run(&mut engine, &["EXISTS", "missing"]), Object::Integer(0));
+        assert_eq!(
+            run(&mut engine, &["SETRANGE", "k", "10", ""]),
+            Object::Integer(5)
+        );
+        assert_eq!(
+            run(&mut engine, &["SETRANGE", "k", "-1", "a"]),
+            error("ERR offset is out of range")
+        );
+        assert_eq!(
+            run(&mut engine, &["SETRANGE", "k", "536870912", "a"]),
+            error("ERR string exceeds maximum allowed size (proto-max-bulk-len)")
+        );
+    }
+
+    #[test]
+    fn getrange_clamps_negative_and_reversed_indexes() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["SET", "k", "Hello World"]);
+        let cases: &[(&str, &str, &[u8])] = &[
+            ("0", "-1", b"Hello World"),
+            ("-5", "-1", b"World"),
+            ("3", "100", b"lo World"),
+            ("-100", "2", b"Hel"),
+            ("0", "-100", b"H"),
+            ("-1", "-5", b""),
+            ("5", "2", b""),
+            ("11", "20", b""),
+        ];
+        for &(start, stop, expected) in cases {
+            assert_eq!(
+                run(&mut engine, &["GETRANGE", "k", start, stop]),
+                bulk(expected),
+                "{start} {stop}"
+            );
+        }
+        assert_eq!(
+            run(&mut engine, &["GETRANGE", "missing", "0", "-1"]),
+            bulk(b"")
+        );
+    }
+
+    #[test]
+    fn getex_sets_and_removes_ttls() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["SET", "k", "v"]);
+        assert_eq!(run(&mut engine, &["GETEX", "k", "EX", "100"]), bulk(b"v"));
+        assert_eq!(run(&mut engine, &["TTL", "k"]), Object::Integer(100));
+        assert_eq!(run(&mut engine, &["GETEX", "k"]), bulk(b"v"));
+        assert_eq!(run(&mut engine, &["TTL", "k"]), Object::Integer(100));
+        assert_eq!(run(&mut engine, &["GETEX", "k", "PERSIST"]), bulk(b"v"));
+        assert_eq!(run(&mut engine, &["TTL", "k"]), Object::Integer(-1));
+
+        assert_eq!(
+            run(&mut engine, &["GETEX", "k", "EX", "10", "PERSIST"]),
+            error("ERR syntax error")
+        );
+        assert_eq!(
+            run(&mut engine, &["GETEX", "k", "EX", "0"]),
+            error("ERR invalid expire time in 'getex' command")
+        );
+        assert_eq!(run(&mut engine, &["TTL", "k"]), Object::Integer(-1));
+        assert_eq!(
+            run(&mut engine, &["GETEX", "missing", "PERSIST"]),
+            Object::BulkString(None)
+        );
+    }
+
+    #[test]
+    fn msetnx_writes_nothing_when_any_key_exists() {
+        let mut engine = Engine::new();
+        run(&mut engine, &["RPUSH", "list", "x"]);
+        assert_eq!(
+            run(&mut engine, &["MSETNX", "a", "1", "list", "2", "b", "3"]),
+            Object::Integer(0)
+        );
+        assert_eq!(
+            run(&mut engine, &["MGET", "a", "list", "b"]),
+            Object::new_array(vec![Object::BulkString(None); 3])
+        );
+        assert_eq!(run(&mut engine, &["EXISTS", "a", "b"]), Object::Integer(0));
+
+        assert_eq!(
+            run(&mut engine, &["MSETNX", "a", "1", "b", "2"]),
+            Object::Integer(1)
+        );
+        assert_eq!(
+            run(&mut engine, &["MSETNX", "c", "3", "b", "4"]),
+            Object::Integer(0)
+        );
+        assert_eq!(
+            run(&mut engine, &["MGET", "a", "b", "c"]),
+            Object::new_array(vec![bulk(b"1"), bulk(b"2"), Object::BulkString(None)])
+        );
+        assert_eq!(
+            run(&mut engine, &["MSETNX", "a", "1", "b"]),
+            error("ERR wrong number of arguments for 'msetnx' command")
+        );
+    }
+}
@@ -0,0 +1,48 @@
+//! The values stored in the engine's key/value data table.
+//!
+//! Values are kept separate from `Object`, which is only what gets sent
+//! back to clients, so that each kind of value has a representation suited
+//! to its commands.
+
+use super::hash::Hash;
+use super::quicklist::QuickList;
+use super::set::Set;
+use super::sorted_set::SortedSet;
+use super::stream::Stream;
+
+/// A value stored at a key. More kinds of values are added as commands for
+/// them are supported.
+#[derive(Clone, Debug)]
+pub enum Value {
+    /// A binary safe string.
+    String(Vec<u8>),
+
+    /// A list of strings.
+    List(QuickList),
+
+    /// A map of fields to strings.
+    Hash(Hash),
+
+    /// An unordered set of strings.
+    Set(Set),
+
+    /// A set of strings ordered by score.
+    SortedSet(SortedSet),
+
+    /// An append-only log of entries.
+    Stream(Stream),
+}
+
+impl Value {
+    /// Returns the name of the value's type as reported by `TYPE`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Hash(_) => "hash",
+            Value::Set(_) => "set",
+            Value::SortedSet(_) => "zset",
+            Value::Stream(_) => "stream",
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Glob-style pattern matching as used by `KEYS` and `SCAN`.
+//!
+//! Patterns support `*` to match any run of bytes, `?` to match a single
+//! byte, `[...]` to match a class of bytes with `^` negation and `a-z`
+//! ranges, and `\` to escape the next byte.
+
+/// Returns whether the whole string matches the pattern.
+///
+/// Only the last `*` seen needs remembering: when a later part of the
+/// pattern fails to match, the star takes one more byte and matching
+/// resumes just after it. An earlier star could only take bytes the last
+/// star can take instead, so this never backtracks further and runs in
+/// time proportional to the product of the lengths at worst.
+pub fn is_match(pattern: &[u8], string: &[u8]) -> bool {
+    let mut p = 0;
+    let mut s = 0;
+    // The pattern offset just past the last star and the string offset
+    // that star has matched up to.
+    let mut star = None;
+    while s < string.len() {
+        let next = match pattern.get(p) {
+            Some(b'*') => {
+                // Consecutive stars are the same as one.
+                while pattern.get(p) == Some(&b'*') {
+                    p += 1;
+                }
+                star = Some((p, s));
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => {
+                let (is_in_class, end) = match_class(pattern, p + 1, string[s]);
+                is_in_class.then_some(end + 1)
+            }
+            Some(&c) => {
+                // An escape matches the next byte literally. A trailing
+                // backslash matches itself.
+                let (c, end) = if c == b'\\' && p + 1 < pattern.len() {
+                    (pattern[p + 1], p + 1)
+                } else {
+                    (c, p)
+                };
+                (string[s] == c).then_some(end + 1)
+            }
+            None => None,
+        };
+        match (next, star) {
+            (Some(next), _) => {
+                p = next;
+                s += 1;
+            }
+            (None, Some((star_p, star_s))) => {
+                p = star_p;
+                s = star_s + 1;
+                star = Some((star_p, s));
+            }
+            (None, None) => return false,
+        }
+    }
+    // Only stars can match what's left of the pattern.
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+/// Matches a byte against the class starting at `start`, just after the
+/// opening `[`. Returns whether the byte is in the class and the offset of
+/// the closing `]`. An unterminated class extends to the end of the pattern.
+fn match_class(pattern: &[u8], start: usize, b: u8) -> (bool, usize) {
+    let mut p = start;
+    let is_negated = pattern.get(p) == Some(&b'^');
+    if is_negated {
+        p += 1;
+    }
+
+    let mut is_in_class = false;
+    while p < pattern.len() && pattern[p] != b']' {
+        if pattern[p] == b'\\' && p + 1 < pattern.len() {
+            p += 1;
+            is_in_class |= pattern[p] == b;
+        } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' && pattern[p + 2] != b']' {
+            let (low, high) = if pattern[p] <= pattern[p + 2] {
+                (pattern[p], pattern[p + 2])
+            } else {
+                (pattern[p + 2], pattern[p])
+            };
+            is_in_class |= (low..=high).contains(&b);
+            p += 2;
+        } else {
+            is_in_class |= pattern[p] == b;
+        }
+        p += 1;
+    }
+
+    // Leave `p` on the closing `]`, or the last byte when unterminated, so
+    // the caller's advance moves past the class.
+    let end = std::cmp::min(p, pattern.len() - 1);
+    (is_in_class != is_negated, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_match;
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(is_match(b"*", b""));
+        assert!(is_match(b"h?llo", b"hello"));
+        assert!(!is_match(b"h?llo", b"hllo"));
+        assert!(is_match(b"h*llo", b"heeeello"));
+        assert!(is_match(b"h**o", b"hello"));
+        assert!(is_match(b"*llo", b"hello"));
+        assert!(!is_match(b"*lo*x", b"hello"));
+        assert!(is_match(b"a*b*c", b"aXbYbZc"));
+        assert!(!is_match(b"a*b*c", b"aXbYbZ"));
+    }
+
+    #[test]
+    fn matches_classes_and_escapes() {
+        assert!(is_match(b"h[ae]llo", b"hallo"));
+        assert!(!is_match(b"h[ae]llo", b"hillo"));
+        assert!(is_match(b"h[^e]llo", b"hallo"));
+        assert!(!is_match(b"h[^e]llo", b"hello"));
+        assert!(is_match(b"h[a-b]llo", b"hbllo"));
+        assert!(is_match(b"h[b-a]llo", b"hallo"));
+        assert!(is_match(b"*[0-9]", b"key7"));
+        assert!(is_match(b"h\\*llo", b"h*llo"));
+        assert!(!is_match(b"h\\*llo", b"hello"));
+        assert!(is_match(b"a\\", b"a\\"));
+        assert!(!is_match(b"a[", b"a"));
+    }
+
+    #[test]
+    fn many_stars_match_quickly() {
+        let string = [b'a'; 10_000];
+        assert!(!is_match(b"*a*a*a*a*a*a*a*a*a*a*b", &string));
+        assert!(is_match(b"*a*a*a*a*a*a*a*a*a*a*", &string));
+    }
+}
@@ -1,6 +1,7 @@
 //! Code Crafters build a Redis challenge
 
 mod engine;
+mod glob;
 mod resp;
 
 use std::collections::HashMap;
@@ -9,6 +10,15 @@ use std::net;
 use std::sync::mpsc;
 use std::sync::mpsc::Receiver;
 use std::thread;
+use std::time;
+
+/// How often a connection waiting on a response checks whether its client
+/// has disconnected, such as while blocked by `BLPOP`.
+const DISCONNECT_POLL: time::Duration = time::Duration::from_millis(100);
+
+/// How often the engine thread runs its periodic tasks, such as actively
+/// expiring keys.
+const ENGINE_TICK: time::Duration = time::Duration::from_millis(100);
 
 fn main() {
     let mut connection_id = 0;
@@ -42,11 +52,7 @@ fn main() {
 }
 
 /// Handles all the interactions for a connection.
-fn handle_connection<T: io::Read + io::Write>(
-    id: ConnectionId,
-    stream: T,
-    tx_req: mpsc::Sender<Request>,
-) {
+fn handle_connection(id: ConnectionId, stream: net::TcpStream, tx_req: mpsc::Sender<Request>) {
     // Tell engine where to send responses.
     let (tx_res, rx_res) = mpsc::channel();
     let req = Request::new_sender(id, tx_res);
@@ -73,15 +79,14 @@ fn handle_connection<T: io::Read + io::Write>(
     // TODO: Check the response.
     if let Err(e) = rx_res.recv() {
         eprintln!("{e}");
-        return;
     }
 }
 
 /// Goes into a loop of reading commands from the client, requesting the
 /// engine do the command, and then sending the result back to the client.
-fn read_request_respond_loop<T: io::Read + io::Write>(
+fn read_request_respond_loop(
     id: ConnectionId,
-    mut stream: T,
+    mut stream: net::TcpStream,
     tx_req: &mpsc::Sender<Request>,
     rx_res: &mpsc::Receiver<Response>,
 ) -> anyhow::Result<()> {
@@ -90,44 +95,129 @@ fn read_request_respond_loop<T: io::Read + io::Write>(
         let command = resp::deserialize_object(&mut read_state, &mut stream)?;
         let req = Request::new_command(id, command);
         tx_req.send(req)?;
-        match rx_res.recv()? {
-            Response::Ok => write!(stream, "+OK\r\n")?,
-            Response::Return(res) => resp::serialize(&mut stream, &res)?,
+        // Serialize into a buffer first so the response goes out in one
+        // write rather than many small packets.
+        let mut buffer = Vec::new();
+        let res = loop {
+            match rx_res.recv_timeout(DISCONNECT_POLL) {
+                Ok(res) => break res,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    // The response is deferred, so stop waiting if the
+                    // client has gone away.
+                    if is_disconnected(&stream) {
+                        return Ok(());
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        match res {
+            Response::Ok => buffer.extend_from_slice(b"+OK\r\n"),
+            Response::Return(res, protocol) => resp::serialize(&mut buffer, &res, protocol)?,
         }
+        io::Write::write_all(&mut stream, &buffer)?;
     }
     Ok(())
 }
 
+/// Checks whether the client has closed its end of the connection without
+/// consuming any bytes it has sent.
+fn is_disconnected(stream: &net::TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let mut buffer = [0; 1];
+    let is_disconnected = match stream.peek(&mut buffer) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(e) => e.kind() != io::ErrorKind::WouldBlock,
+    };
+    let _ = stream.set_nonblocking(false);
+    is_disconnected
+}
+
 /// `fn` run in the engine thread.
 fn run_engine(rx_req: Receiver<Request>) {
     // Create the engine object.
     let mut engine = engine::Engine::new();
     // A map of senders to send responses to.
     let mut senders = HashMap::new();
+    // When the periodic tasks last ran.
+    let mut last_tick = time::Instant::now();
     // Start request processing loop.
-    for req in rx_req {
-        // Handle the request.
-        let res = match req.value {
-            RequestValue::Command(object) => {
-                let res = engine.do_command(object);
-                Response::Return(res)
-            }
-            RequestValue::Sender(tx_res) => {
-                senders.insert(req.id, tx_res);
-                Response::Ok
-            }
-            RequestValue::Done => {
-                let _ = senders.remove(&req.id);
-                Response::Ok
-            }
+    loop {
+        // Wait for a request, but not past when the next tick is due or a
+        // blocked client times out.
+        let mut timeout = ENGINE_TICK.saturating_sub(last_tick.elapsed());
+        if let Some(deadline) = engine.next_block_deadline() {
+            let until_deadline = deadline.saturating_duration_since(time::Instant::now());
+            timeout = timeout.min(until_deadline);
+        }
+        let req = match rx_req.recv_timeout(timeout) {
+            Ok(req) => Some(req),
+            Err(mpsc::RecvTimeoutError::Timeout) => None,
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
         };
-        // Try to respond to the request.
-        if let Some(tx_res) = senders.get(&req.id) {
-            if let Err(e) = tx_res.send(res) {
-                // TODO: Do more in response to the error?
-                eprintln!("error responding to request: {e}");
+
+        if let Some(req) = req {
+            handle_request(&mut engine, &mut senders, req);
+        }
+
+        // Reply to blocked clients that were served or timed out.
+        engine.time_out_blocked_clients();
+        for (id, res) in engine.take_replies() {
+            let protocol = engine.protocol(id);
+            respond(&senders, id, Response::Return(res, protocol));
+        }
+
+        // Run periodic tasks.
+        if last_tick.elapsed() >= ENGINE_TICK {
+            engine.active_expire_cycle();
+            last_tick = time::Instant::now();
+        }
+    }
+}
+
+/// Handles a single request in the engine thread.
+fn handle_request(
+    engine: &mut engine::Engine,
+    senders: &mut HashMap<ConnectionId, mpsc::Sender<Response>>,
+    req: Request,
+) {
+    let res = match req.value {
+        RequestValue::Command(object) => match engine.do_command(req.id, object) {
+            Some(res) => Response::Return(res, engine.protocol(req.id)),
+            // The client is blocked and gets its reply later.
+            None => return,
+        },
+        RequestValue::Sender(tx_res) => {
+            senders.insert(req.id, tx_res);
+            Response::Ok
+        }
+        RequestValue::Done => {
+            engine.remove_client(req.id);
+            // Respond before dropping the sender so the connection isn't
+            // left waiting.
+            if let Some(tx_res) = senders.remove(&req.id) {
+                let _ = tx_res.send(Response::Ok);
             }
+            return;
         }
+    };
+    respond(senders, req.id, res);
+}
+
+/// Tries to send a response to a connection.
+fn respond(
+    senders: &HashMap<ConnectionId, mpsc::Sender<Response>>,
+    id: ConnectionId,
+    res: Response,
+) {
+    if let Some(tx_res) = senders.get(&id)
+        && let Err(e) = tx_res.send(res)
+    {
+        // TODO: Do more in response to the error?
+        eprintln!("error responding to request: {e}");
     }
 }
 
@@ -176,9 +266,74 @@ enum RequestValue {
 enum Response {
     /// A generic OK.
     Ok,
-    /// An object to return back to a connection's client.
-    Return(engine::Object),
+    /// An object to return back to a connection's client, along with the
+    /// protocol to send it in.
+    Return(engine::Object, resp::Protocol),
 }
 
 /// An ID assigned to a connection.
 type ConnectionId = usize;
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net;
+    use std::sync::mpsc;
+
+    use super::{Request, Response, engine, handle_request, is_disconnected};
+
+    /// Creates a command request.
+    fn command(id: usize, args: &[&str]) -> Request {
+        let args = args
+            .iter()
+            .map(|arg| engine::Object::BulkString(Some(arg.as_bytes().to_vec())))
+            .collect();
+        Request::new_command(id, engine::Object::new_array(args))
+    }
+
+    #[test]
+    fn done_connections_stop_waiting() {
+        let mut engine = engine::Engine::new();
+        let mut senders = HashMap::new();
+        let mut receivers = Vec::new();
+        for id in 1..=3 {
+            let (tx_res, rx_res) = mpsc::channel();
+            handle_request(&mut engine, &mut senders, Request::new_sender(id, tx_res));
+            assert!(matches!(rx_res.try_recv(), Ok(Response::Ok)));
+            receivers.push(rx_res);
+        }
+
+        handle_request(&mut engine, &mut senders, command(1, &["BLPOP", "l", "0"]));
+        handle_request(&mut engine, &mut senders, command(2, &["BLPOP", "l", "0"]));
+        assert!(receivers[0].try_recv().is_err());
+
+        handle_request(&mut engine, &mut senders, Request::new_done(1));
+        assert!(matches!(receivers[0].try_recv(), Ok(Response::Ok)));
+        assert!(!senders.contains_key(&1));
+
+        handle_request(&mut engine, &mut senders, command(3, &["RPUSH", "l", "a"]));
+        assert!(matches!(receivers[2].try_recv(), Ok(Response::Return(..))));
+        let replies = engine.take_replies();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].0, 2);
+    }
+
+    #[test]
+    fn detects_closed_connections() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        assert!(!is_disconnected(&server));
+
+        drop(client);
+        let mut is_closed = false;
+        for _ in 0..100 {
+            if is_disconnected(&server) {
+                is_closed = true;
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(is_closed);
+    }
+}
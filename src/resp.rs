@@ -6,41 +6,126 @@ use anyhow::anyhow;
 
 use crate::engine::{self, ObjectArray};
 
-/// Serializes an object and writes it to a given stream.
-pub fn serialize<T: io::Write>(stream: &mut T, object: &engine::Object) -> io::Result<()> {
+/// A version of the protocol, which a client picks with `HELLO`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Protocol {
+    /// The version clients speak until they ask for another.
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+/// Serializes an object and writes it to a given stream. RESP2 clients get
+/// types only RESP3 has as the nearest RESP2 type: maps as arrays of keys
+/// and values, sets and pushes as arrays, doubles, big numbers and verbatim
+/// strings as bulk strings, booleans as 1 or 0, and nulls as null arrays.
+pub fn serialize<T: io::Write>(
+    stream: &mut T,
+    object: &engine::Object,
+    protocol: Protocol,
+) -> io::Result<()> {
+    let is_resp3 = protocol == Protocol::Resp3;
     match object {
         engine::Object::Array(elements) => {
-            write!(stream, "*{}\r\n", elements.items.len())?;
-            for e in elements.items.iter() {
-                serialize(stream, e)?;
-            }
-            Ok(())
+            serialize_aggregate(stream, b'*', &elements.items, protocol)
         }
-        engine::Object::Integer(i) => {
-            write!(stream, ":{i}\r\n")
-        }
-        engine::Object::BulkString(Some(string)) => {
-            write!(stream, "${}\r\n", string.len())?;
-            stream.write(&string)?;
+        engine::Object::BigNumber(digits) if is_resp3 => {
+            write!(stream, "(")?;
+            stream.write_all(digits)?;
             write!(stream, "\r\n")
         }
+        engine::Object::BigNumber(digits) => serialize_bulk_string(stream, digits),
+        engine::Object::Boolean(b) if is_resp3 => {
+            write!(stream, "#{}\r\n", if *b { 't' } else { 'f' })
+        }
+        engine::Object::Boolean(b) => {
+            write!(stream, ":{}\r\n", i64::from(*b))
+        }
+        engine::Object::BulkString(Some(string)) => serialize_bulk_string(stream, string),
+        engine::Object::BulkString(None) | engine::Object::Null if is_resp3 => {
+            write!(stream, "_\r\n")
+        }
         engine::Object::BulkString(None) => {
             write!(stream, "$-1\r\n")
         }
+        engine::Object::Double(n) if is_resp3 => {
+            write!(stream, ",{}\r\n", engine::format_double(*n))
+        }
+        engine::Object::Double(n) => {
+            serialize_bulk_string(stream, engine::format_double(*n).as_bytes())
+        }
         engine::Object::Error(message) => {
             write!(stream, "-")?;
-            stream.write(&message)?;
+            stream.write_all(message)?;
             write!(stream, "\r\n")
         }
+        engine::Object::Integer(i) => {
+            write!(stream, ":{i}\r\n")
+        }
+        engine::Object::Map(pairs) => {
+            if is_resp3 {
+                write!(stream, "%{}\r\n", pairs.len())?;
+            } else {
+                write!(stream, "*{}\r\n", pairs.len() * 2)?;
+            }
+            for (key, value) in pairs.iter() {
+                serialize(stream, key, protocol)?;
+                serialize(stream, value, protocol)?;
+            }
+            Ok(())
+        }
+        engine::Object::Null => {
+            write!(stream, "*-1\r\n")
+        }
+        engine::Object::Push(items) => {
+            let marker = if is_resp3 { b'>' } else { b'*' };
+            serialize_aggregate(stream, marker, items, protocol)
+        }
+        engine::Object::Set(items) => {
+            let marker = if is_resp3 { b'~' } else { b'*' };
+            serialize_aggregate(stream, marker, items, protocol)
+        }
         engine::Object::SimpleString(string) => {
             write!(stream, "+")?;
-            stream.write(&string)?;
+            stream.write_all(string)?;
             write!(stream, "\r\n")
         }
+        engine::Object::VerbatimString(format, text) if is_resp3 => {
+            // The length counts the format and the colon after it.
+            write!(stream, "={}\r\n", format.len() + 1 + text.len())?;
+            stream.write_all(format)?;
+            write!(stream, ":")?;
+            stream.write_all(text)?;
+            write!(stream, "\r\n")
+        }
+        engine::Object::VerbatimString(_, text) => serialize_bulk_string(stream, text),
+    }
+}
+
+/// Serializes an aggregate of objects that starts with the given type byte
+/// and a count of items.
+fn serialize_aggregate<T: io::Write>(
+    stream: &mut T,
+    marker: u8,
+    items: &[engine::Object],
+    protocol: Protocol,
+) -> io::Result<()> {
+    write!(stream, "{}{}\r\n", marker as char, items.len())?;
+    for item in items.iter() {
+        serialize(stream, item, protocol)?;
     }
+    Ok(())
+}
+
+/// Serializes a non-null bulk string.
+fn serialize_bulk_string<T: io::Write>(stream: &mut T, string: &[u8]) -> io::Result<()> {
+    write!(stream, "${}\r\n", string.len())?;
+    stream.write_all(string)?;
+    write!(stream, "\r\n")
 }
 
-/// Deserializes an object read from a given stream.
+/// Deserializes an object read from a given stream. Every RESP2 type is
+/// accepted, so this reads what `serialize` writes for RESP2 clients.
 pub fn deserialize_object<T: io::Read>(
     state: &mut ReadState,
     stream: &mut T,
@@ -49,6 +134,8 @@ pub fn deserialize_object<T: io::Read>(
         Some(b'$') => deserialize_bulk_string(state, stream),
         Some(b'*') => deserialize_array(state, stream),
         Some(b':') => deserialize_integer(state, stream),
+        Some(b'+') => deserialize_simple_string(state, stream),
+        Some(b'-') => deserialize_error(state, stream),
         Some(b) => Err(anyhow!("byte is not a data type: {:x}", b)),
         None => Err(anyhow!("unexpected end of state")),
     }
@@ -61,33 +148,51 @@ fn deserialize_integer<T: io::Read>(
 ) -> anyhow::Result<engine::Object> {
     assert_eq!(input.current(stream)?, Some(b':'));
     input.advance();
-    let sign = match input.current(stream)? {
-        Some(c) if c == b'+' || c == b'-' => {
-            input.advance();
-            Some(c)
-        }
-        _ => None,
-    };
-    let value = read_digits(input, stream)?;
+    // The sign is parsed along with the digits, since the most negative
+    // integer can't be negated from a positive one.
+    let mut value = String::new();
+    if let Some(c) = input.current(stream)?.filter(|c| *c == b'+' || *c == b'-') {
+        input.advance();
+        value.push(c as char);
+    }
+    value.push_str(&read_digits(input, stream)?);
     expect_delimiter(input, stream)?;
     let value = parse_i64(&value)?;
-    let value = match sign {
-        Some(b'-') => -value,
-        _ => value,
-    };
     Ok(engine::Object::Integer(value))
 }
 
-/// Deserializes an array object.
+/// Deserializes a simple string object.
+fn deserialize_simple_string<T: io::Read>(
+    input: &mut ReadState,
+    stream: &mut T,
+) -> anyhow::Result<engine::Object> {
+    assert_eq!(input.current(stream)?, Some(b'+'));
+    input.advance();
+    let string = read_line(input, stream)?;
+    Ok(engine::Object::SimpleString(string))
+}
+
+/// Deserializes an error object.
+fn deserialize_error<T: io::Read>(
+    input: &mut ReadState,
+    stream: &mut T,
+) -> anyhow::Result<engine::Object> {
+    assert_eq!(input.current(stream)?, Some(b'-'));
+    input.advance();
+    let message = read_line(input, stream)?;
+    Ok(engine::Object::Error(message))
+}
+
+/// Deserializes an array object. A length of -1 is a null array.
 fn deserialize_array<T: io::Read>(
     input: &mut ReadState,
     stream: &mut T,
 ) -> anyhow::Result<engine::Object> {
     assert_eq!(input.current(stream)?, Some(b'*'));
     input.advance();
-    let length = read_digits(input, stream)?;
-    let length = parse_u32(&length)?;
-    expect_delimiter(input, stream)?;
+    let Some(length) = read_length(input, stream)? else {
+        return Ok(engine::Object::Null);
+    };
     let mut items = Vec::new();
     for _ in 0..length {
         let element = deserialize_object(input, stream)?;
@@ -97,17 +202,16 @@ fn deserialize_array<T: io::Read>(
     Ok(engine::Object::Array(array))
 }
 
-/// Deserializes a bulk string object.
+/// Deserializes a bulk string object. A length of -1 is a null bulk string.
 fn deserialize_bulk_string<T: io::Read>(
     input: &mut ReadState,
     stream: &mut T,
 ) -> anyhow::Result<engine::Object> {
     assert_eq!(input.current(stream)?, Some(b'$'));
     input.advance();
-    // TODO: Support null bulk strings that look like `$-1\r\n`.
-    let length = read_digits(input, stream)?;
-    let length = parse_u32(&length)?;
-    expect_delimiter(input, stream)?;
+    let Some(length) = read_length(input, stream)? else {
+        return Ok(engine::Object::BulkString(None));
+    };
     let mut string = Vec::new();
     for _ in 0..length {
         let Some(b) = input.current(stream)? else {
@@ -120,6 +224,40 @@ fn deserialize_bulk_string<T: io::Read>(
     Ok(engine::Object::BulkString(Some(string)))
 }
 
+/// Reads the length of a bulk string or array and the delimiter after it.
+/// Returns `None` for a length of -1, which marks a null. Other negative
+/// lengths are an error.
+fn read_length<T: io::Read>(input: &mut ReadState, stream: &mut T) -> anyhow::Result<Option<u32>> {
+    let is_negative = input.current(stream)? == Some(b'-');
+    if is_negative {
+        input.advance();
+    }
+    let length = read_digits(input, stream)?;
+    expect_delimiter(input, stream)?;
+    if is_negative {
+        return match length.as_str() {
+            "1" => Ok(None),
+            _ => Err(anyhow!("invalid negative length `-{length}`")),
+        };
+    }
+    parse_u32(&length).map(Some)
+}
+
+/// Reads the bytes up to the next `\r\n`, then advances past it. Lone `\r`
+/// and `\n` bytes aren't allowed in the line.
+fn read_line<T: io::Read>(input: &mut ReadState, stream: &mut T) -> anyhow::Result<Vec<u8>> {
+    let mut line = Vec::new();
+    while let Some(b) = input
+        .current(stream)?
+        .filter(|b| *b != b'\r' && *b != b'\n')
+    {
+        line.push(b);
+        input.advance();
+    }
+    expect_delimiter(input, stream)?;
+    Ok(line)
+}
+
 /// Read from stream ASCII digits, putting them into a `String`.
 fn read_digits<T: io::Read>(input: &mut ReadState, stream: &mut T) -> anyhow::Result<String> {
     let mut result = String::new();
@@ -170,7 +308,7 @@ fn parse_i64(s: &str) -> anyhow::Result<i64> {
 
 /// Returns whether a byte is an ASCII digit.
 fn is_digit(b: u8) -> bool {
-    b'0' <= b && b <= b'9'
+    b.is_ascii_digit()
 }
 
 /// Attempts to print the byte as an ASCII character.
@@ -255,3 +393,66 @@ impl ReadState {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Protocol, ReadState, deserialize_object, serialize};
+    use crate::engine::Object;
+
+    /// Serializes an object for a RESP2 client, checks the bytes written,
+    /// and checks that reading them back gives the object again.
+    fn assert_round_trip(object: Object, expected: &[u8]) {
+        let mut bytes = Vec::new();
+        serialize(&mut bytes, &object, Protocol::Resp2).unwrap();
+        assert_eq!(bytes, expected);
+        let mut stream = bytes.as_slice();
+        let read = deserialize_object(&mut ReadState::new(), &mut stream).unwrap();
+        assert_eq!(read, object);
+    }
+
+    /// Reads an object from the given bytes.
+    fn deserialize(mut bytes: &[u8]) -> anyhow::Result<Object> {
+        deserialize_object(&mut ReadState::new(), &mut bytes)
+    }
+
+    #[test]
+    fn strings_round_trip() {
+        assert_round_trip(Object::BulkString(Some(b"hi".to_vec())), b"$2\r\nhi\r\n");
+        assert_round_trip(Object::BulkString(Some(Vec::new())), b"$0\r\n\r\n");
+        assert_round_trip(Object::BulkString(None), b"$-1\r\n");
+        assert_round_trip(Object::new_simple_string(b"OK"), b"+OK\r\n");
+        assert_round_trip(Object::new_error(b"ERR no"), b"-ERR no\r\n");
+    }
+
+    #[test]
+    fn integers_round_trip() {
+        assert_round_trip(Object::Integer(0), b":0\r\n");
+        assert_round_trip(Object::Integer(-1), b":-1\r\n");
+        assert_round_trip(Object::Integer(i64::MAX), b":9223372036854775807\r\n");
+        assert_round_trip(Object::Integer(i64::MIN), b":-9223372036854775808\r\n");
+        assert_eq!(deserialize(b":+7\r\n").unwrap(), Object::Integer(7));
+        assert!(deserialize(b":9223372036854775808\r\n").is_err());
+        assert!(deserialize(b":-9223372036854775809\r\n").is_err());
+        assert!(deserialize(b":-\r\n").is_err());
+    }
+
+    #[test]
+    fn arrays_round_trip() {
+        assert_round_trip(Object::Null, b"*-1\r\n");
+        assert_round_trip(Object::new_empty_array(), b"*0\r\n");
+        assert_round_trip(
+            Object::new_array(vec![
+                Object::Integer(1),
+                Object::new_array(vec![Object::BulkString(None), Object::Null]),
+                Object::new_simple_string(b"OK"),
+            ]),
+            b"*3\r\n:1\r\n*2\r\n$-1\r\n*-1\r\n+OK\r\n",
+        );
+    }
+
+    #[test]
+    fn other_negative_lengths_are_errors() {
+        assert!(deserialize(b"$-2\r\n").is_err());
+        assert!(deserialize(b"*-2\r\n").is_err());
+    }
+}